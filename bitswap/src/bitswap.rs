@@ -12,17 +12,23 @@ use libp2p_rs::swarm::Control as SwarmControl;
 
 use crate::block::Block;
 use crate::control::Control;
+use crate::engine::Engine;
 use crate::error::BitswapError;
-use crate::ledger::{Ledger, Message, Priority};
-use crate::protocol::{send_message, Handler, ProtocolEvent};
+use crate::ledger::{Ledger, LedgerInfo, Message, Priority, ProtocolVersion, WantEntry, WantType};
+use crate::protocol::{send_messages, Handler, ProtocolEvent};
+use crate::session::SessionId;
 use crate::stat::Stats;
-use crate::BsBlockStore;
+use crate::wants::{WantAction, WantManager};
+use crate::{BsBlockStore, MAX_MESSAGE_SIZE};
 use libp2p_rs::core::routing::Routing;
 use libp2p_rs::swarm::protocol_handler::{IProtocolHandler, ProtocolImpl};
 const WANT_DEADLINE: Duration = Duration::from_secs(30);
+/// How long the peers of a session are given to provide a block before searching the providers
+/// and asking all connected peers.
+const SESSION_FALLBACK_DELAY: Duration = Duration::from_secs(1);
 
 pub(crate) enum ControlCommand {
-    WantBlock(Cid, oneshot::Sender<Result<Block>>),
+    WantBlock(Cid, Option<SessionId>, oneshot::Sender<Result<Block>>),
     HasBlock(Cid, oneshot::Sender<Result<()>>),
     CancelBlock(Cid, oneshot::Sender<Result<()>>),
     WantList(
@@ -31,6 +37,13 @@ pub(crate) enum ControlCommand {
     ),
     Peers(oneshot::Sender<Result<Vec<PeerId>>>),
     Stats(oneshot::Sender<Result<Stats>>),
+    Ledger(PeerId, oneshot::Sender<Result<LedgerInfo>>),
+    CloseSession(SessionId),
+    /// Sent by the main loop to itself when the peers of a session have not provided the block in
+    /// time.
+    SessionFallback(Cid),
+    /// Sent by the main loop to itself when a request for the block has timed out.
+    WantTimeout(Cid),
 }
 
 pub struct Bitswap<TBlockStore, TRouting> {
@@ -57,11 +70,20 @@ pub struct Bitswap<TBlockStore, TRouting> {
 
     want_deadline: Duration,
 
+    /// The upper limit for the size of the messages sent to peers.
+    max_message_size: usize,
+
     /// Wanted blocks
     ///
     /// The oneshot::Sender is used to send the block back to the API users.
     wanted_blocks: HashMap<Cid, Vec<oneshot::Sender<Block>>>,
 
+    /// Decides whom the wanted blocks are asked from.
+    wants: WantManager,
+
+    /// Decides the order in which the blocks wanted by peers are sent.
+    engine: Engine,
+
     /// Ledger
     connected_peers: HashMap<PeerId, Ledger>,
 
@@ -91,12 +113,22 @@ where
             control_tx,
             control_rx,
             want_deadline: WANT_DEADLINE,
+            max_message_size: MAX_MESSAGE_SIZE,
             wanted_blocks: Default::default(),
+            wants: Default::default(),
+            engine: Default::default(),
             connected_peers: Default::default(),
             stats: Default::default(),
         }
     }
 
+    /// Sets the upper limit for the size of the messages sent to and received from peers; larger
+    /// messages are split when sending and refused when receiving. The default is 512 KiB.
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
     /// Get control of floodsub, which can be used to publish or subscribe.
     pub fn control(&self) -> Control {
         Control::new(self.control_tx.clone())
@@ -121,47 +153,103 @@ where
         }
     }
 
-    fn send_message_to(&mut self, peer_id: PeerId, message: Message) {
+    fn send_messages_to(&mut self, peer_id: PeerId, messages: Vec<Message>) {
+        if messages.is_empty() {
+            return;
+        }
+
+        let num_blocks = messages
+            .iter()
+            .map(|message| message.num_of_blocks())
+            .sum::<usize>() as u64;
+        let bytes = messages
+            .iter()
+            .map(|message| message.bytes_of_blocks())
+            .sum::<usize>();
+
         if let Some(peer_stats) = self.stats.get_mut(&peer_id) {
-            peer_stats.update_outgoing(
-                message.num_of_blocks() as u64,
-                message.bytes_of_blocks() as u64,
-            );
+            peer_stats.update_outgoing(num_blocks, bytes as u64);
         }
 
-        // spwan a task to send the message
+        if let Some(ledger) = self.connected_peers.get_mut(&peer_id) {
+            ledger.update_sent(num_blocks, bytes as u64);
+        }
+
+        // spwan a task to send the messages, in order
         let swarm = self.swarm.clone().expect("swarm??");
+        let mut poster = self.peer_tx.clone();
         task::spawn(async move {
-            let _ = send_message(swarm, peer_id, message).await;
+            // the error is not Send, so it cannot be held over the awaits below
+            let version = send_messages(swarm, peer_id, messages).await.ok();
+            if let Some(version @ ProtocolVersion::V1_1_0) = version {
+                let _ = poster
+                    .send(ProtocolEvent::Negotiated {
+                        peer: peer_id,
+                        version,
+                    })
+                    .await;
+            }
+            if bytes > 0 {
+                // let the engine send more blocks to the peer
+                let _ = poster
+                    .send(ProtocolEvent::Sent {
+                        peer: peer_id,
+                        bytes,
+                    })
+                    .await;
+            }
         });
     }
 
-    fn broadcast_messages(&mut self) {
-        for (peer_id, ledger) in &mut self.connected_peers {
-            if let Some(message) = ledger.send() {
-                if let Some(peer_stats) = self.stats.get_mut(peer_id) {
-                    peer_stats.update_outgoing(
-                        message.num_of_blocks() as u64,
-                        message.bytes_of_blocks() as u64,
-                    );
-                }
+    /// Sends the blocks chosen by the decision engine.
+    fn schedule_blocks(&mut self) {
+        let connected_peers = &self.connected_peers;
+        let batches = self.engine.next_batch(|peer| {
+            connected_peers
+                .get(peer)
+                .map(Ledger::debt_ratio)
+                .unwrap_or_default()
+        });
 
-                // spwan a task to send the message
-                let swarm = self.swarm.clone().expect("swarm??");
-                let peer_id = *peer_id;
-                task::spawn(async move {
-                    let _ = send_message(swarm, peer_id, message).await;
-                });
-            }
+        for (peer, blocks) in batches {
+            let ledger = match self.connected_peers.get_mut(&peer) {
+                Some(ledger) => ledger,
+                None => continue,
+            };
+
+            log::debug!("sending {} block(s) to {:?}", blocks.len(), peer);
+            blocks.into_iter().for_each(|block| ledger.add_block(block));
+
+            let messages = ledger.send();
+            self.send_messages_to(peer, messages);
+        }
+    }
+
+    fn broadcast_messages(&mut self) {
+        let outgoing = self
+            .connected_peers
+            .iter_mut()
+            .map(|(peer_id, ledger)| (*peer_id, ledger.send()))
+            .collect::<Vec<_>>();
+
+        for (peer_id, messages) in outgoing {
+            self.send_messages_to(peer_id, messages);
         }
     }
 
     fn handle_event(&mut self, evt: Option<ProtocolEvent>) {
         match evt {
-            Some(ProtocolEvent::Blocks(peer, blocks)) => {
+            Some(ProtocolEvent::Responses {
+                peer,
+                blocks,
+                haves,
+                dont_haves,
+            }) => {
                 log::debug!(
-                    "blockstore reports {} block(s) for {:?}",
+                    "blockstore reports {} block(s), {} have(s) and {} dont-have(s) for {:?}",
                     blocks.len(),
+                    haves.len(),
+                    dont_haves.len(),
                     peer
                 );
                 let ledger = if let Some(l) = self.connected_peers.get_mut(&peer) {
@@ -171,23 +259,54 @@ where
                     return;
                 };
 
-                //self.s
-                blocks.into_iter().for_each(|block| ledger.add_block(block));
+                // the blocks are sent by the decision engine, unless they were cancelled already
+                for block in blocks {
+                    if let Some(priority) = ledger.wanted_priority(&block.cid) {
+                        self.engine.push(peer, priority, block);
+                    }
+                }
+
+                // the presences are small and sent right away
+                haves.iter().for_each(|cid| ledger.have_block(cid));
+                dont_haves
+                    .iter()
+                    .for_each(|cid| ledger.dont_have_block(cid));
+
+                let messages = ledger.send();
+                self.send_messages_to(peer, messages);
 
-                if let Some(message) = ledger.send() {
-                    self.send_message_to(peer, message);
+                self.schedule_blocks();
+            }
+            Some(ProtocolEvent::Sent { peer, bytes }) => {
+                self.engine.sent(&peer, bytes);
+                self.schedule_blocks();
+            }
+            Some(ProtocolEvent::Negotiated { peer, version }) => {
+                if let Some(ledger) = self.connected_peers.get_mut(&peer) {
+                    if ledger.version() != version {
+                        log::debug!("{:?} speaks bitswap {:?}", peer, version);
+                        ledger.set_version(version);
+                    }
                 }
             }
             Some(ProtocolEvent::NewPeer(p)) => {
                 log::debug!("{:?} connected", p);
                 // make a ledge for the peer and send wantlist to it
-                self.connected_peers.entry(p).or_default();
+                let max_message_size = self.max_message_size;
+                self.connected_peers
+                    .entry(p)
+                    .or_insert_with(|| Ledger::with_max_message_size(max_message_size));
                 self.stats.entry(p).or_default();
                 self.send_want_list(p);
             }
             Some(ProtocolEvent::DeadPeer(p)) => {
                 log::debug!("{:?} disconnected", p);
                 self.connected_peers.remove(&p);
+                self.engine.remove_peer(&p);
+
+                // ask the blocks requested from the peer from the next peer which has them
+                let actions = self.wants.peer_disconnected(&p);
+                self.apply(actions);
             }
             None => {}
         }
@@ -195,11 +314,13 @@ where
 
     async fn handle_incoming_message(&mut self, source: PeerId, mut message: Message) {
         log::debug!(
-            "incoming message: from {:?}, w={} c={} b={}",
+            "incoming message: from {:?}, w={} c={} b={} h={} dh={}",
             source,
             message.want().len(),
             message.cancel().len(),
-            message.blocks().len()
+            message.blocks().len(),
+            message.have().len(),
+            message.dont_have().len()
         );
 
         let current_wantlist = self.local_wantlist();
@@ -211,30 +332,23 @@ where
             return;
         };
 
+        ledger.update_wants_received(message.want().len() as u64);
+
         // Process the incoming cancel list.
         for cid in message.cancel() {
             ledger.received_want_list.remove(cid);
+            self.engine.cancel(&source, cid);
         }
 
-        // block presences had added into bitswap proto when 2020.1
-        // But it still doesn't work now in go-ipfs v0.7
-        // for have in message.have() {
-        //     log::info!("peer {} have block {}", source, have);
-        // }
-        //
-        // for dont in message.dont_have() {
-        //     log::info!("peer {} don't have block {}", source, dont);
-        // }
-
         // Process the incoming wantlist.
         let mut to_check = vec![];
-        for (cid, priority) in message
+        for (cid, entry) in message
             .want()
             .iter()
             .filter(|&(cid, _)| !current_wantlist.contains(&cid))
         {
-            ledger.received_want_list.insert(cid.to_owned(), *priority);
-            to_check.push(cid.to_owned());
+            ledger.received_want_list.insert(cid.to_owned(), *entry);
+            to_check.push((cid.to_owned(), *entry));
         }
 
         if !to_check.is_empty() {
@@ -248,18 +362,44 @@ where
             let mut poster = self.peer_tx.clone();
             task::spawn(async move {
                 let mut blocks = vec![];
-                for cid in to_check {
-                    if let Ok(Some(block)) = blockstore.get(&cid).await {
-                        log::debug!("block {} found in blockstore", cid);
-                        blocks.push(block);
+                let mut haves = vec![];
+                let mut dont_haves = vec![];
+                for (cid, entry) in to_check {
+                    match entry.want_type {
+                        WantType::Block => match blockstore.get(&cid).await {
+                            Ok(Some(block)) => {
+                                log::debug!("block {} found in blockstore", cid);
+                                blocks.push(block);
+                            }
+                            _ if entry.send_dont_have => dont_haves.push(cid),
+                            _ => {}
+                        },
+                        WantType::Have => match blockstore.contains(&cid).await {
+                            Ok(true) => haves.push(cid),
+                            _ if entry.send_dont_have => dont_haves.push(cid),
+                            _ => {}
+                        },
                     }
                 }
-                if !blocks.is_empty() {
-                    let _ = poster.send(ProtocolEvent::Blocks(source, blocks)).await;
+                if !blocks.is_empty() || !haves.is_empty() || !dont_haves.is_empty() {
+                    let event = ProtocolEvent::Responses {
+                        peer: source,
+                        blocks,
+                        haves,
+                        dont_haves,
+                    };
+                    let _ = poster.send(event).await;
                 }
             });
         }
 
+        // Process the incoming block presences.
+        let haves = message.have().iter().cloned().collect::<Vec<_>>();
+        let dont_haves = message.dont_have().iter().cloned().collect::<Vec<_>>();
+        if !haves.is_empty() || !dont_haves.is_empty() {
+            self.handle_block_presences(source, haves, dont_haves);
+        }
+
         // Process the incoming blocks.
         // TODO: send block to any peer who want
         let blocks = message.take_blocks();
@@ -268,10 +408,66 @@ where
         }
     }
 
+    fn handle_block_presences(&mut self, source: PeerId, haves: Vec<Cid>, dont_haves: Vec<Cid>) {
+        log::debug!(
+            "{:?} has {} and doesn't have {} block(s)",
+            source,
+            haves.len(),
+            dont_haves.len()
+        );
+
+        let actions = self.wants.presences(source, &haves, &dont_haves);
+        self.apply(actions);
+    }
+
+    /// Carries out the decisions of the [`WantManager`].
+    fn apply(&mut self, actions: impl IntoIterator<Item = WantAction>) {
+        for action in actions {
+            match action {
+                WantAction::AskSession(cid, priority, peers) => {
+                    self.ask_session(cid, priority, peers)
+                }
+                WantAction::Request(cid, peer) => self.request_block_from(&cid, peer),
+                WantAction::Broadcast(cid, priority) => self.broadcast_want(cid, priority),
+            }
+        }
+    }
+
+    /// Asks the block from a single peer with a want-block entry, once the peer has told it has
+    /// the block.
+    fn request_block_from(&mut self, cid: &Cid, peer: PeerId) {
+        let ledger = match self.connected_peers.get_mut(&peer) {
+            Some(ledger) => ledger,
+            None => return,
+        };
+
+        log::debug!("asking block {} from {:?}", cid, peer);
+
+        ledger.want(
+            cid,
+            WantEntry {
+                priority: 1,
+                want_type: WantType::Block,
+                send_dont_have: true,
+            },
+        );
+
+        let messages = ledger.send();
+        self.send_messages_to(peer, messages);
+    }
+
     fn handle_received_blocks(&mut self, source: PeerId, blocks: Vec<Block>) {
         log::debug!("received {} block(s) from {:?}", blocks.len(), source);
 
+        if let Some(ledger) = self.connected_peers.get_mut(&source) {
+            for block in &blocks {
+                ledger.update_received(block.data().len() as u64);
+            }
+        }
+
         for block in &blocks {
+            self.wants.received(source, &block.cid);
+
             // publish block to all pending API users
             let _ = self.wanted_blocks.remove(&block.cid).map(|txs| {
                 txs.into_iter().for_each(|tx| {
@@ -312,8 +508,8 @@ where
 
     fn handle_control_command(&mut self, cmd: Option<ControlCommand>) -> Result<()> {
         match cmd {
-            Some(ControlCommand::WantBlock(cid, reply)) => {
-                self.want_block(cid, 1, reply);
+            Some(ControlCommand::WantBlock(cid, session, reply)) => {
+                self.want_block(cid, 1, session, reply);
             }
             Some(ControlCommand::HasBlock(cid, reply)) => {
                 self.has_block(cid, reply);
@@ -338,6 +534,21 @@ where
             Some(ControlCommand::Stats(reply)) => {
                 let _ = reply.send(Ok(self.stats()));
             }
+            Some(ControlCommand::Ledger(peer, reply)) => {
+                let _ = reply.send(Ok(self.ledger(&peer)));
+            }
+            Some(ControlCommand::CloseSession(session)) => {
+                log::debug!("bitswap session {} closed", session);
+                self.wants.close_session(session);
+            }
+            Some(ControlCommand::SessionFallback(cid)) => {
+                let fallback = self.wants.session_fallback(&cid);
+                if fallback.is_some() {
+                    log::debug!("session peers did not provide {} in time", cid);
+                }
+                self.apply(fallback);
+            }
+            Some(ControlCommand::WantTimeout(cid)) => self.want_timed_out(&cid),
             None => {
                 // control channel closed, exit the main loop
                 return Err(BitswapError::Closing);
@@ -346,17 +557,80 @@ where
         Ok(())
     }
 
-    /// Retrieves the wanted block.
+    /// Retrieves the wanted block, optionally within a session.
+    ///
+    /// Within a session, the block is first asked only from the peers which have responded to
+    /// the earlier wants of the session.
     ///
     /// A user request
     pub fn want_block(
         &mut self,
         cid: Cid,
         priority: Priority,
+        session: Option<SessionId>,
         reply: oneshot::Sender<Result<Block>>,
     ) {
-        log::debug!("bitswap want block {} ", cid);
+        log::debug!("bitswap want block {} in session {:?}", cid, session);
+
+        let (tx, rx) = oneshot::channel();
+        self.wanted_blocks
+            .entry(cid.clone())
+            .or_insert_with(Vec::new)
+            .push(tx);
+
+        let deadline = self.want_deadline;
+        let mut control_tx = self.control_tx.clone();
+        let timed_out = cid.clone();
+        task::spawn(async move {
+            let r = task::timeout(deadline, rx).await;
+            if let Ok(block) = r {
+                let _ = reply.send(block.map_err(BitswapError::Cancel));
+            } else {
+                let _ = reply.send(Err(BitswapError::Timeout));
+                // the receiver has been dropped, letting the main loop know this request is gone
+                let _ = control_tx
+                    .send(ControlCommand::WantTimeout(timed_out))
+                    .await;
+            }
+        });
 
+        let connected_peers = &self.connected_peers;
+        let action = self.wants.want(&cid, priority, session, |peer| {
+            connected_peers.contains_key(peer)
+        });
+        self.apply(action);
+    }
+
+    /// Asks the block from the peers of the session with want-have entries, falling back to
+    /// asking everyone unless they answer in time.
+    fn ask_session(&mut self, cid: Cid, priority: Priority, peers: Vec<PeerId>) {
+        log::debug!("asking {} from {} peer(s) of a session", cid, peers.len());
+
+        let entry = WantEntry {
+            priority,
+            want_type: WantType::Have,
+            send_dont_have: true,
+        };
+        for peer in &peers {
+            if let Some(ledger) = self.connected_peers.get_mut(peer) {
+                if !ledger.is_block_wanted(&cid) {
+                    ledger.want(&cid, entry);
+                }
+            }
+        }
+
+        self.broadcast_messages();
+
+        let mut control_tx = self.control_tx.clone();
+        task::spawn(async move {
+            let _ = task::timeout(SESSION_FALLBACK_DELAY, futures::future::pending::<()>()).await;
+            let _ = control_tx.send(ControlCommand::SessionFallback(cid)).await;
+        });
+    }
+
+    /// Searches for the providers of the block, and asks all connected peers whether they have
+    /// the block.
+    fn broadcast_want(&mut self, cid: Cid, priority: Priority) {
         // TODO: should run a dedicated peer manager for find_providers...
         let mut routing = self.routing.clone();
         let mut swarm = self.swarm.clone().expect("Swarm??");
@@ -374,27 +648,20 @@ where
             }
         });
 
+        // ask everyone if they have the block, except the peer it is already being asked from
+        let entry = WantEntry {
+            priority,
+            want_type: WantType::Have,
+            send_dont_have: true,
+        };
         for (_peer_id, ledger) in self.connected_peers.iter_mut() {
-            ledger.want_block(&cid, priority);
+            if !ledger.is_block_wanted(&cid) {
+                ledger.want(&cid, entry);
+            }
         }
-        let (tx, rx) = oneshot::channel();
-        self.wanted_blocks
-            .entry(cid)
-            .or_insert_with(Vec::new)
-            .push(tx);
 
         // ask all known peers for the wanted block
         self.broadcast_messages();
-
-        let deadline = self.want_deadline;
-        task::spawn(async move {
-            let r = task::timeout(deadline, rx).await;
-            if let Ok(block) = r {
-                let _ = reply.send(block.map_err(BitswapError::Cancel));
-            } else {
-                let _ = reply.send(Err(BitswapError::Timeout));
-            }
-        });
     }
 
     /// Announces a new block.
@@ -408,6 +675,7 @@ where
             ledger.cancel_block(&cid);
         }
         self.wanted_blocks.remove(&cid);
+        self.wants.remove(&cid);
 
         // announce via routing
         let mut routing = self.routing.clone();
@@ -429,9 +697,31 @@ where
             ledger.cancel_block(cid);
         }
         self.wanted_blocks.remove(cid);
+        self.wants.remove(cid);
         let _ = reply.send(Ok(()));
     }
 
+    /// Forgets the block once all of the requests for it have timed out, so that wanting it again
+    /// starts over by asking the peers and searching the providers.
+    fn want_timed_out(&mut self, cid: &Cid) {
+        let expired = match self.wanted_blocks.get_mut(cid) {
+            Some(txs) => {
+                txs.retain(|tx| !tx.is_canceled());
+                txs.is_empty()
+            }
+            None => false,
+        };
+
+        if expired {
+            log::debug!("bitswap want for {} timed out", cid);
+            for (_peer_id, ledger) in self.connected_peers.iter_mut() {
+                ledger.cancel_block(cid);
+            }
+            self.wanted_blocks.remove(cid);
+            self.wants.remove(cid);
+        }
+    }
+
     /// Returns the wantlist of a peer, if known
     pub fn peer_wantlist(&self, peer: &PeerId) -> Option<Vec<(Cid, Priority)>> {
         self.connected_peers.get(peer).map(Ledger::wantlist)
@@ -450,6 +740,15 @@ where
         self.connected_peers.keys().cloned().collect()
     }
 
+    /// Returns the accounting of the blocks exchanged with the peer, which is empty for peers
+    /// which are not connected.
+    pub fn ledger(&self, peer: &PeerId) -> LedgerInfo {
+        self.connected_peers
+            .get(peer)
+            .map(Ledger::info)
+            .unwrap_or_default()
+    }
+
     /// Returns the statistics of bitswap.
     pub fn stats(&self) -> Stats {
         self.stats
@@ -462,22 +761,27 @@ where
 
     /// Sends the wantlist to the peer.
     fn send_want_list(&mut self, peer_id: PeerId) {
-        if !self.wanted_blocks.is_empty() {
-            // FIXME: this can produce too long a message
-            // FIXME: we should shard these across all of our peers by some logic; also, peers may
-            // have been discovered to provide some specific wantlist item
-            let mut message = Message::default();
-            for cid in self.wanted_blocks.keys() {
-                // TODO: set priority
-                message.want_block(cid, 1);
-            }
+        let ledger = match self.connected_peers.get_mut(&peer_id) {
+            Some(ledger) => ledger,
+            None => return,
+        };
 
-            // spwan a task to send the message
-            let swarm = self.swarm.clone().expect("swarm??");
-            task::spawn(async move {
-                let _ = send_message(swarm, peer_id, message).await;
-            });
+        // FIXME: we should shard these across all of our peers by some logic; also, peers may
+        // have been discovered to provide some specific wantlist item
+        for cid in self.wanted_blocks.keys() {
+            // TODO: set priority
+            ledger.want(
+                cid,
+                WantEntry {
+                    priority: 1,
+                    want_type: WantType::Have,
+                    send_dont_have: true,
+                },
+            );
         }
+
+        let messages = ledger.send();
+        self.send_messages_to(peer_id, messages);
     }
 }
 
@@ -488,7 +792,11 @@ where
 {
     /// Get handler of floodsub, swarm will call "handle" func after muxer negotiate success.
     fn handler(&self) -> IProtocolHandler {
-        Box::new(Handler::new(self.incoming_tx.clone(), self.peer_tx.clone()))
+        Box::new(Handler::new(
+            self.incoming_tx.clone(),
+            self.peer_tx.clone(),
+            self.max_message_size,
+        ))
     }
 
     /// Start message process loop.
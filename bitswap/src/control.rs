@@ -7,7 +7,8 @@ use libp2p_rs::core::PeerId;
 use crate::bitswap::ControlCommand;
 use crate::block::Block;
 use crate::error::BitswapError;
-use crate::{Priority, Stats};
+use crate::session::Session;
+use crate::{LedgerInfo, Priority, Stats};
 
 #[derive(Clone)]
 pub struct Control(mpsc::UnboundedSender<ControlCommand>);
@@ -33,10 +34,19 @@ impl Control {
         _priority: Priority,
     ) -> Result<Block, BitswapError> {
         let (tx, rx) = oneshot::channel();
-        self.0.send(ControlCommand::WantBlock(cid, tx)).await?;
+        self.0
+            .send(ControlCommand::WantBlock(cid, None, tx))
+            .await?;
         rx.await?
     }
 
+    /// Creates a new session for fetching the blocks of a single DAG.
+    ///
+    /// See [`Session`] for more information.
+    pub fn new_session(&self) -> Session {
+        Session::new(self.0.clone())
+    }
+
     /// Announces a new block.
     ///
     /// A user request
@@ -76,6 +86,15 @@ impl Control {
         rx.await?
     }
 
+    /// Returns the accounting of the blocks exchanged with the peer.
+    ///
+    /// A user request
+    pub async fn ledger(&mut self, peer: PeerId) -> Result<LedgerInfo, BitswapError> {
+        let (tx, rx) = oneshot::channel();
+        self.0.send(ControlCommand::Ledger(peer, tx)).await?;
+        rx.await?
+    }
+
     /// Returns the bitswap statistics per peer basis.
     ///
     /// A user request
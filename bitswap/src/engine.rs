@@ -0,0 +1,275 @@
+use cid::Cid;
+use std::collections::HashMap;
+
+use libp2p_rs::core::PeerId;
+
+use crate::block::Block;
+use crate::ledger::Priority;
+
+/// The default upper limit for the block bytes being sent to a single peer at a time.
+pub(crate) const MAX_OUTSTANDING_BYTES_PER_PEER: usize = 1024 * 1024;
+
+/// The default upper limit for the block bytes being sent to all peers at a time.
+pub(crate) const MAX_OUTSTANDING_BYTES: usize = 8 * 1024 * 1024;
+
+/// A block waiting to be sent to a peer.
+#[derive(Debug)]
+struct Task {
+    priority: Priority,
+    /// Arrival order, used to serve the tasks of the same priority in order.
+    seq: u64,
+    block: Block,
+}
+
+/// The blocks waiting to be sent to a peer.
+#[derive(Debug, Default)]
+struct PeerQueue {
+    tasks: Vec<Task>,
+    /// Bytes of blocks sent but not yet reported as sent.
+    outstanding: usize,
+}
+
+/// The decision engine decides which of the blocks wanted by peers are sent next.
+///
+/// Peers are served in the order of their debt ratio, so that the peers which have sent us the
+/// most compared to what we have sent them are served first. The blocks of a single peer are
+/// served in the order of the want priority. The amount of bytes being sent is capped both per
+/// peer and in total.
+#[derive(Debug)]
+pub(crate) struct Engine {
+    queues: HashMap<PeerId, PeerQueue>,
+    max_outstanding_per_peer: usize,
+    max_outstanding: usize,
+    outstanding: usize,
+    seq: u64,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine::new(MAX_OUTSTANDING_BYTES_PER_PEER, MAX_OUTSTANDING_BYTES)
+    }
+}
+
+impl Engine {
+    pub(crate) fn new(max_outstanding_per_peer: usize, max_outstanding: usize) -> Self {
+        Engine {
+            queues: Default::default(),
+            max_outstanding_per_peer,
+            max_outstanding,
+            outstanding: 0,
+            seq: 0,
+        }
+    }
+
+    /// Queues the block to be sent to the peer.
+    pub(crate) fn push(&mut self, peer: PeerId, priority: Priority, block: Block) {
+        let queue = self.queues.entry(peer).or_default();
+
+        if queue.tasks.iter().any(|task| task.block.cid == block.cid) {
+            return;
+        }
+
+        self.seq += 1;
+        queue.tasks.push(Task {
+            priority,
+            seq: self.seq,
+            block,
+        });
+    }
+
+    /// Removes the block from the queue of the peer, when the peer no longer wants it.
+    pub(crate) fn cancel(&mut self, peer: &PeerId, cid: &Cid) {
+        if let Some(queue) = self.queues.get_mut(peer) {
+            queue.tasks.retain(|task| &task.block.cid != cid);
+        }
+    }
+
+    /// Forgets the peer and its queued blocks.
+    pub(crate) fn remove_peer(&mut self, peer: &PeerId) {
+        if let Some(queue) = self.queues.remove(peer) {
+            self.outstanding -= queue.outstanding;
+        }
+    }
+
+    /// Reports that sending the given amount of block bytes to the peer has completed, either
+    /// successfully or not.
+    pub(crate) fn sent(&mut self, peer: &PeerId, bytes: usize) {
+        if let Some(queue) = self.queues.get_mut(peer) {
+            let bytes = bytes.min(queue.outstanding);
+            queue.outstanding -= bytes;
+            self.outstanding -= bytes;
+        }
+    }
+
+    /// Returns the number of blocks queued for the peer.
+    #[cfg(test)]
+    pub(crate) fn queued(&self, peer: &PeerId) -> usize {
+        self.queues
+            .get(peer)
+            .map(|queue| queue.tasks.len())
+            .unwrap_or(0)
+    }
+
+    /// Takes the blocks to be sent next, grouped by peer. The peers are ordered by the given debt
+    /// ratio, lowest first.
+    ///
+    /// A block larger than the limits is only sent when nothing else is being sent to the peer,
+    /// or in total.
+    pub(crate) fn next_batch<F>(&mut self, debt_ratio: F) -> Vec<(PeerId, Vec<Block>)>
+    where
+        F: Fn(&PeerId) -> f64,
+    {
+        let mut peers = self
+            .queues
+            .iter()
+            .filter(|(_, queue)| !queue.tasks.is_empty())
+            .map(|(peer, _)| (*peer, debt_ratio(peer)))
+            .collect::<Vec<_>>();
+
+        peers.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+
+        let mut batches = Vec::new();
+
+        for (peer, _) in peers {
+            let queue = self.queues.get_mut(&peer).expect("peer was just found");
+
+            // highest priority last, so that they can be popped
+            queue
+                .tasks
+                .sort_by(|a, b| a.priority.cmp(&b.priority).then_with(|| b.seq.cmp(&a.seq)));
+
+            let mut blocks = Vec::new();
+
+            while let Some(task) = queue.tasks.last() {
+                let len = task.block.data().len();
+
+                let fits_peer = queue.outstanding == 0
+                    || queue.outstanding + len <= self.max_outstanding_per_peer;
+                let fits_total =
+                    self.outstanding == 0 || self.outstanding + len <= self.max_outstanding;
+
+                if !fits_peer || !fits_total {
+                    break;
+                }
+
+                let task = queue.tasks.pop().expect("task was just found");
+                queue.outstanding += len;
+                self.outstanding += len;
+                blocks.push(task.block);
+            }
+
+            if !blocks.is_empty() {
+                batches.push((peer, blocks));
+            }
+        }
+
+        batches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use multihash::Sha2_256;
+
+    fn block_of(len: usize, seed: u8) -> Block {
+        let mut data = vec![seed; len];
+        data[0] = seed.wrapping_add(1);
+        let cid = Cid::new_v1(cid::Codec::Raw, Sha2_256::digest(&data));
+        Block::new(data.into_boxed_slice(), cid)
+    }
+
+    #[test]
+    fn blocks_are_served_by_priority() {
+        let mut engine = Engine::new(100, 1000);
+        let peer = PeerId::random();
+
+        let low = block_of(10, 0);
+        let high = block_of(10, 1);
+        let first_of_normal = block_of(10, 2);
+        let second_of_normal = block_of(10, 3);
+
+        engine.push(peer, 1, first_of_normal.clone());
+        engine.push(peer, 0, low.clone());
+        engine.push(peer, 10, high.clone());
+        engine.push(peer, 1, second_of_normal.clone());
+
+        let batches = engine.next_batch(|_| 0.0);
+
+        assert_eq!(
+            batches,
+            vec![(peer, vec![high, first_of_normal, second_of_normal, low])]
+        );
+    }
+
+    #[test]
+    fn outstanding_bytes_are_capped_per_peer() {
+        let mut engine = Engine::new(100, 1000);
+        let peer = PeerId::random();
+
+        for seed in 0..5 {
+            engine.push(peer, 1, block_of(40, seed));
+        }
+
+        let batches = engine.next_batch(|_| 0.0);
+        assert_eq!(batches[0].1.len(), 2);
+        assert_eq!(engine.queued(&peer), 3);
+
+        // nothing more until the earlier blocks have been sent
+        assert!(engine.next_batch(|_| 0.0).is_empty());
+
+        engine.sent(&peer, 80);
+
+        let batches = engine.next_batch(|_| 0.0);
+        assert_eq!(batches[0].1.len(), 2);
+        assert_eq!(engine.queued(&peer), 1);
+    }
+
+    #[test]
+    fn oversized_block_is_sent_to_idle_peer() {
+        let mut engine = Engine::new(100, 1000);
+        let peer = PeerId::random();
+
+        engine.push(peer, 1, block_of(500, 0));
+        engine.push(peer, 1, block_of(10, 1));
+
+        let batches = engine.next_batch(|_| 0.0);
+        assert_eq!(batches[0].1.len(), 1);
+        assert_eq!(batches[0].1[0].data().len(), 500);
+    }
+
+    #[test]
+    fn peers_with_lower_debt_are_served_first() {
+        let mut engine = Engine::new(100, 100);
+        let debtor = PeerId::random();
+        let creditor = PeerId::random();
+
+        engine.push(debtor, 1, block_of(60, 0));
+        engine.push(creditor, 1, block_of(60, 1));
+
+        let debt_ratio = |peer: &PeerId| if *peer == debtor { 10.0 } else { 0.1 };
+
+        // only one of the blocks fits in the total limit
+        let batches = engine.next_batch(debt_ratio);
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].0, creditor);
+
+        engine.sent(&creditor, 60);
+
+        let batches = engine.next_batch(debt_ratio);
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].0, debtor);
+    }
+
+    #[test]
+    fn cancelled_blocks_are_not_sent() {
+        let mut engine = Engine::new(100, 1000);
+        let peer = PeerId::random();
+        let block = block_of(10, 0);
+
+        engine.push(peer, 1, block.clone());
+        engine.cancel(&peer, &block.cid);
+
+        assert!(engine.next_batch(|_| 0.0).is_empty());
+    }
+}
@@ -2,6 +2,7 @@ use crate::bitswap_pb;
 use crate::block::Block;
 use crate::error::BitswapError;
 use crate::prefix::Prefix;
+use crate::MAX_MESSAGE_SIZE;
 use cid::Cid;
 use prost::Message as ProstMessage;
 use std::collections::{HashMap, HashSet};
@@ -10,15 +11,100 @@ use std::mem;
 
 pub type Priority = i32;
 
+/// Upper bounds for the protobuf encoding overhead of the message parts in addition to the `Cid`
+/// or the block data, used when splitting messages.
+const ENTRY_OVERHEAD: usize = 24;
+const PRESENCE_OVERHEAD: usize = 16;
+const BLOCK_OVERHEAD: usize = 48;
+
+/// The kind of a wantlist entry, added in bitswap 1.2.0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WantType {
+    /// The peer wants the block itself.
+    Block,
+    /// The peer only wants to know whether the block is available.
+    Have,
+}
+
+impl Default for WantType {
+    fn default() -> Self {
+        WantType::Block
+    }
+}
+
+/// A single entry of the wantlist.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WantEntry {
+    pub priority: Priority,
+    pub want_type: WantType,
+    /// Whether the peer wants to be told with a `DontHave` presence when the block is not
+    /// available.
+    pub send_dont_have: bool,
+}
+
+impl WantEntry {
+    /// An entry for the block itself, as sent by bitswap 1.0.0 and 1.1.0.
+    pub fn block(priority: Priority) -> Self {
+        WantEntry {
+            priority,
+            want_type: WantType::Block,
+            send_dont_have: false,
+        }
+    }
+}
+
+/// The version of the bitswap protocol spoken with a peer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ProtocolVersion {
+    /// `/ipfs/bitswap/1.1.0`, without want-have entries and block presences.
+    V1_1_0,
+    /// `/ipfs/bitswap/1.2.0`.
+    V1_2_0,
+}
+
 /// The Ledger contains the history of transactions with a peer.
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct Ledger {
     /// The list of wanted blocks sent to the peer.
-    sent_want_list: HashMap<Cid, Priority>,
+    sent_want_list: HashMap<Cid, WantEntry>,
     /// The list of wanted blocks received from the peer.
-    pub(crate) received_want_list: HashMap<Cid, Priority>,
+    pub(crate) received_want_list: HashMap<Cid, WantEntry>,
     /// Queued message.
     message: Message,
+    /// The upper limit for the size of the sent messages.
+    max_message_size: usize,
+    /// Bytes of blocks sent to the peer.
+    bytes_sent: u64,
+    /// Bytes of blocks received from the peer.
+    bytes_received: u64,
+    /// Number of blocks sent to and received from the peer.
+    exchanged: u64,
+    /// Number of want entries received from the peer, not counting cancels.
+    wants_received: u64,
+    /// The protocol version of the peer; 1.2.0 until a stream negotiated with the peer says
+    /// otherwise.
+    version: ProtocolVersion,
+}
+
+/// The accounting of the blocks exchanged with a peer.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LedgerInfo {
+    /// The debt ratio of the peer: bytes sent to the peer per bytes received from the peer.
+    pub value: f64,
+    /// Bytes of blocks sent to the peer.
+    pub sent: u64,
+    /// Bytes of blocks received from the peer.
+    pub received: u64,
+    /// Number of blocks sent to and received from the peer.
+    pub exchanged: u64,
+    /// Number of want entries received from the peer, not counting cancels.
+    pub wants_received: u64,
+}
+
+impl Default for Ledger {
+    fn default() -> Self {
+        Self::with_max_message_size(MAX_MESSAGE_SIZE)
+    }
 }
 
 impl Ledger {
@@ -27,6 +113,79 @@ impl Ledger {
         Self::default()
     }
 
+    /// Creates a new `PeerLedger` which splits the queued message into messages of at most
+    /// `max_message_size` bytes.
+    pub fn with_max_message_size(max_message_size: usize) -> Self {
+        Ledger {
+            sent_want_list: Default::default(),
+            received_want_list: Default::default(),
+            message: Default::default(),
+            max_message_size,
+            bytes_sent: 0,
+            bytes_received: 0,
+            exchanged: 0,
+            wants_received: 0,
+            version: ProtocolVersion::V1_2_0,
+        }
+    }
+
+    /// Returns the protocol version of the peer.
+    pub(crate) fn version(&self) -> ProtocolVersion {
+        self.version
+    }
+
+    /// Records the protocol version negotiated with the peer. The want-have entries for a 1.1.0
+    /// peer are turned into want-block entries, as such peers would take them for ones.
+    pub(crate) fn set_version(&mut self, version: ProtocolVersion) {
+        self.version = version;
+
+        if version == ProtocolVersion::V1_1_0 {
+            for entry in self.sent_want_list.values_mut() {
+                *entry = WantEntry::block(entry.priority);
+            }
+            self.message.downgrade();
+        }
+    }
+
+    /// Accounts the blocks sent to the peer.
+    pub fn update_sent(&mut self, num_blocks: u64, bytes: u64) {
+        self.exchanged += num_blocks;
+        self.bytes_sent += bytes;
+    }
+
+    /// Accounts a block received from the peer.
+    pub fn update_received(&mut self, bytes: u64) {
+        self.exchanged += 1;
+        self.bytes_received += bytes;
+    }
+
+    /// Accounts the want entries received from the peer.
+    pub fn update_wants_received(&mut self, num_wants: u64) {
+        self.wants_received += num_wants;
+    }
+
+    /// Returns the debt ratio of the peer, like go-bitswap: the bytes sent to the peer per the
+    /// bytes received from the peer. The lower the ratio, the more the peer has given to us.
+    pub fn debt_ratio(&self) -> f64 {
+        self.bytes_sent as f64 / (self.bytes_received as f64 + 1.0)
+    }
+
+    /// Returns the accounting of the exchanged blocks.
+    pub fn info(&self) -> LedgerInfo {
+        LedgerInfo {
+            value: self.debt_ratio(),
+            sent: self.bytes_sent,
+            received: self.bytes_received,
+            exchanged: self.exchanged,
+            wants_received: self.wants_received,
+        }
+    }
+
+    /// Returns the priority of the block in the wantlist of the peer, if the peer wants it.
+    pub fn wanted_priority(&self, cid: &Cid) -> Option<Priority> {
+        self.received_want_list.get(cid).map(|entry| entry.priority)
+    }
+
     pub fn add_block(&mut self, block: Block) {
         self.message.add_block(block);
     }
@@ -35,31 +194,60 @@ impl Ledger {
         self.message.want_block(cid, priority);
     }
 
+    /// Queues the entry, as a want-block entry for the peers which do not support want-have.
+    pub fn want(&mut self, cid: &Cid, entry: WantEntry) {
+        let entry = match self.version {
+            ProtocolVersion::V1_1_0 => WantEntry::block(entry.priority),
+            ProtocolVersion::V1_2_0 => entry,
+        };
+        self.message.add_want(cid, entry);
+    }
+
+    /// Queues a cancel for the block and forgets that it was wanted, so that wanting the block
+    /// again queues a new want.
     pub fn cancel_block(&mut self, cid: &Cid) {
+        self.sent_want_list.remove(cid);
         self.message.cancel_block(cid);
     }
 
+    pub fn have_block(&mut self, cid: &Cid) {
+        self.message.have_block(cid);
+    }
+
+    pub fn dont_have_block(&mut self, cid: &Cid) {
+        self.message.dont_have_block(cid);
+    }
+
+    /// Returns whether the block was already asked from the peer with a want-block entry.
+    pub fn is_block_wanted(&self, cid: &Cid) -> bool {
+        self.sent_want_list
+            .get(cid)
+            .map(|entry| entry.want_type == WantType::Block)
+            .unwrap_or(false)
+    }
+
     /// Returns the blocks wanted by the peer in unspecified order
     pub fn wantlist(&self) -> Vec<(Cid, Priority)> {
         self.received_want_list
             .iter()
-            .map(|(cid, prio)| (cid.clone(), *prio))
+            .map(|(cid, entry)| (cid.clone(), entry.priority))
             .collect()
     }
 
-    pub fn send(&mut self) -> Option<Message> {
+    /// Takes the queued message as messages no larger than the maximum message size, to be sent
+    /// in order. Returns an empty list if nothing was queued.
+    pub fn send(&mut self) -> Vec<Message> {
         if self.message.is_empty() {
-            return None;
+            return Vec::new();
         }
-        // FIXME: this might produce too large message
         for cid in self.message.cancel() {
             self.sent_want_list.remove(cid);
         }
-        for (cid, priority) in self.message.want() {
-            self.sent_want_list.insert(cid.clone(), *priority);
+        for (cid, entry) in self.message.want() {
+            self.sent_want_list.insert(cid.clone(), *entry);
         }
 
-        Some(mem::take(&mut self.message))
+        mem::take(&mut self.message).split(self.max_message_size)
     }
 }
 
@@ -67,7 +255,7 @@ impl Ledger {
 #[derive(Clone, PartialEq, Default)]
 pub struct Message {
     /// List of wanted blocks.
-    want: HashMap<Cid, Priority>,
+    want: HashMap<Cid, WantEntry>,
     /// List of blocks to cancel.
     cancel: HashSet<Cid>,
     /// List of blocks which peer has
@@ -83,7 +271,11 @@ pub struct Message {
 impl Message {
     /// Checks whether the queued message is empty.
     pub fn is_empty(&self) -> bool {
-        self.want.is_empty() && self.cancel.is_empty() && self.blocks.is_empty()
+        self.want.is_empty()
+            && self.cancel.is_empty()
+            && self.blocks.is_empty()
+            && self.haves.is_empty()
+            && self.dont_haves.is_empty()
     }
 
     /// Returns the list of blocks.
@@ -109,7 +301,7 @@ impl Message {
     }
 
     /// Returns the list of wanted blocks.
-    pub fn want(&self) -> &HashMap<Cid, Priority> {
+    pub fn want(&self) -> &HashMap<Cid, WantEntry> {
         &self.want
     }
 
@@ -150,14 +342,81 @@ impl Message {
 
     /// Adds a block to the want list.
     pub fn want_block(&mut self, cid: &Cid, priority: Priority) {
-        self.want.insert(cid.to_owned(), priority);
+        self.add_want(cid, WantEntry::block(priority));
     }
 
-    /// Adds a block to the cancel list.
+    /// Adds an entry to the want list, replacing any previous entry or cancel for the block.
+    pub fn add_want(&mut self, cid: &Cid, entry: WantEntry) {
+        self.cancel.remove(cid);
+        self.want.insert(cid.to_owned(), entry);
+    }
+
+    /// Adds a block to the cancel list, replacing any previous entry for the block.
     pub fn cancel_block(&mut self, cid: &Cid) {
+        self.want.remove(cid);
         self.cancel.insert(cid.to_owned());
     }
 
+    /// Splits the message into messages which encode to at most `max_size` bytes. The wants,
+    /// cancels and block presences are placed first, followed by the blocks. A single block which
+    /// is larger than `max_size` is placed in a message of its own.
+    pub fn split(self, max_size: usize) -> Vec<Message> {
+        let mut splitter = Splitter {
+            max_size,
+            size: 0,
+            current: Message {
+                full: self.full,
+                ..Default::default()
+            },
+            messages: Vec::new(),
+        };
+
+        for (cid, entry) in self.want {
+            splitter
+                .reserve(cid.to_bytes().len() + ENTRY_OVERHEAD)
+                .add_want(&cid, entry);
+        }
+        for cid in self.cancel {
+            splitter
+                .reserve(cid.to_bytes().len() + ENTRY_OVERHEAD)
+                .cancel_block(&cid);
+        }
+        for cid in self.haves {
+            splitter
+                .reserve(cid.to_bytes().len() + PRESENCE_OVERHEAD)
+                .have_block(&cid);
+        }
+        for cid in self.dont_haves {
+            splitter
+                .reserve(cid.to_bytes().len() + PRESENCE_OVERHEAD)
+                .dont_have_block(&cid);
+        }
+        for block in self.blocks {
+            let len = block.data().len() + BLOCK_OVERHEAD;
+            if len > max_size {
+                log::warn!(
+                    "block {} of {} bytes exceeds the maximum message size {}",
+                    block.cid(),
+                    block.data().len(),
+                    max_size
+                );
+            }
+            splitter.reserve(len).add_block(block);
+        }
+
+        splitter.finish()
+    }
+
+    /// Turns the message into one for a bitswap 1.1.0 peer: the want-have entries become
+    /// want-block entries and the block presences are left out.
+    pub(crate) fn downgrade(&mut self) {
+        for entry in self.want.values_mut() {
+            *entry = WantEntry::block(entry.priority);
+        }
+        self.haves.clear();
+        self.dont_haves.clear();
+    }
+
     /// Removes the block from the want list.
     #[allow(unused)]
     pub fn remove_want_block(&mut self, cid: &Cid) {
@@ -165,14 +424,49 @@ impl Message {
     }
 }
 
+/// Packs the parts of a message into messages of bounded size.
+struct Splitter {
+    max_size: usize,
+    /// Estimated encoded size of `current`.
+    size: usize,
+    current: Message,
+    messages: Vec<Message>,
+}
+
+impl Splitter {
+    /// Returns the message to which the part of `len` bytes should be added, starting a new
+    /// message if the current one would grow too large.
+    fn reserve(&mut self, len: usize) -> &mut Message {
+        if self.size + len > self.max_size && !self.current.is_empty() {
+            self.messages.push(mem::take(&mut self.current));
+            self.size = 0;
+        }
+        self.size += len;
+        &mut self.current
+    }
+
+    fn finish(mut self) -> Vec<Message> {
+        if !self.current.is_empty() {
+            self.messages.push(self.current);
+        }
+        self.messages
+    }
+}
+
 impl From<&Message> for Vec<u8> {
     fn from(msg: &Message) -> Vec<u8> {
         let mut proto = bitswap_pb::Message::default();
         let mut wantlist = bitswap_pb::message::Wantlist::default();
-        for (cid, priority) in msg.want() {
+        for (cid, entry) in msg.want() {
+            let want_type = match entry.want_type {
+                WantType::Block => bitswap_pb::message::wantlist::WantType::Block,
+                WantType::Have => bitswap_pb::message::wantlist::WantType::Have,
+            };
             let entry = bitswap_pb::message::wantlist::Entry {
                 block: cid.to_bytes(),
-                priority: *priority,
+                priority: entry.priority,
+                want_type: want_type as i32,
+                send_dont_have: entry.send_dont_have,
                 ..Default::default()
             };
             wantlist.entries.push(entry);
@@ -192,6 +486,20 @@ impl From<&Message> for Vec<u8> {
             };
             proto.payload.push(payload);
         }
+        for cid in msg.have() {
+            let presence = bitswap_pb::message::BlockPresence {
+                cid: cid.to_bytes(),
+                r#type: bitswap_pb::message::BlockPresenceType::Have as i32,
+            };
+            proto.block_presences.push(presence);
+        }
+        for cid in msg.dont_have() {
+            let presence = bitswap_pb::message::BlockPresence {
+                cid: cid.to_bytes(),
+                r#type: bitswap_pb::message::BlockPresenceType::DontHave as i32,
+            };
+            proto.block_presences.push(presence);
+        }
         if !wantlist.entries.is_empty() {
             proto.wantlist = Some(wantlist);
         }
@@ -231,11 +539,23 @@ impl TryFrom<&[u8]> for Message {
             if entry.cancel {
                 message.cancel_block(&cid);
             } else {
-                message.want_block(&cid, entry.priority);
+                let want_type = bitswap_pb::message::wantlist::WantType::from_i32(entry.want_type)
+                    .ok_or(BitswapError::InvalidData)?;
+                let want_type = match want_type {
+                    bitswap_pb::message::wantlist::WantType::Block => WantType::Block,
+                    bitswap_pb::message::wantlist::WantType::Have => WantType::Have,
+                };
+                message.add_want(
+                    &cid,
+                    WantEntry {
+                        priority: entry.priority,
+                        want_type,
+                        send_dont_have: entry.send_dont_have,
+                    },
+                );
             }
         }
-        // block presences had added into bitswap proto when 2020.1
-        // But it still doesn't work now in go-ipfs v0.7
+        // block presences are only sent by bitswap 1.2.0 peers
         for bp in proto.block_presences {
             let cid = Cid::try_from(bp.cid)?;
             let msg_type = bitswap_pb::message::BlockPresenceType::from_i32(bp.r#type)
@@ -266,13 +586,16 @@ impl TryFrom<&[u8]> for Message {
 impl std::fmt::Debug for Message {
     fn fmt(&self, fmt: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
         let mut first = true;
-        for (cid, priority) in self.want() {
+        for (cid, entry) in self.want() {
             if first {
                 first = false;
             } else {
                 write!(fmt, ", ")?;
             }
-            write!(fmt, "want: {} {}", cid, priority)?;
+            match entry.want_type {
+                WantType::Block => write!(fmt, "want: {} {}", cid, entry.priority)?,
+                WantType::Have => write!(fmt, "want-have: {} {}", cid, entry.priority)?,
+            }
         }
         for cid in self.cancel() {
             if first {
@@ -290,6 +613,22 @@ impl std::fmt::Debug for Message {
             }
             write!(fmt, "block: {}", block.cid())?;
         }
+        for cid in self.have() {
+            if first {
+                first = false;
+            } else {
+                write!(fmt, ", ")?;
+            }
+            write!(fmt, "have: {}", cid)?;
+        }
+        for cid in self.dont_have() {
+            if first {
+                first = false;
+            } else {
+                write!(fmt, ", ")?;
+            }
+            write!(fmt, "dont-have: {}", cid)?;
+        }
 
         if first {
             write!(fmt, "(empty message)")?;
@@ -298,3 +637,214 @@ impl std::fmt::Debug for Message {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn want_types_and_presences_roundtrip() {
+        let want_have =
+            Cid::try_from("bafyreihyrpefhacm6kkp4ql6j6udakdit7g3dmkzfriqfykhjw6cad5lrm").unwrap();
+        let want_block = Cid::try_from("QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL").unwrap();
+        let have = Cid::try_from("QmPJ4A6Su27ABvvduX78x2qdWMzkdAYxqeH5TVrHeo3xyy").unwrap();
+
+        let mut message = Message::default();
+        message.add_want(
+            &want_have,
+            WantEntry {
+                priority: 2,
+                want_type: WantType::Have,
+                send_dont_have: true,
+            },
+        );
+        message.want_block(&want_block, 1);
+        message.have_block(&have);
+        message.dont_have_block(&want_have);
+
+        let decoded = Message::from_bytes(&message.to_bytes()).unwrap();
+
+        assert_eq!(decoded, message);
+        assert_eq!(decoded.want()[&want_block], WantEntry::block(1));
+    }
+
+    #[test]
+    fn presences_only_message_is_not_empty() {
+        let cid = Cid::try_from("QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL").unwrap();
+
+        let mut ledger = Ledger::new();
+        ledger.dont_have_block(&cid);
+
+        assert_eq!(ledger.send().len(), 1);
+        assert!(ledger.send().is_empty());
+    }
+
+    #[test]
+    fn wanting_again_after_cancel() {
+        let cid = Cid::try_from("QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL").unwrap();
+
+        let mut ledger = Ledger::new();
+        ledger.want_block(&cid, 1);
+        ledger.send();
+        assert!(ledger.is_block_wanted(&cid));
+
+        ledger.cancel_block(&cid);
+        assert!(!ledger.is_block_wanted(&cid));
+
+        ledger.want_block(&cid, 1);
+        let messages = ledger.send();
+
+        // only the latest of the want and the cancel is sent
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].want().contains_key(&cid));
+        assert!(messages[0].cancel().is_empty());
+        assert!(ledger.is_block_wanted(&cid));
+    }
+
+    #[test]
+    fn wants_of_legacy_peers_are_want_blocks() {
+        let queued = Cid::try_from("QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL").unwrap();
+        let sent = Cid::try_from("QmPJ4A6Su27ABvvduX78x2qdWMzkdAYxqeH5TVrHeo3xyy").unwrap();
+        let later =
+            Cid::try_from("bafyreihyrpefhacm6kkp4ql6j6udakdit7g3dmkzfriqfykhjw6cad5lrm").unwrap();
+
+        let want_have = WantEntry {
+            priority: 2,
+            want_type: WantType::Have,
+            send_dont_have: true,
+        };
+
+        let mut ledger = Ledger::new();
+        ledger.want(&sent, want_have);
+        ledger.send();
+        assert!(!ledger.is_block_wanted(&sent));
+
+        ledger.want(&queued, want_have);
+        ledger.set_version(ProtocolVersion::V1_1_0);
+        ledger.want(&later, want_have);
+
+        // the peer will send the block it was asked about
+        assert!(ledger.is_block_wanted(&sent));
+
+        let messages = ledger.send();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].want()[&queued], WantEntry::block(2));
+        assert_eq!(messages[0].want()[&later], WantEntry::block(2));
+    }
+
+    #[test]
+    fn debt_ratio() {
+        let mut ledger = Ledger::new();
+        assert_eq!(ledger.debt_ratio(), 0.0);
+
+        ledger.update_sent(2, 2000);
+        ledger.update_received(999);
+        ledger.update_wants_received(4);
+
+        assert_eq!(
+            ledger.info(),
+            LedgerInfo {
+                value: 2.0,
+                sent: 2000,
+                received: 999,
+                exchanged: 3,
+                wants_received: 4,
+            }
+        );
+    }
+
+    fn block_of(len: usize, seed: u8) -> Block {
+        use multihash::Sha2_256;
+
+        let mut data = vec![seed; len];
+        data[0] = seed.wrapping_add(1);
+        let cid = Cid::new_v1(cid::Codec::Raw, Sha2_256::digest(&data));
+        Block::new(data.into_boxed_slice(), cid)
+    }
+
+    #[test]
+    fn large_blocks_are_split_to_messages_under_the_limit() {
+        let mut ledger = Ledger::new();
+
+        let wanted = Cid::try_from("QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL").unwrap();
+        ledger.want_block(&wanted, 1);
+
+        let blocks = (0..10u8)
+            .map(|seed| block_of(256 * 1024, seed))
+            .collect::<Vec<_>>();
+
+        for block in &blocks {
+            ledger.add_block(block.clone());
+        }
+
+        let messages = ledger.send();
+
+        // only one of the blocks fits in each message as two blocks would exceed 512 KiB
+        assert_eq!(messages.len(), blocks.len());
+
+        // the wants are sent first
+        assert!(messages[0].want().contains_key(&wanted));
+
+        let mut received = Vec::new();
+        for message in &messages {
+            let bytes = message.to_bytes();
+            assert!(
+                bytes.len() <= MAX_MESSAGE_SIZE,
+                "{} > {}",
+                bytes.len(),
+                MAX_MESSAGE_SIZE
+            );
+            let decoded = Message::from_bytes(&bytes).unwrap();
+            received.extend(decoded.blocks().iter().cloned());
+        }
+
+        assert_eq!(received, blocks);
+    }
+
+    #[test]
+    fn small_blocks_are_packed_up_to_the_limit() {
+        let max_message_size = 64 * 1024;
+        let mut ledger = Ledger::with_max_message_size(max_message_size);
+
+        let blocks = (0..100u8)
+            .map(|seed| block_of(10 * 1024, seed))
+            .collect::<Vec<_>>();
+
+        for block in &blocks {
+            ledger.add_block(block.clone());
+        }
+
+        let messages = ledger.send();
+
+        assert_eq!(messages.len(), 17);
+
+        for message in &messages {
+            assert!(message.to_bytes().len() <= max_message_size);
+            assert!(message.num_of_blocks() <= 6);
+        }
+
+        let received = messages
+            .iter()
+            .flat_map(|message| message.blocks().iter().cloned())
+            .collect::<Vec<_>>();
+
+        assert_eq!(received, blocks);
+    }
+
+    #[test]
+    fn oversized_block_is_sent_alone() {
+        let mut ledger = Ledger::with_max_message_size(1024);
+
+        let small = block_of(100, 0);
+        let large = block_of(4096, 1);
+
+        ledger.add_block(small.clone());
+        ledger.add_block(large.clone());
+        ledger.add_block(small.clone());
+
+        let messages = ledger.send();
+
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[1].blocks(), &[large][..]);
+    }
+}
@@ -1,22 +1,32 @@
 mod bitswap;
 mod block;
 mod control;
+mod engine;
 mod error;
 mod ledger;
 mod prefix;
 mod protocol;
+mod session;
 mod stat;
+mod wants;
 
 pub use crate::bitswap::Bitswap;
 pub use block::Block;
 pub use block::BsBlockStore;
 pub use control::Control;
-pub use ledger::Priority;
+pub use ledger::{LedgerInfo, Priority, WantEntry, WantType};
+pub use session::{Session, SessionId};
 pub use stat::Stats;
 
 //pub use error::BitswapError;
 
-const BS_PROTO_ID: &[u8] = b"/ipfs/bitswap/1.1.0";
+const BS_PROTO_ID: &[u8] = b"/ipfs/bitswap/1.2.0";
+/// Older protocol version without block presences or want-have entries, still spoken by many
+/// peers.
+const BS_PROTO_ID_1_1_0: &[u8] = b"/ipfs/bitswap/1.1.0";
+
+/// The default upper limit for the size of a single message, both sent and received.
+const MAX_MESSAGE_SIZE: usize = 524_288;
 
 mod bitswap_pb {
     include!(concat!(env!("OUT_DIR"), "/bitswap_pb.rs"));
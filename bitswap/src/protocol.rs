@@ -1,4 +1,5 @@
 use async_trait::async_trait;
+use cid::Cid;
 use futures::channel::mpsc;
 use futures::SinkExt;
 use std::error::Error;
@@ -11,31 +12,58 @@ use libp2p_rs::swarm::substream::Substream;
 use libp2p_rs::swarm::Control as SwarmControl;
 use libp2p_rs::traits::{ReadEx, WriteEx};
 
-use crate::ledger::Message;
-use crate::{Block, BS_PROTO_ID};
-
-const MAX_BUF_SIZE: usize = 524_288;
+use crate::ledger::{Message, ProtocolVersion};
+use crate::{Block, BS_PROTO_ID, BS_PROTO_ID_1_1_0};
 
 pub(crate) enum ProtocolEvent {
     NewPeer(PeerId),
     DeadPeer(PeerId),
-    Blocks(PeerId, Vec<Block>),
+    /// The answers to the wantlist of the peer: the found blocks, and the `Have` and `DontHave`
+    /// block presences.
+    Responses {
+        peer: PeerId,
+        blocks: Vec<Block>,
+        haves: Vec<Cid>,
+        dont_haves: Vec<Cid>,
+    },
+    /// Sending the given amount of block bytes to the peer has completed.
+    Sent {
+        peer: PeerId,
+        bytes: usize,
+    },
+    /// A stream with the peer was negotiated with an older protocol version.
+    Negotiated {
+        peer: PeerId,
+        version: ProtocolVersion,
+    },
+}
+
+fn version_of(protocol: &ProtocolId) -> ProtocolVersion {
+    if *protocol == ProtocolId::from(BS_PROTO_ID_1_1_0) {
+        ProtocolVersion::V1_1_0
+    } else {
+        ProtocolVersion::V1_2_0
+    }
 }
 
 #[derive(Clone)]
 pub struct Handler {
     incoming_tx: mpsc::UnboundedSender<(PeerId, Message)>,
     new_peer: mpsc::UnboundedSender<ProtocolEvent>,
+    /// The largest message read from a stream, see [`crate::Bitswap::with_max_message_size`].
+    max_message_size: usize,
 }
 
 impl Handler {
     pub(crate) fn new(
         incoming_tx: mpsc::UnboundedSender<(PeerId, Message)>,
         new_peer: mpsc::UnboundedSender<ProtocolEvent>,
+        max_message_size: usize,
     ) -> Self {
         Handler {
             incoming_tx,
             new_peer,
+            max_message_size,
         }
     }
 }
@@ -44,7 +72,7 @@ impl UpgradeInfo for Handler {
     type Info = ProtocolId;
 
     fn protocol_info(&self) -> Vec<Self::Info> {
-        vec![BS_PROTO_ID.into()]
+        vec![BS_PROTO_ID.into(), BS_PROTO_ID_1_1_0.into()]
     }
 }
 
@@ -66,11 +94,20 @@ impl ProtocolHandler for Handler {
     async fn handle(
         &mut self,
         mut stream: Substream,
-        _info: <Self as UpgradeInfo>::Info,
+        info: <Self as UpgradeInfo>::Info,
     ) -> Result<(), Box<dyn Error>> {
         log::trace!("Handle stream from {}", stream.remote_peer());
+
+        let version = version_of(&info);
+        if version == ProtocolVersion::V1_1_0 {
+            let _ = self.new_peer.unbounded_send(ProtocolEvent::Negotiated {
+                peer: stream.remote_peer(),
+                version,
+            });
+        }
+
         loop {
-            let packet = stream.read_one(MAX_BUF_SIZE).await?;
+            let packet = stream.read_one(self.max_message_size).await?;
             let message = Message::from_bytes(&packet)?;
             let peer = stream.remote_peer();
             self.incoming_tx.send((peer, message)).await?;
@@ -82,14 +119,26 @@ impl ProtocolHandler for Handler {
     }
 }
 
-// Sends bitswap message to remote peer.
-pub(crate) async fn send_message(
+// Sends bitswap messages to remote peer over a single stream, in order. Returns the negotiated
+// protocol version; the messages to 1.1.0 peers are downgraded.
+pub(crate) async fn send_messages(
     mut swarm: SwarmControl,
     peer_id: PeerId,
-    message: Message,
-) -> Result<(), Box<dyn Error>> {
-    log::debug!("sending message to {:?}...", peer_id);
-    let mut stream = swarm.new_stream(peer_id, vec![BS_PROTO_ID.into()]).await?;
-    stream.write_one(message.to_bytes().as_ref()).await?;
-    Ok(())
+    messages: Vec<Message>,
+) -> Result<ProtocolVersion, Box<dyn Error>> {
+    log::debug!("sending {} message(s) to {:?}...", messages.len(), peer_id);
+    let mut stream = swarm
+        .new_stream(peer_id, vec![BS_PROTO_ID.into(), BS_PROTO_ID_1_1_0.into()])
+        .await?;
+    let version = version_of(&stream.protocol());
+    for mut message in messages {
+        if version == ProtocolVersion::V1_1_0 {
+            message.downgrade();
+            if message.is_empty() {
+                continue;
+            }
+        }
+        stream.write_one(message.to_bytes().as_ref()).await?;
+    }
+    Ok(version)
 }
@@ -0,0 +1,68 @@
+use cid::Cid;
+use futures::channel::{mpsc, oneshot};
+use futures::SinkExt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::bitswap::ControlCommand;
+use crate::block::Block;
+use crate::error::BitswapError;
+
+/// Identifies a session in the bitswap main loop.
+pub type SessionId = u64;
+
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A bitswap session, used to fetch the blocks of a single DAG.
+///
+/// The session remembers the peers which have provided earlier blocks of the session, and asks
+/// the following blocks from them first. Only when none of them have the block, the providers are
+/// searched for and all connected peers are asked, like when fetching blocks outside of a session.
+///
+/// Clones of the session refer to the same session, which is closed when the last of them is
+/// dropped.
+#[derive(Clone)]
+pub struct Session(Arc<SessionInner>);
+
+struct SessionInner {
+    id: SessionId,
+    tx: mpsc::UnboundedSender<ControlCommand>,
+}
+
+impl Session {
+    pub(crate) fn new(tx: mpsc::UnboundedSender<ControlCommand>) -> Self {
+        let id = NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed);
+        Session(Arc::new(SessionInner { id, tx }))
+    }
+
+    /// Returns the identifier of the session.
+    pub fn id(&self) -> SessionId {
+        self.0.id
+    }
+
+    /// Retrieves the wanted block within the session.
+    pub async fn want_block(&self, cid: Cid) -> Result<Block, BitswapError> {
+        let (tx, rx) = oneshot::channel();
+        self.0
+            .tx
+            .clone()
+            .send(ControlCommand::WantBlock(cid, Some(self.0.id), tx))
+            .await?;
+        rx.await?
+    }
+}
+
+impl Drop for SessionInner {
+    fn drop(&mut self) {
+        // the main loop might have already exited
+        let _ = self
+            .tx
+            .unbounded_send(ControlCommand::CloseSession(self.id));
+    }
+}
+
+impl std::fmt::Debug for Session {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fmt.debug_tuple("Session").field(&self.0.id).finish()
+    }
+}
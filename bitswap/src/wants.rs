@@ -0,0 +1,437 @@
+use cid::Cid;
+use std::collections::HashMap;
+
+use libp2p_rs::core::PeerId;
+
+use crate::ledger::Priority;
+use crate::session::SessionId;
+
+/// The block presences received for a wanted block.
+#[derive(Debug, Default)]
+struct WantState {
+    /// The peer the block has been asked from with a want-block entry.
+    requested_from: Option<PeerId>,
+    /// The peers which have told they have the block, in the order they told it.
+    haves: Vec<PeerId>,
+    /// The session the block was first wanted in.
+    session: Option<SessionId>,
+    /// The peers of the session which have been asked and have not answered yet.
+    pending_session_peers: Vec<PeerId>,
+    /// Whether providers have been searched for and all connected peers asked.
+    broadcasted: bool,
+}
+
+/// The peers which have provided blocks for a session.
+#[derive(Debug, Default)]
+struct SessionState {
+    /// The peers in the order they first responded.
+    peers: Vec<PeerId>,
+}
+
+/// What the main loop should do for a wanted block.
+#[derive(Debug, PartialEq)]
+pub(crate) enum WantAction {
+    /// Ask the peers of the session whether they have the block, and fall back to broadcasting
+    /// unless they answer in time.
+    AskSession(Cid, Priority, Vec<PeerId>),
+    /// Ask the block from the peer with a want-block entry.
+    Request(Cid, PeerId),
+    /// Search for the providers of the block and ask all connected peers whether they have it.
+    Broadcast(Cid, Priority),
+}
+
+/// Decides whom the wanted blocks are asked from.
+///
+/// Wanted blocks are first asked with want-have entries, either from the peers of the session
+/// the block is wanted in or from all connected peers, and only requested from the first peer
+/// which reports having the block. The peers which have provided blocks for a session are asked
+/// first for the following blocks of the session.
+#[derive(Debug, Default)]
+pub(crate) struct WantManager {
+    wants: HashMap<Cid, WantState>,
+    sessions: HashMap<SessionId, SessionState>,
+}
+
+impl WantManager {
+    /// A new want for the block, optionally in a session. `connected` tells whether a peer is
+    /// still connected.
+    pub(crate) fn want<F>(
+        &mut self,
+        cid: &Cid,
+        priority: Priority,
+        session: Option<SessionId>,
+        connected: F,
+    ) -> Option<WantAction>
+    where
+        F: Fn(&PeerId) -> bool,
+    {
+        let session_peers = match session {
+            Some(id) => self
+                .sessions
+                .entry(id)
+                .or_default()
+                .peers
+                .iter()
+                .filter(|peer| connected(peer))
+                .copied()
+                .collect::<Vec<_>>(),
+            None => Vec::new(),
+        };
+
+        let state = self.wants.entry(cid.clone()).or_default();
+        if state.session.is_none() {
+            state.session = session;
+        }
+
+        if state.broadcasted || state.requested_from.is_some() {
+            // already being fetched from everyone or from a peer which has it
+            return None;
+        }
+
+        if session_peers.is_empty() {
+            return Some(self.broadcast(cid, priority));
+        }
+
+        state.pending_session_peers = session_peers.clone();
+        Some(WantAction::AskSession(cid.clone(), priority, session_peers))
+    }
+
+    /// The peer has told it has the blocks and does not have the `dont_haves`.
+    pub(crate) fn presences(
+        &mut self,
+        source: PeerId,
+        haves: &[Cid],
+        dont_haves: &[Cid],
+    ) -> Vec<WantAction> {
+        let mut actions = Vec::new();
+
+        for cid in haves {
+            let state = match self.wants.get_mut(cid) {
+                Some(state) => state,
+                // no longer wanted
+                None => continue,
+            };
+
+            if !state.haves.contains(&source) {
+                state.haves.push(source);
+            }
+
+            state.pending_session_peers.retain(|peer| *peer != source);
+
+            if state.requested_from.is_none() {
+                state.requested_from = Some(source);
+                actions.push(WantAction::Request(cid.clone(), source));
+            }
+
+            if let Some(session) = state.session {
+                self.add_session_peer(session, source);
+            }
+        }
+
+        for cid in dont_haves {
+            let state = match self.wants.get_mut(cid) {
+                Some(state) => state,
+                None => continue,
+            };
+
+            state.haves.retain(|peer| *peer != source);
+            state.pending_session_peers.retain(|peer| *peer != source);
+
+            if state.requested_from == Some(source) {
+                state.requested_from = state.haves.first().copied();
+                if let Some(next) = state.requested_from {
+                    actions.push(WantAction::Request(cid.clone(), next));
+                    continue;
+                }
+            }
+
+            // none of the peers of the session have the block
+            if state.requested_from.is_none()
+                && state.haves.is_empty()
+                && state.pending_session_peers.is_empty()
+                && !state.broadcasted
+            {
+                actions.push(self.broadcast(cid, 1));
+            }
+        }
+
+        actions
+    }
+
+    /// The block has been received from the peer.
+    pub(crate) fn received(&mut self, source: PeerId, cid: &Cid) {
+        if let Some(session) = self.wants.remove(cid).and_then(|state| state.session) {
+            self.add_session_peer(session, source);
+        }
+    }
+
+    /// The peer has disconnected; the blocks requested from it are requested from the next peer
+    /// which has them.
+    pub(crate) fn peer_disconnected(&mut self, peer: &PeerId) -> Vec<WantAction> {
+        for session in self.sessions.values_mut() {
+            session.peers.retain(|p| p != peer);
+        }
+
+        self.wants
+            .iter_mut()
+            .filter_map(|(cid, state)| {
+                state.haves.retain(|p| p != peer);
+                state.pending_session_peers.retain(|p| p != peer);
+                if state.requested_from == Some(*peer) {
+                    state.requested_from = state.haves.first().copied();
+                    state
+                        .requested_from
+                        .map(|next| WantAction::Request(cid.clone(), next))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// The peers of the session have not provided the block in time.
+    pub(crate) fn session_fallback(&mut self, cid: &Cid) -> Option<WantAction> {
+        let fallback = self
+            .wants
+            .get(cid)
+            .map(|state| !state.broadcasted && state.requested_from.is_none())
+            .unwrap_or(false);
+
+        if fallback {
+            Some(self.broadcast(cid, 1))
+        } else {
+            None
+        }
+    }
+
+    /// The block is no longer wanted.
+    pub(crate) fn remove(&mut self, cid: &Cid) {
+        self.wants.remove(cid);
+    }
+
+    /// Forgets the peers of the session.
+    pub(crate) fn close_session(&mut self, session: SessionId) {
+        self.sessions.remove(&session);
+    }
+
+    fn broadcast(&mut self, cid: &Cid, priority: Priority) -> WantAction {
+        if let Some(state) = self.wants.get_mut(cid) {
+            state.broadcasted = true;
+            state.pending_session_peers.clear();
+        }
+        WantAction::Broadcast(cid.clone(), priority)
+    }
+
+    /// Remembers the peer as a responder of the session.
+    fn add_session_peer(&mut self, session: SessionId, peer: PeerId) {
+        if let Some(session) = self.sessions.get_mut(&session) {
+            if !session.peers.contains(&peer) {
+                session.peers.push(peer);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use multihash::Sha2_256;
+    use std::convert::TryFrom;
+
+    fn cid() -> Cid {
+        Cid::try_from("QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL").unwrap()
+    }
+
+    #[test]
+    fn block_is_requested_from_the_first_peer_which_has_it() {
+        let mut wants = WantManager::default();
+        let (first, second) = (PeerId::random(), PeerId::random());
+        let cid = cid();
+
+        assert_eq!(
+            wants.want(&cid, 1, None, |_| true),
+            Some(WantAction::Broadcast(cid.clone(), 1))
+        );
+
+        assert_eq!(
+            wants.presences(first, &[cid.clone()], &[]),
+            vec![WantAction::Request(cid.clone(), first)]
+        );
+
+        // the block is asked from one peer at a time
+        assert!(wants.presences(second, &[cid.clone()], &[]).is_empty());
+
+        // wanting the block again does not ask anyone again
+        assert_eq!(wants.want(&cid, 1, None, |_| true), None);
+    }
+
+    #[test]
+    fn dont_have_moves_the_request_to_the_next_peer() {
+        let mut wants = WantManager::default();
+        let (first, second) = (PeerId::random(), PeerId::random());
+        let cid = cid();
+
+        wants.want(&cid, 1, None, |_| true);
+        wants.presences(first, &[cid.clone()], &[]);
+        wants.presences(second, &[cid.clone()], &[]);
+
+        assert_eq!(
+            wants.presences(first, &[], &[cid.clone()]),
+            vec![WantAction::Request(cid.clone(), second)]
+        );
+
+        // already broadcasted, so there is no one else to ask
+        assert!(wants.presences(second, &[], &[cid.clone()]).is_empty());
+
+        // until someone else tells they have it
+        let third = PeerId::random();
+        assert_eq!(
+            wants.presences(third, &[cid.clone()], &[]),
+            vec![WantAction::Request(cid, third)]
+        );
+    }
+
+    #[test]
+    fn disconnected_peer_is_replaced() {
+        let mut wants = WantManager::default();
+        let (first, second) = (PeerId::random(), PeerId::random());
+        let cid = cid();
+
+        wants.want(&cid, 1, None, |_| true);
+        wants.presences(first, &[cid.clone()], &[]);
+        wants.presences(second, &[cid.clone()], &[]);
+
+        assert_eq!(
+            wants.peer_disconnected(&first),
+            vec![WantAction::Request(cid.clone(), second)]
+        );
+        assert!(wants.peer_disconnected(&second).is_empty());
+    }
+
+    fn cid_of(data: &[u8]) -> Cid {
+        Cid::new_v1(cid::Codec::Raw, Sha2_256::digest(data))
+    }
+
+    /// Fetches the first block of the session from the peer, making it a peer of the session.
+    fn session_with(wants: &mut WantManager, session: SessionId, peer: PeerId) {
+        let first = cid_of(b"first block of the session");
+
+        wants.want(&first, 1, Some(session), |_| true);
+        wants.presences(peer, &[first.clone()], &[]);
+        wants.received(peer, &first);
+    }
+
+    #[test]
+    fn session_blocks_are_asked_from_the_session_peers() {
+        let mut wants = WantManager::default();
+        let (responder, other) = (PeerId::random(), PeerId::random());
+        let cid = cid();
+
+        session_with(&mut wants, 0, responder);
+
+        assert_eq!(
+            wants.want(&cid, 1, Some(0), |_| true),
+            Some(WantAction::AskSession(cid.clone(), 1, vec![responder]))
+        );
+
+        // the block is requested from the session peer once it tells it has the block, and the
+        // fallback is no longer needed
+        assert_eq!(
+            wants.presences(responder, &[cid.clone()], &[]),
+            vec![WantAction::Request(cid.clone(), responder)]
+        );
+        assert_eq!(wants.session_fallback(&cid), None);
+
+        // other sessions and wants outside of sessions still ask everyone
+        let unrelated = cid_of(b"unrelated");
+        assert_eq!(
+            wants.want(&unrelated, 1, Some(1), |_| true),
+            Some(WantAction::Broadcast(unrelated.clone(), 1))
+        );
+        assert_eq!(
+            wants.presences(other, &[unrelated.clone()], &[]),
+            vec![WantAction::Request(unrelated, other)]
+        );
+    }
+
+    #[test]
+    fn session_falls_back_to_broadcast() {
+        let mut wants = WantManager::default();
+        let responder = PeerId::random();
+        let cid = cid();
+
+        session_with(&mut wants, 0, responder);
+        wants.want(&cid, 1, Some(0), |_| true);
+
+        // the session peer has not answered within the fallback delay
+        assert_eq!(
+            wants.session_fallback(&cid),
+            Some(WantAction::Broadcast(cid.clone(), 1))
+        );
+        assert_eq!(wants.session_fallback(&cid), None);
+    }
+
+    #[test]
+    fn session_broadcasts_when_no_session_peer_has_the_block() {
+        let mut wants = WantManager::default();
+        let (first, second) = (PeerId::random(), PeerId::random());
+        let cid = cid();
+
+        session_with(&mut wants, 0, first);
+        session_with(&mut wants, 0, second);
+
+        assert_eq!(
+            wants.want(&cid, 1, Some(0), |_| true),
+            Some(WantAction::AskSession(cid.clone(), 1, vec![first, second]))
+        );
+
+        assert!(wants.presences(first, &[], &[cid.clone()]).is_empty());
+        assert_eq!(
+            wants.presences(second, &[], &[cid.clone()]),
+            vec![WantAction::Broadcast(cid, 1)]
+        );
+    }
+
+    #[test]
+    fn session_skips_disconnected_and_closed_peers() {
+        let mut wants = WantManager::default();
+        let responder = PeerId::random();
+        let cid = cid();
+
+        session_with(&mut wants, 0, responder);
+        assert_eq!(
+            wants.want(&cid, 1, Some(0), |peer| *peer != responder),
+            Some(WantAction::Broadcast(cid.clone(), 1))
+        );
+        wants.remove(&cid);
+
+        wants.peer_disconnected(&responder);
+        assert_eq!(
+            wants.want(&cid, 1, Some(0), |_| true),
+            Some(WantAction::Broadcast(cid.clone(), 1))
+        );
+        wants.remove(&cid);
+
+        session_with(&mut wants, 1, responder);
+        wants.close_session(1);
+        assert_eq!(
+            wants.want(&cid, 1, Some(1), |_| true),
+            Some(WantAction::Broadcast(cid, 1))
+        );
+    }
+
+    #[test]
+    fn presences_of_unwanted_blocks_are_ignored() {
+        let mut wants = WantManager::default();
+        let peer = PeerId::random();
+        let cid = cid();
+
+        assert!(wants.presences(peer, &[cid.clone()], &[]).is_empty());
+
+        wants.want(&cid, 1, None, |_| true);
+        wants.received(peer, &cid);
+
+        assert!(wants.presences(peer, &[cid.clone()], &[]).is_empty());
+        assert!(wants.presences(peer, &[], &[cid]).is_empty());
+    }
+}
@@ -1,3 +1,12 @@
 fn main() {
-    prost_build::compile_protos(&["src/ipld/dag_pb.proto"], &["src"]).unwrap();
+    prost_build::compile_protos(
+        &[
+            "src/ipld/dag_pb.proto",
+            "src/ipns/ipns_pb.proto",
+            "src/keystore/keys.proto",
+            "src/p2p/store.proto",
+        ],
+        &["src"],
+    )
+    .unwrap();
 }
@@ -1,5 +1,6 @@
 //! go-ipfs compatible configuration file handling and setup.
 
+use ipfs::keystore::{decode_private_key, encode_private_key, KeystoreError};
 use ipfs::{multiaddr, secp256k1, Keypair, Multiaddr};
 use serde::{Deserialize, Serialize};
 use std::fs::{self, File};
@@ -8,12 +9,6 @@ use std::str::FromStr;
 use structopt::StructOpt;
 use thiserror::Error;
 
-/// Temporary module required to de/ser config files base64'd protobuf rsa private key format.
-/// Temporary until the private key import/export can be PR'd into rust-libp2p.
-mod keys_proto {
-    include!(concat!(env!("OUT_DIR"), "/keys_proto.rs"));
-}
-
 /// Defines the configuration types supported by the API.
 #[derive(Debug, StructOpt)]
 pub enum Profile {
@@ -48,16 +43,18 @@ pub enum InitializationError {
     #[error("key generation failed: {0}")]
     KeyGeneration(Box<dyn std::error::Error + 'static>),
     #[error("key encoding failed: {0}")]
-    PrivateKeyEncodingFailed(prost::EncodeError),
+    PrivateKeyEncodingFailed(KeystoreError),
     #[error("config serialization failed: {0}")]
     ConfigWritingFailed(Box<dyn std::error::Error + 'static>),
+    #[error("bootstrap list creation failed: {0}")]
+    BootstrapListCreationFailed(std::io::Error),
 }
 
 /// Creates the IPFS_PATH directory structure and creates a new compatible configuration file with
-/// Secp256k1 key. Returns the Peer ID.
+/// Secp256k1 key. The default profile also seeds the list of bootstrap nodes with the public
+/// ones, which the test profile leaves empty. Returns the Peer ID.
 pub fn init(ipfs_path: &Path, profiles: Vec<Profile>) -> Result<String, InitializationError> {
     use multibase::Base::Base64Pad;
-    use prost::Message;
     use std::fs::OpenOptions;
     use std::io::{BufWriter, Write};
 
@@ -65,29 +62,12 @@ pub fn init(ipfs_path: &Path, profiles: Vec<Profile>) -> Result<String, Initiali
         unimplemented!("Multiple profiles are currently unsupported!")
     }
 
-    let inner_kpair = secp256k1::Keypair::generate();
-    let kp = inner_kpair.clone();
-    let private = kp.secret();
-
-    let keypair = Keypair::Secp256k1(inner_kpair);
+    let keypair = Keypair::Secp256k1(secp256k1::Keypair::generate());
 
     let peer_id = keypair.public().into_peer_id().to_string();
 
-    // TODO: this part could be PR'd to rust-libp2p as they already have some public key
-    // import/export but probably not if ring does not support these required conversions.
-
-    let key_desc = keys_proto::PrivateKey {
-        r#type: keys_proto::KeyType::Secp256k1 as i32,
-        data: private.to_bytes().to_vec(),
-    };
-
-    let private_key = {
-        let mut buf = Vec::with_capacity(key_desc.encoded_len());
-        key_desc
-            .encode(&mut buf)
-            .map_err(InitializationError::PrivateKeyEncodingFailed)?;
-        buf
-    };
+    let private_key =
+        encode_private_key(&keypair).map_err(InitializationError::PrivateKeyEncodingFailed)?;
 
     let private_key = Base64Pad.encode(&private_key);
 
@@ -128,6 +108,11 @@ pub fn init(ipfs_path: &Path, profiles: Vec<Profile>) -> Result<String, Initiali
         .flush()
         .map_err(|e| InitializationError::ConfigWritingFailed(Box::new(e)))?;
 
+    if let Profile::Default = profiles[0] {
+        ipfs::config::init_bootstrap_list(ipfs_path)
+            .map_err(InitializationError::BootstrapListCreationFailed)?;
+    }
+
     Ok(peer_id)
 }
 
@@ -150,8 +135,6 @@ pub enum LoadingError {
     ConfigurationFileFormat(serde_json::Error),
     #[error("failed to load the private key: {0}")]
     PrivateKeyLoadingFailed(Box<dyn std::error::Error + 'static>),
-    #[error("unsupported private key format: {0}")]
-    UnsupportedPrivateKeyType(i32),
     #[error("loaded PeerId {loaded:?} is not the same as in configuration file {stored:?}, this is likely a bug in rust-ipfs-http")]
     PeerIdMismatch { loaded: String, stored: String },
 }
@@ -213,26 +196,13 @@ struct Identity {
 
 impl Identity {
     fn load_keypair(&self) -> Result<ipfs::Keypair, LoadingError> {
-        use keys_proto::KeyType;
         use multibase::Base::Base64Pad;
-        use prost::Message;
 
         let bytes = Base64Pad
             .decode(&self.private_key)
             .map_err(|e| LoadingError::PrivateKeyLoadingFailed(Box::new(e)))?;
 
-        let private_key = keys_proto::PrivateKey::decode(bytes.as_slice())
-            .map_err(|e| LoadingError::PrivateKeyLoadingFailed(Box::new(e)))?;
-
-        Ok(match KeyType::from_i32(private_key.r#type) {
-            Some(KeyType::Secp256k1) => {
-                let pk = secp256k1::SecretKey::from_bytes(private_key.data.to_vec())
-                    .map_err(|e| LoadingError::PrivateKeyLoadingFailed(Box::new(e)))?;
-
-                Keypair::Secp256k1(secp256k1::Keypair::from(pk))
-            }
-            _keytype => return Err(LoadingError::UnsupportedPrivateKeyType(private_key.r#type)),
-        })
+        decode_private_key(&bytes).map_err(|e| LoadingError::PrivateKeyLoadingFailed(Box::new(e)))
     }
 }
 
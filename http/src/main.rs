@@ -1,4 +1,5 @@
 use std::path::PathBuf;
+use std::time::Duration;
 use structopt::StructOpt;
 
 use ipfs::{Ipfs, IpfsOptions, IpfsTypes, UninitializedIpfs};
@@ -21,7 +22,11 @@ enum Options {
         profile: Vec<config::Profile>,
     },
     /// Start the IPFS node in the foreground (not detaching from parent process).
-    Daemon,
+    Daemon {
+        /// Publishes and resolves IPNS records over pubsub in addition to the DHT.
+        #[structopt(long)]
+        enable_namesys_pubsub: bool,
+    },
 }
 
 fn main() {
@@ -59,6 +64,13 @@ fn main() {
 
     let config_path = home.join("config");
 
+    let ipns_pubsub = matches!(
+        opts,
+        Options::Daemon {
+            enable_namesys_pubsub: true
+        }
+    );
+
     let config = match opts {
         Options::Init { profile } => {
             println!("initializing IPFS node at {:?}", home);
@@ -106,7 +118,7 @@ fn main() {
                 }
             }
         }
-        Options::Daemon => {
+        Options::Daemon { .. } => {
             // FIXME: toctou, should just match for this err?
             if !config_path.is_file() {
                 eprintln!("Error: no IPFS repo found in {:?}", home);
@@ -134,9 +146,13 @@ fn main() {
             ipfs_path: home.clone(),
             keypair: config.keypair,
             bootstrap: Vec::new(),
+            bootstrap_interval: Some(Duration::from_secs(30)),
             mdns: false,
             kad_protocol: None,
             listening_addrs: config.swarm,
+            ipns_pubsub,
+            dnslink: Default::default(),
+            reprovider: Default::default(),
             span: None,
         };
 
@@ -12,6 +12,7 @@ pub mod dag;
 pub mod dht;
 pub mod id;
 pub mod ipns;
+pub mod key;
 pub mod pin;
 pub mod pubsub;
 pub mod refs;
@@ -90,6 +91,8 @@ pub fn routes<T: IpfsTypes>(
             .and(query::<version::Query>())
             .and_then(version::version),
         warp::path("bitswap").and(combine!(
+            and_boxed!(warp::path!("ledger"), bitswap::ledger(ipfs)),
+            and_boxed!(warp::path!("reprovide"), bitswap::reprovide(ipfs)),
             and_boxed!(warp::path!("wantlist"), bitswap::wantlist(ipfs)),
             and_boxed!(warp::path!("stat"), bitswap::stat(ipfs))
         )),
@@ -110,6 +113,8 @@ pub fn routes<T: IpfsTypes>(
             and_boxed!(warp::path!("rm" / "all"), bootstrap::bootstrap_clear(ipfs)),
         )),
         warp::path("dag").and(combine!(
+            and_boxed!(warp::path!("export"), dag::export(ipfs)),
+            and_boxed!(warp::path!("import"), dag::import(ipfs)),
             and_boxed!(warp::path!("put"), dag::put(ipfs)),
             and_boxed!(warp::path!("resolve"), dag::resolve(ipfs)),
         )),
@@ -135,6 +140,24 @@ pub fn routes<T: IpfsTypes>(
             and_boxed!(warp::path!("disconnect"), swarm::disconnect(ipfs)),
             and_boxed!(warp::path!("peers"), swarm::peers(ipfs)),
         )),
+        warp::path("key").and(combine!(
+            and_boxed!(warp::path!("export"), key::export(ipfs)),
+            and_boxed!(warp::path!("gen"), key::gen(ipfs)),
+            and_boxed!(warp::path!("import"), key::import(ipfs)),
+            and_boxed!(warp::path!("list"), key::list(ipfs)),
+            and_boxed!(warp::path!("rename"), key::rename(ipfs)),
+            and_boxed!(warp::path!("rm"), key::rm(ipfs)),
+        )),
+        warp::path("name").and(combine!(
+            and_boxed!(warp::path!("publish"), ipns::publish(ipfs)),
+            and_boxed!(warp::path!("resolve"), ipns::name_resolve(ipfs)),
+            and_boxed!(
+                warp::path!("pubsub" / "cancel"),
+                ipns::pubsub_cancel(ipfs)
+            ),
+            and_boxed!(warp::path!("pubsub" / "state"), ipns::pubsub_state(ipfs)),
+            and_boxed!(warp::path!("pubsub" / "subs"), ipns::pubsub_subs(ipfs)),
+        )),
         warp::path("pin").and(combine!(
             and_boxed!(warp::path!("add"), pin::add(ipfs)),
             and_boxed!(warp::path!("ls"), pin::list(ipfs)),
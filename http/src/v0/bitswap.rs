@@ -101,3 +101,67 @@ pub fn stat<T: IpfsTypes>(
 ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
     with_ipfs(ipfs).and_then(stat_query)
 }
+
+#[derive(Debug, Deserialize)]
+pub struct LedgerQuery {
+    arg: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct LedgerResponse {
+    peer: String,
+    value: f64,
+    sent: u64,
+    recv: u64,
+    exchanged: u64,
+}
+
+async fn ledger_query<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    query: LedgerQuery,
+) -> Result<impl Reply, Rejection> {
+    let peer_id = query.arg.parse().map_err(|_| InvalidPeerId)?;
+    let ledger = ipfs
+        .bitswap_ledger(peer_id)
+        .await
+        .map_err(StringError::from)?;
+    let response = LedgerResponse {
+        peer: peer_id.to_string(),
+        value: ledger.value,
+        sent: ledger.sent,
+        recv: ledger.received,
+        exchanged: ledger.exchanged,
+    };
+    Ok(reply::json(&response))
+}
+
+pub fn ledger<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(query::<LedgerQuery>())
+        .and_then(ledger_query)
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ReprovideResponse {
+    provided: u64,
+    failed: u64,
+}
+
+async fn reprovide_query<T: IpfsTypes>(ipfs: Ipfs<T>) -> Result<impl Reply, Rejection> {
+    let stats = ipfs.reprovide().await.map_err(StringError::from)?;
+    let response = ReprovideResponse {
+        provided: stats.provided,
+        failed: stats.failed,
+    };
+    Ok(reply::json(&response))
+}
+
+pub fn reprovide<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs).and_then(reprovide_query)
+}
@@ -1,6 +1,6 @@
 use crate::v0::support::{
-    try_only_named_multipart, with_ipfs, MaybeTimeoutExt, NotImplemented, StringError,
-    StringSerialized,
+    try_only_named_multipart, with_ipfs, HandledErr, MaybeTimeoutExt, NotImplemented,
+    StreamResponseCar, StreamResponseJson, StringError, StringSerialized,
 };
 use cid::{Cid, Codec};
 use futures::stream::Stream;
@@ -147,3 +147,111 @@ async fn inner_resolve<T: IpfsTypes>(
         "RemPath": StringSerialized(remaining),
     })))
 }
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    arg: String,
+}
+
+/// Per https://docs.ipfs.io/reference/http/api/#api-v0-dag-export this endpoint streams the DAG
+/// under the given root as a CARv1 archive.
+pub fn export<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(query::<ExportQuery>())
+        .and_then(export_query)
+}
+
+async fn export_query<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    query: ExportQuery,
+) -> Result<impl Reply, Rejection> {
+    let root: Cid = query.arg.parse().map_err(StringError::from)?;
+
+    Ok(StreamResponseCar(ipfs::car::export(ipfs, root)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportQuery {
+    #[serde(rename = "pin-roots")]
+    pin_roots: Option<bool>,
+}
+
+/// Per https://docs.ipfs.io/reference/http/api/#api-v0-dag-import this endpoint reads CAR archives
+/// from the multipart fields, storing the blocks and pinning the roots unless `pin-roots=false`
+/// was given.
+pub fn import<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(query::<ImportQuery>())
+        .and(warp::header::<Mime>("content-type")) // TODO: rejects if missing
+        .and(warp::body::stream())
+        .and_then(import_query)
+}
+
+async fn import_query<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    query: ImportQuery,
+    mime: Mime,
+    body: impl Stream<Item = Result<impl Buf, warp::Error>> + Send + Unpin,
+) -> Result<impl Reply, Rejection> {
+    use bytes::Bytes;
+    use futures::stream::TryStreamExt;
+    use mpart_async::server::MultipartStream;
+
+    let boundary = mime
+        .get_param("boundary")
+        .map(|v| v.to_string())
+        .ok_or_else(|| StringError::from("missing 'boundary' on content-type"))?;
+
+    let mut fields = MultipartStream::new(
+        Bytes::from(boundary),
+        body.map_ok(|mut buf| buf.copy_to_bytes(buf.remaining())),
+    );
+
+    // keeps the imported blocks from being collected before the roots have been pinned
+    let _permit = ipfs.gc_permit().await;
+
+    let mut roots = Vec::new();
+
+    while let Some(field) = fields.try_next().await.map_err(StringError::from)? {
+        let reader = field
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+            .into_async_read();
+
+        // the roots are pinned only after all of the archives have been read, as go-ipfs does
+        roots.extend(
+            ipfs.import_car(reader, false)
+                .await
+                .map_err(StringError::from)?,
+        );
+    }
+
+    let mut lines = Vec::with_capacity(roots.len());
+
+    for root in roots {
+        let pin_error = if query.pin_roots.unwrap_or(true) {
+            match ipfs.insert_pin(&root, true).await {
+                Ok(_) => String::new(),
+                Err(e) => e.to_string(),
+            }
+        } else {
+            String::new()
+        };
+
+        let mut line = serde_json::to_vec(&json!({
+            "Root": {
+                "Cid": { "/": root.to_string() },
+                "PinErrorMsg": pin_error,
+            }
+        }))
+        .expect("serializing the root cannot fail");
+        line.extend_from_slice(b"\r\n");
+
+        lines.push(Ok::<_, HandledErr>(line));
+    }
+
+    Ok(StreamResponseJson(futures::stream::iter(lines)))
+}
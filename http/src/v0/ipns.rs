@@ -1,8 +1,13 @@
 use crate::v0::support::{with_ipfs, StringError, StringSerialized};
-use ipfs::{Ipfs, IpfsPath, IpfsTypes};
+use ipfs::path::PathRoot;
+use ipfs::{Ipfs, IpfsPath, IpfsTypes, IpnsResolveOptions};
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 use warp::{query, Filter, Rejection, Reply};
 
+/// The default lifetime of the published records, as in go-ipfs.
+const DEFAULT_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+
 #[derive(Debug, Deserialize)]
 pub struct ResolveQuery {
     // the name to resolve
@@ -10,7 +15,7 @@ pub struct ResolveQuery {
     #[serde(rename = "dht-record-count")]
     dht_record_count: Option<usize>,
     #[serde(rename = "dht-timeout")]
-    dht_timeout: Option<String>,
+    dht_timeout: Option<StringSerialized<humantime::Duration>>,
 }
 
 pub fn resolve<T: IpfsTypes>(
@@ -25,10 +30,15 @@ async fn resolve_query<T: IpfsTypes>(
     ipfs: Ipfs<T>,
     query: ResolveQuery,
 ) -> Result<impl Reply, Rejection> {
-    let ResolveQuery { arg, .. } = query;
+    let ResolveQuery {
+        arg,
+        dht_record_count,
+        dht_timeout,
+    } = query;
+    let options = resolve_options(false, dht_record_count, dht_timeout)?;
     let name = arg.into_inner();
     let path = ipfs
-        .resolve_ipns(&name, false)
+        .resolve_ipns_with(&name, false, options)
         .await
         .map_err(StringError::from)?
         .to_string();
@@ -59,32 +69,256 @@ pub fn dns<T: IpfsTypes>(
 
 async fn dns_query<T: IpfsTypes>(ipfs: Ipfs<T>, query: DnsQuery) -> Result<impl Reply, Rejection> {
     let DnsQuery { arg, recursive } = query;
-    // attempt to parse the argument prepended with "/ipns/" if it fails to parse like a compliant
-    // IpfsPath and there is no leading slash
-    let path = if !arg.starts_with('/') {
+    let path = parse_name(&arg)?;
+
+    let path = ipfs
+        .resolve_ipns(&path, recursive.unwrap_or(false))
+        .await
+        .map_err(StringError::from)?
+        .to_string();
+
+    let response = DnsResponse { path };
+
+    Ok(warp::reply::json(&response))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct DnsResponse {
+    path: String,
+}
+
+/// The options for looking up the IPNS records. The records are looked up from the DHT with a
+/// single query, so a `dht-record-count` other than one is rejected.
+fn resolve_options(
+    nocache: bool,
+    dht_record_count: Option<usize>,
+    dht_timeout: Option<StringSerialized<humantime::Duration>>,
+) -> Result<IpnsResolveOptions, StringError> {
+    match dht_record_count {
+        None | Some(1) => {}
+        Some(_) => return Err(StringError::from("dht-record-count is not supported")),
+    }
+
+    Ok(IpnsResolveOptions {
+        nocache,
+        dht_timeout: dht_timeout.map(|timeout| timeout.into_inner().into()),
+    })
+}
+
+/// Parses the argument as an `IpfsPath`. The argument is prepended with "/ipns/" if it fails to
+/// parse like a compliant `IpfsPath` and there is no leading slash.
+fn parse_name(arg: &str) -> Result<IpfsPath, StringError> {
+    if !arg.starts_with('/') {
         if let Ok(parsed) = arg.parse() {
-            Ok(parsed)
-        } else {
-            format!("/ipns/{}", arg).parse()
+            return Ok(parsed);
         }
+        format!("/ipns/{}", arg).parse()
     } else {
         arg.parse()
     }
-    .map_err(StringError::from)?;
+    .map_err(StringError::from)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PublishQuery {
+    // the path to publish
+    arg: StringSerialized<IpfsPath>,
+    resolve: Option<bool>,
+    lifetime: Option<StringSerialized<humantime::Duration>>,
+    key: Option<String>,
+}
+
+pub fn publish<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(query::<PublishQuery>())
+        .and_then(publish_query)
+}
+
+async fn publish_query<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    query: PublishQuery,
+) -> Result<impl Reply, Rejection> {
+    let PublishQuery {
+        arg,
+        resolve,
+        lifetime,
+        key,
+    } = query;
+    let path = arg.into_inner();
+
+    if resolve.unwrap_or(true) {
+        // only checking that the path can be resolved, the path itself is published
+        ipfs.dag()
+            .resolve(path.clone(), true)
+            .await
+            .map_err(StringError::from)?;
+    }
+
+    let lifetime = lifetime
+        .map(|lifetime| lifetime.into_inner().into())
+        .unwrap_or(DEFAULT_LIFETIME);
+
+    let name = ipfs
+        .publish_ipns_with_key(key.as_deref().unwrap_or("self"), &path, lifetime)
+        .await
+        .map_err(StringError::from)?;
+
+    let response = PublishResponse {
+        // the name without the /ipns/ prefix
+        name: name
+            .root()
+            .to_string()
+            .trim_start_matches("/ipns/")
+            .to_owned(),
+        value: path.to_string(),
+    };
+
+    Ok(warp::reply::json(&response))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PublishResponse {
+    name: String,
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NameResolveQuery {
+    // the name to resolve, the node's own name by default
+    arg: Option<String>,
+    recursive: Option<bool>,
+    nocache: Option<bool>,
+    #[serde(rename = "dht-record-count")]
+    dht_record_count: Option<usize>,
+    #[serde(rename = "dht-timeout")]
+    dht_timeout: Option<StringSerialized<humantime::Duration>>,
+}
+
+pub fn name_resolve<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(query::<NameResolveQuery>())
+        .and_then(name_resolve_query)
+}
+
+async fn name_resolve_query<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    query: NameResolveQuery,
+) -> Result<impl Reply, Rejection> {
+    let NameResolveQuery {
+        arg,
+        recursive,
+        nocache,
+        dht_record_count,
+        dht_timeout,
+    } = query;
+    let options = resolve_options(nocache.unwrap_or(false), dht_record_count, dht_timeout)?;
+
+    let name = match arg {
+        Some(arg) => parse_name(&arg)?,
+        None => {
+            let (public_key, _) = ipfs.identity().await.map_err(StringError::from)?;
+            IpfsPath::from(public_key.into_peer_id())
+        }
+    };
 
     let path = ipfs
-        .resolve_ipns(&path, recursive.unwrap_or(false))
+        .resolve_ipns_with(&name, recursive.unwrap_or(true), options)
         .await
         .map_err(StringError::from)?
         .to_string();
 
-    let response = DnsResponse { path };
+    let response = ResolveResponse { path };
 
     Ok(warp::reply::json(&response))
 }
 
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "PascalCase")]
-struct DnsResponse {
-    path: String,
+struct PubsubStateResponse {
+    enabled: bool,
+}
+
+/// `name/pubsub/state`
+pub fn pubsub_state<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs).and_then(pubsub_state_inner)
+}
+
+async fn pubsub_state_inner<T: IpfsTypes>(ipfs: Ipfs<T>) -> Result<impl Reply, Rejection> {
+    let response = PubsubStateResponse {
+        enabled: ipfs.ipns_pubsub_enabled(),
+    };
+
+    Ok(warp::reply::json(&response))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PubsubSubsResponse {
+    strings: Vec<String>,
+}
+
+/// `name/pubsub/subs`
+pub fn pubsub_subs<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs).and_then(pubsub_subs_inner)
+}
+
+async fn pubsub_subs_inner<T: IpfsTypes>(ipfs: Ipfs<T>) -> Result<impl Reply, Rejection> {
+    let strings = ipfs
+        .ipns_pubsub_subscriptions()
+        .into_iter()
+        .map(|peer| IpfsPath::from(peer).to_string())
+        .collect();
+
+    Ok(warp::reply::json(&PubsubSubsResponse { strings }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PubsubCancelQuery {
+    // the name to stop receiving the records of
+    arg: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PubsubCancelResponse {
+    canceled: bool,
+}
+
+/// `name/pubsub/cancel`
+pub fn pubsub_cancel<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(query::<PubsubCancelQuery>())
+        .and_then(pubsub_cancel_query)
+}
+
+async fn pubsub_cancel_query<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    query: PubsubCancelQuery,
+) -> Result<impl Reply, Rejection> {
+    if !ipfs.ipns_pubsub_enabled() {
+        return Err(StringError::from("IPNS over pubsub is not enabled").into());
+    }
+
+    let peer = match parse_name(&query.arg)?.root() {
+        PathRoot::Ipns(peer) => *peer,
+        _ => return Err(StringError::from("only peer id names are subscribed to").into()),
+    };
+
+    let response = PubsubCancelResponse {
+        canceled: ipfs.ipns_pubsub_cancel(&peer),
+    };
+
+    Ok(warp::reply::json(&response))
 }
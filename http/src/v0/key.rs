@@ -0,0 +1,269 @@
+//! `/api/v0/key/*` endpoints for the named keys of the keystore.
+
+use crate::v0::support::{try_only_named_multipart, with_ipfs, StringError};
+use bytes::Buf;
+use futures::stream::Stream;
+use ipfs::{Ipfs, IpfsTypes, KeyFormat, KeyInfo, KeyType};
+use mime::Mime;
+use serde::{Deserialize, Serialize};
+use warp::{http::Response, query, Filter, Rejection, Reply};
+
+/// The largest accepted key file; encoded keys are only a few kilobytes even for large RSA keys.
+const MAX_KEY_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct KeyResponse {
+    name: String,
+    id: String,
+}
+
+impl From<KeyInfo> for KeyResponse {
+    fn from(info: KeyInfo) -> Self {
+        KeyResponse {
+            name: info.name,
+            id: info.id.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct KeysResponse {
+    keys: Vec<KeyResponse>,
+}
+
+fn parse_format(format: Option<String>) -> Result<KeyFormat, StringError> {
+    format
+        .map(|format| format.parse().map_err(StringError::from))
+        .unwrap_or_else(|| Ok(KeyFormat::default()))
+}
+
+/// Collects the repeated `arg` values and the `force` flag of the query.
+fn parse_args(query: &str) -> Result<(Vec<String>, bool), StringError> {
+    let mut args = Vec::new();
+    let mut force = false;
+
+    for (key, value) in url::form_urlencoded::parse(query.as_bytes()) {
+        match &*key {
+            "arg" => args.push(value.into_owned()),
+            "force" | "f" => {
+                force = value
+                    .parse()
+                    .map_err(|_| StringError::from(format!("invalid boolean for {}", key)))?
+            }
+            _ => {}
+        }
+    }
+
+    Ok((args, force))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GenQuery {
+    // the name of the new key
+    arg: String,
+    #[serde(rename = "type")]
+    key_type: Option<String>,
+    // the bits of rsa keys
+    size: Option<usize>,
+}
+
+/// `key/gen`, generating ed25519 keys by default as in recent go-ipfs.
+pub fn gen<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs).and(query::<GenQuery>()).and_then(gen_query)
+}
+
+async fn gen_query<T: IpfsTypes>(ipfs: Ipfs<T>, query: GenQuery) -> Result<impl Reply, Rejection> {
+    let key_type = query
+        .key_type
+        .as_deref()
+        .unwrap_or("ed25519")
+        .parse::<KeyType>()
+        .map_err(StringError::from)?;
+
+    let info = ipfs
+        .keystore()
+        .generate_with_size(&query.arg, key_type, query.size)
+        .await
+        .map_err(StringError::from)?;
+
+    Ok(warp::reply::json(&KeyResponse::from(info)))
+}
+
+/// `key/list`, including the node's own key as `self`.
+pub fn list<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs).and_then(list_inner)
+}
+
+async fn list_inner<T: IpfsTypes>(ipfs: Ipfs<T>) -> Result<impl Reply, Rejection> {
+    let keys = ipfs.list_keys().await.map_err(StringError::from)?;
+
+    let response = KeysResponse {
+        keys: keys.into_iter().map(KeyResponse::from).collect(),
+    };
+
+    Ok(warp::reply::json(&response))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct RenameResponse {
+    was: String,
+    now: String,
+    id: String,
+    overwrite: bool,
+}
+
+/// `key/rename` with the old and the new name as the two `arg`s.
+pub fn rename<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(warp::query::raw())
+        .and_then(rename_query)
+}
+
+async fn rename_query<T: IpfsTypes>(ipfs: Ipfs<T>, query: String) -> Result<impl Reply, Rejection> {
+    let (args, force) = parse_args(&query)?;
+
+    let (from, to) = match args.as_slice() {
+        [from, to] => (from, to),
+        _ => return Err(StringError::from("expected the old and the new name").into()),
+    };
+
+    let (info, overwrite) = ipfs
+        .keystore()
+        .rename(from, to, force)
+        .await
+        .map_err(StringError::from)?;
+
+    let response = RenameResponse {
+        was: from.to_owned(),
+        now: info.name,
+        id: info.id.to_string(),
+        overwrite,
+    };
+
+    Ok(warp::reply::json(&response))
+}
+
+/// `key/rm` with one or more names as the `arg`s.
+pub fn rm<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs).and(warp::query::raw()).and_then(rm_query)
+}
+
+async fn rm_query<T: IpfsTypes>(ipfs: Ipfs<T>, query: String) -> Result<impl Reply, Rejection> {
+    let (args, _) = parse_args(&query)?;
+
+    if args.is_empty() {
+        return Err(StringError::from("missing the names of the keys to remove").into());
+    }
+
+    let mut keys = Vec::with_capacity(args.len());
+    for name in args {
+        let info = ipfs
+            .keystore()
+            .remove(&name)
+            .await
+            .map_err(StringError::from)?;
+        keys.push(KeyResponse::from(info));
+    }
+
+    Ok(warp::reply::json(&KeysResponse { keys }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportQuery {
+    // the name of the imported key
+    arg: String,
+    format: Option<String>,
+}
+
+/// `key/import` reading the key from the multipart body.
+pub fn import<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(query::<ImportQuery>())
+        .and(warp::header::<Mime>("content-type"))
+        .and(warp::body::stream())
+        .and_then(import_inner)
+}
+
+async fn import_inner<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    query: ImportQuery,
+    mime: Mime,
+    body: impl Stream<Item = Result<impl Buf, warp::Error>> + Unpin,
+) -> Result<impl Reply, Rejection> {
+    let format = parse_format(query.format)?;
+
+    let boundary = mime
+        .get_param("boundary")
+        .map(|v| v.to_string())
+        .ok_or_else(|| StringError::from("missing 'boundary' on content-type"))?;
+
+    let data = try_only_named_multipart(&["file", "key"], MAX_KEY_SIZE, boundary, body)
+        .await
+        .map_err(StringError::from)?;
+
+    let info = ipfs
+        .keystore()
+        .import(&query.arg, &data, format)
+        .await
+        .map_err(StringError::from)?;
+
+    Ok(warp::reply::json(&KeyResponse::from(info)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    // the name of the exported key
+    arg: String,
+    format: Option<String>,
+}
+
+/// `key/export` responding with the encoded key.
+pub fn export<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(query::<ExportQuery>())
+        .and_then(export_query)
+}
+
+async fn export_query<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    query: ExportQuery,
+) -> Result<impl Reply, Rejection> {
+    let format = parse_format(query.format)?;
+
+    let data = ipfs
+        .keystore()
+        .export(&query.arg, format)
+        .await
+        .map_err(StringError::from)?;
+
+    Ok(Response::builder().body(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_args;
+
+    #[test]
+    fn rename_args() {
+        let (args, force) = parse_args("arg=a&arg=b%20c&force=true").unwrap();
+        assert_eq!(args, vec!["a".to_owned(), "b c".to_owned()]);
+        assert!(force);
+
+        assert!(parse_args("arg=a&force=maybe").is_err());
+    }
+}
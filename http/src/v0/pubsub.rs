@@ -4,13 +4,11 @@
 //! semantics of getting the messages received on that topic from request onwards. This is
 //! implemented with [`tokio::sync::broadcast`] which supports these semantics.
 //!
-//! # Panics
-//!
-//! The subscription functionality *assumes* that there are no other users for
-//! `ipfs::Ipfs::pubsub_subscribe` and thus will panic if an subscription was made outside of this
-//! locking mechanism.
+//! Other users of `ipfs::Ipfs::pubsub_subscribe`, such as IPNS over pubsub, get streams of their
+//! own sharing the subscription, but an `ipfs::Ipfs::pubsub_unsubscribe` of the topic ends the
+//! streams of this module as well, after which they are subscribed again.
 
-use futures::stream::{Stream, TryStream};
+use futures::stream::{Stream, StreamExt, TryStream};
 use serde::{Deserialize, Serialize};
 
 use tokio::sync::{broadcast, Mutex};
@@ -98,10 +96,6 @@ async fn inner_publish<T: IpfsTypes>(
 }
 
 /// Handling of https://docs-beta.ipfs.io/reference/http/api/#api-v0-pubsub-sub
-///
-/// # Panics
-///
-/// Note the module documentation.
 pub fn subscribe<T: IpfsTypes>(
     ipfs: &Ipfs<T>,
     pubsub: Arc<Pubsub>,
@@ -238,9 +232,7 @@ async fn shovel<T: IpfsTypes>(
             if oe.get().receiver_count() > 0 {
                 if unsubscribed {
                     // this is tricky, se should obtain a new shoveled by resubscribing
-                    // and reusing the existing broadcast::channel. this will fail if
-                    // we introduce other Ipfs::pubsub_subscribe using code which does
-                    // not use the `Pubsub` thing.
+                    // and reusing the existing broadcast::channel.
                     debug!(
                         "resubscribing with the existing broadcast channel to {:?}",
                         topic
@@ -28,6 +28,26 @@ pub struct AddArgs {
     /// When true, a new directory is created to hold more than 1 root level directories.
     #[serde(default, rename = "wrap-with-directory")]
     wrap_with_directory: bool,
+    /// Chunking algorithm: `size-{bytes}`, `rabin`, `rabin-{avg}` or `rabin-{min}-{avg}-{max}`.
+    chunker: Option<String>,
+    /// When true, the files are added with the trickle layout instead of the balanced one.
+    #[serde(default)]
+    trickle: bool,
+    /// Store the file contents as raw blocks; defaults to true with CIDv1.
+    #[serde(rename = "raw-leaves")]
+    raw_leaves: Option<bool>,
+    /// Cid version, 0 or 1; defaults to 1 when a hash other than sha2-256 is used.
+    #[serde(rename = "cid-version")]
+    cid_version: Option<u8>,
+    /// Hash function: `sha2-256`, `blake2b-256` or `sha3-256`.
+    hash: Option<String>,
+    /// When true, the octal `mode` headers of the parts are stored as the UnixFs mode.
+    #[serde(default, rename = "preserve-mode")]
+    preserve_mode: bool,
+    /// When true, the `mtime` and `mtime-nsecs` headers of the parts are stored as the UnixFs
+    /// modification time.
+    #[serde(default, rename = "preserve-mtime")]
+    preserve_mtime: bool,
 }
 
 pub fn add<T: IpfsTypes>(
@@ -397,6 +417,78 @@ mod tests {
         assert_eq!(found, expected);
     }
 
+    #[tokio::test]
+    async fn add_and_get_preserve_metadata() {
+        let ipfs = Node::new("test_node").await;
+
+        let response = warp::test::request()
+            .path("/add?preserve-mode=true&preserve-mtime=true")
+            .header("content-type", "multipart/form-data; boundary=boundary")
+            .body(
+                &b"--boundary\r\n\
+                    Content-Disposition: form-data; name=\"dir\"; filename=\"dir\"\r\n\
+                    Content-Type: application/x-directory\r\n\
+                    mode: 0750\r\n\
+                    mtime: 1500000000\r\n\
+                    \r\n\
+                    \r\n--boundary\r\n\
+                    Content-Disposition: form-data; name=\"file\"; filename=\"dir/file.txt\"\r\n\
+                    Content-Type: application/octet-stream\r\n\
+                    mode: 0600\r\n\
+                    mtime: 1600000000\r\n\
+                    mtime-nsecs: 123\r\n\
+                    \r\n\
+                    contents\n\
+                    \r\n--boundary--\r\n"[..],
+            )
+            .reply(&super::add(&ipfs))
+            .await;
+
+        assert_eq!(response.status(), 200);
+
+        let body = std::str::from_utf8(response.body()).unwrap();
+        let root = body
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .find(|added| added["Name"] == "dir")
+            .unwrap()["Hash"]
+            .as_str()
+            .unwrap()
+            .to_owned();
+
+        let response = warp::test::request()
+            .method("POST")
+            .path(&format!("/get?arg={}", root))
+            .reply(&super::get(&ipfs))
+            .await;
+
+        assert_eq!(response.status(), 200);
+
+        let mut cursor = std::io::Cursor::new(response.body().as_ref());
+        let mut archive = tar::Archive::new(&mut cursor);
+
+        let found = archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                let header = entry.header();
+                (
+                    entry.path().unwrap().into_owned(),
+                    header.mode().unwrap(),
+                    header.mtime().unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let expected = vec![
+            (PathBuf::from(&root), 0o750, 1_500_000_000),
+            (PathBuf::from(&root).join("file.txt"), 0o600, 1_600_000_000),
+        ];
+
+        assert_eq!(found, expected);
+    }
+
     fn get_archive_entries(bytes: impl AsRef<[u8]>) -> Vec<Entry> {
         let mut cursor = std::io::Cursor::new(bytes.as_ref());
 
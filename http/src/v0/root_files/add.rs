@@ -1,13 +1,14 @@
 use super::AddArgs;
 use crate::v0::support::StringError;
 use bytes::{Buf, BufMut, Bytes, BytesMut};
-use cid::Cid;
+use cid::{Cid, Version};
 use futures::stream::{Stream, StreamExt, TryStreamExt};
 use ipfs::unixfs::ll::{
     dir::builder::{
         BufferingTreeBuilder, TreeBuildingFailed, TreeConstructionFailed, TreeNode, TreeOptions,
     },
-    file::adder::FileAdder,
+    file::adder::{Chunker, FileAdder, FileAdderBuilder, TrickleCollector},
+    HashFunction, Metadata,
 };
 use ipfs::{Block, Ipfs, IpfsTypes};
 use mime::Mime;
@@ -15,6 +16,7 @@ use mpart_async::server::{MultipartError, MultipartStream};
 use serde::Serialize;
 use std::borrow::Cow;
 use std::fmt;
+use warp::http::HeaderMap;
 use warp::{Rejection, Reply};
 
 pub(super) async fn add_inner<T: IpfsTypes>(
@@ -28,12 +30,32 @@ pub(super) async fn add_inner<T: IpfsTypes>(
         .map(|v| v.to_string())
         .ok_or_else(|| StringError::from("missing 'boundary' on content-type"))?;
 
+    let (version, hash, raw_leaves) =
+        parse_cid_options(opts.cid_version, opts.hash.as_deref(), opts.raw_leaves)?;
+
+    let mut builder = FileAdder::builder()
+        .with_chunker(parse_chunker(opts.chunker.as_deref())?)
+        .map_err(StringError::from)?
+        .with_cid_version(version)
+        .with_hash(hash)
+        .with_raw_leaves(raw_leaves);
+    if opts.trickle {
+        builder = builder.with_collector(TrickleCollector::default());
+    }
+
+    let mut tree_opts = TreeOptions::default();
+    tree_opts.cid_version(version);
+    tree_opts.hash(hash);
+    if opts.wrap_with_directory {
+        tree_opts.wrap_with_directory();
+    }
+
     let st = MultipartStream::new(
         Bytes::from(boundary),
         body.map_ok(|mut buf| buf.copy_to_bytes(buf.remaining())),
     );
 
-    let st = add_stream(ipfs, st, opts);
+    let st = add_stream(ipfs, st, opts, builder, tree_opts);
 
     // map the errors into json objects; as we can't return them as trailers yet
 
@@ -73,6 +95,7 @@ enum AddError {
     Persisting(ipfs::Error),
     TreeGathering(TreeBuildingFailed),
     TreeBuilding(TreeConstructionFailed),
+    InvalidMetadata(String),
 }
 
 impl From<MultipartError> for AddError {
@@ -94,16 +117,151 @@ impl fmt::Display for AddError {
             Persisting(e) => write!(fmt, "put_block failed: {}", e),
             TreeGathering(g) => write!(fmt, "invalid directory tree: {}", g),
             TreeBuilding(b) => write!(fmt, "constructed invalid directory tree: {}", b),
+            InvalidMetadata(e) => write!(fmt, "invalid metadata header: {}", e),
         }
     }
 }
 
 impl std::error::Error for AddError {}
 
+/// The largest chunk go-ipfs accepts.
+const CHUNK_SIZE_LIMIT: usize = 1024 * 1024;
+
+/// The average chunk size of the plain `rabin` chunker.
+const DEFAULT_RABIN_AVG: usize = 256 * 1024;
+
+/// Parses the `chunker` option with the same formats and limits as go-ipfs.
+fn parse_chunker(spec: Option<&str>) -> Result<Chunker, StringError> {
+    let spec = match spec {
+        None | Some("") | Some("default") => return Ok(Chunker::default()),
+        Some(spec) => spec,
+    };
+
+    let number = |part: &str, label: &str| {
+        part.strip_prefix(label)
+            .unwrap_or(part)
+            .parse::<usize>()
+            .map_err(|_| StringError::from(format!("invalid chunker option: {:?}", spec)))
+    };
+
+    let chunker = match spec.split('-').collect::<Vec<_>>().as_slice() {
+        ["size", size] => Chunker::Size(number(size, "")?),
+        ["rabin"] => Chunker::rabin(DEFAULT_RABIN_AVG),
+        ["rabin", avg] => Chunker::rabin(number(avg, "")?),
+        ["rabin", min, avg, max] => Chunker::Rabin {
+            min: number(min, "min:")?,
+            avg: number(avg, "avg:")?,
+            max: number(max, "max:")?,
+        },
+        ["buzhash"] => return Err(StringError::from("buzhash chunker is not supported")),
+        _ => {
+            return Err(StringError::from(format!(
+                "unrecognized chunker option: {:?}",
+                spec
+            )))
+        }
+    };
+
+    chunker.check().map_err(StringError::from)?;
+
+    let max = match chunker {
+        Chunker::Size(size) => size,
+        Chunker::Rabin { max, .. } => max,
+    };
+
+    if max > CHUNK_SIZE_LIMIT {
+        Err(StringError::from(format!(
+            "chunker parameters may not exceed the maximum chunk size of {}",
+            CHUNK_SIZE_LIMIT
+        )))
+    } else {
+        Ok(chunker)
+    }
+}
+
+/// Resolves the `cid-version`, `hash` and `raw-leaves` options as go-ipfs does: hashes other than
+/// sha2-256 require CIDv1, which in turn enables raw leaves unless they are disabled.
+fn parse_cid_options(
+    version: Option<u8>,
+    hash: Option<&str>,
+    raw_leaves: Option<bool>,
+) -> Result<(Version, HashFunction, bool), StringError> {
+    let hash = match hash.unwrap_or("sha2-256") {
+        "sha2-256" => HashFunction::Sha2_256,
+        "blake2b-256" => HashFunction::Blake2b256,
+        "sha3-256" => HashFunction::Sha3_256,
+        other => return Err(StringError::from(format!("unsupported hash: {:?}", other))),
+    };
+
+    let version = match (version, hash) {
+        (Some(0), HashFunction::Sha2_256) | (None, HashFunction::Sha2_256) => Version::V0,
+        (Some(0), _) => return Err(StringError::from("CIDv0 only supports sha2-256")),
+        (Some(1), _) | (None, _) => Version::V1,
+        (Some(_), _) => return Err(StringError::from("invalid cid version")),
+    };
+
+    let raw_leaves = raw_leaves.unwrap_or(version == Version::V1);
+
+    Ok((version, hash, raw_leaves))
+}
+
+/// Reads the metadata of a part from the `mode`, `mtime` and `mtime-nsecs` headers, as sent by
+/// go-ipfs, when preserving them has been requested.
+fn parse_metadata(
+    headers: &HeaderMap,
+    preserve_mode: bool,
+    preserve_mtime: bool,
+) -> Result<Metadata, AddError> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .map(|value| {
+                value
+                    .to_str()
+                    .map(str::trim)
+                    .map_err(|e| AddError::InvalidMetadata(format!("{}: {}", name, e)))
+            })
+            .transpose()
+    };
+
+    let invalid =
+        |name: &str, value: &str| AddError::InvalidMetadata(format!("{}: {:?}", name, value));
+
+    let mut metadata = Metadata::default();
+
+    if preserve_mode {
+        if let Some(mode) = header("mode")? {
+            let parsed = u32::from_str_radix(mode, 8).map_err(|_| invalid("mode", mode))?;
+            metadata = metadata.with_mode(parsed);
+        }
+    }
+
+    if preserve_mtime {
+        if let Some(mtime) = header("mtime")? {
+            let seconds = mtime.parse::<i64>().map_err(|_| invalid("mtime", mtime))?;
+
+            let nanos = match header("mtime-nsecs")? {
+                Some(nsecs) => nsecs
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|&nanos| nanos < 1_000_000_000)
+                    .ok_or_else(|| invalid("mtime-nsecs", nsecs))?,
+                None => 0,
+            };
+
+            metadata = metadata.with_mtime(seconds, nanos);
+        }
+    }
+
+    Ok(metadata)
+}
+
 fn add_stream<St, E>(
     ipfs: Ipfs<impl IpfsTypes>,
     mut fields: MultipartStream<St, E>,
     opts: AddArgs,
+    builder: FileAdderBuilder,
+    tree_opts: TreeOptions,
 ) -> impl Stream<Item = Result<Bytes, AddError>> + Send + 'static
 where
     St: Stream<Item = Result<Bytes, E>> + Send + Unpin + 'static,
@@ -111,11 +269,6 @@ where
 {
     async_stream::try_stream! {
 
-        let mut tree_opts = TreeOptions::default();
-        if opts.wrap_with_directory {
-            tree_opts.wrap_with_directory();
-        }
-
         let mut tree = BufferingTreeBuilder::new(tree_opts);
         let mut buffer = BytesMut::new();
 
@@ -142,6 +295,7 @@ where
             };
 
             let content_type = field.content_type().map_err(AddError::Header)?;
+            let metadata = parse_metadata(field.headers(), opts.preserve_mode, opts.preserve_mtime)?;
 
             let next = match content_type {
                 "application/octet-stream" => {
@@ -153,7 +307,7 @@ where
                         Ok(())
                     }?;
 
-                    let mut adder = FileAdder::default();
+                    let mut adder = builder.clone().with_metadata(metadata).build();
                     // how many bytes we have stored as blocks
                     let mut total_written = 0u64;
                     // how many bytes of input we have read
@@ -247,13 +401,12 @@ where
                     }?;
 
                     // we need to fully consume this part, even though there shouldn't be anything
-                    // except for the already parsed *but* ignored headers
+                    // except for the already parsed headers
                     while field.try_next().await.map_err(AddError::Parsing)?.is_some() {}
 
-                    // while at the moment we don't parse the mtime, mtime-nsec headers and mode
-                    // those should be reflected in the metadata. this will still add an empty
-                    // directory which is a good thing.
-                    tree.set_metadata(&filename, ipfs::unixfs::ll::Metadata::default())
+                    // this will add an empty directory even without metadata, which is a good
+                    // thing.
+                    tree.set_metadata(&filename, metadata)
                         .map_err(AddError::TreeGathering)?;
                     continue;
                 }
@@ -268,11 +421,16 @@ where
         let mut iter = tree.build();
 
         while let Some(res) = iter.next_borrowed() {
-            let TreeNode { path, cid, total_size, block } = res.map_err(AddError::TreeBuilding)?;
+            let TreeNode { path, cid, total_size, block, intermediate_shard } = res.map_err(AddError::TreeBuilding)?;
 
             // shame we need to allocate once again here..
             ipfs.put_block(Block { cid: cid.to_owned(), data: block.into() }).await.map_err(AddError::Persisting)?;
 
+            if intermediate_shard {
+                // only the root shard of a sharded directory is reported, as in go-ipfs
+                continue;
+            }
+
             serde_json::to_writer((&mut buffer).writer(), &Response::Added {
                 name: Cow::Borrowed(path),
                 hash: Quoted(cid),
@@ -409,6 +567,111 @@ mod tests {
         );
     }
 
+    #[test]
+    fn chunker_options() {
+        use super::parse_chunker;
+        use ipfs::unixfs::ll::file::adder::Chunker;
+
+        assert!(matches!(parse_chunker(None), Ok(Chunker::Size(262144))));
+        assert!(matches!(
+            parse_chunker(Some("size-1024")),
+            Ok(Chunker::Size(1024))
+        ));
+        assert!(matches!(
+            parse_chunker(Some("rabin")),
+            Ok(Chunker::Rabin {
+                min: 87381,
+                avg: 262144,
+                max: 393216
+            })
+        ));
+        assert!(matches!(
+            parse_chunker(Some("rabin-min:64-avg:256-max:1024")),
+            Ok(Chunker::Rabin {
+                min: 64,
+                avg: 256,
+                max: 1024
+            })
+        ));
+
+        for invalid in &[
+            "size-0",
+            "size-2000000",
+            "rabin-8-256-1024",
+            "rabin-256-64-1024",
+            "rabin-avg:64-min:256-max:1024",
+            "buzhash",
+            "fastcdc",
+        ] {
+            assert!(parse_chunker(Some(invalid)).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn cid_options() {
+        use super::parse_cid_options;
+        use cid::Version;
+        use ipfs::unixfs::ll::HashFunction;
+
+        assert_eq!(
+            parse_cid_options(None, None, None).unwrap(),
+            (Version::V0, HashFunction::Sha2_256, false)
+        );
+        assert_eq!(
+            parse_cid_options(Some(1), None, None).unwrap(),
+            (Version::V1, HashFunction::Sha2_256, true)
+        );
+        assert_eq!(
+            parse_cid_options(None, Some("blake2b-256"), Some(false)).unwrap(),
+            (Version::V1, HashFunction::Blake2b256, false)
+        );
+        assert_eq!(
+            parse_cid_options(None, None, Some(true)).unwrap(),
+            (Version::V0, HashFunction::Sha2_256, true)
+        );
+
+        assert!(parse_cid_options(Some(0), Some("sha3-256"), None).is_err());
+        assert!(parse_cid_options(Some(2), None, None).is_err());
+        assert!(parse_cid_options(None, Some("md5"), None).is_err());
+    }
+
+    #[test]
+    fn metadata_headers() {
+        use super::parse_metadata;
+        use ipfs::unixfs::ll::Metadata;
+        use warp::http::{HeaderMap, HeaderValue};
+
+        let mut headers = HeaderMap::new();
+        headers.insert("mode", HeaderValue::from_static("0644"));
+        headers.insert("mtime", HeaderValue::from_static("1600000000"));
+        headers.insert("mtime-nsecs", HeaderValue::from_static("500"));
+
+        assert_eq!(
+            parse_metadata(&headers, true, true).unwrap(),
+            Metadata::default()
+                .with_mode(0o644)
+                .with_mtime(1_600_000_000, 500)
+        );
+        assert_eq!(
+            parse_metadata(&headers, true, false).unwrap(),
+            Metadata::default().with_mode(0o644)
+        );
+        assert_eq!(
+            parse_metadata(&headers, false, false).unwrap(),
+            Metadata::default()
+        );
+
+        for (name, value) in &[
+            ("mode", "rwxr-xr-x"),
+            ("mtime", "yesterday"),
+            ("mtime-nsecs", "1000000000"),
+        ] {
+            let mut headers = headers.clone();
+            headers.insert(*name, HeaderValue::from_static(*value));
+            assert!(parse_metadata(&headers, true, true).is_err(), "{}", name);
+        }
+    }
+
     async fn tokio_ipfs() -> ipfs::Ipfs<ipfs::TestTypes> {
         let options = ipfs::IpfsOptions::inmemory_with_generated_keys();
         ipfs::UninitializedIpfs::new(options).start().await.unwrap()
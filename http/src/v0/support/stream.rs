@@ -8,6 +8,7 @@ use warp::Reply;
 
 pub struct StreamResponseJson<S>(pub S);
 pub struct StreamResponseText<S>(pub S);
+pub struct StreamResponseCar<S>(pub S);
 
 impl<S> Reply for StreamResponseJson<S>
 where
@@ -31,6 +32,17 @@ where
     }
 }
 
+impl<S> Reply for StreamResponseCar<S>
+where
+    S: TryStream + Send + 'static,
+    S::Ok: Into<Bytes>,
+    S::Error: StdError + Send + Sync + 'static,
+{
+    fn into_response(self) -> warp::reply::Response {
+        inner_into_response(self.0, "application/vnd.ipld.car")
+    }
+}
+
 fn inner_into_response<S>(stream: S, content_type: &'static str) -> warp::reply::Response
 where
     S: TryStream + Send + 'static,
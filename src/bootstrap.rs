@@ -0,0 +1,309 @@
+//! The list of bootstrap nodes and the periodic re-bootstrap.
+//!
+//! The list starts out as [`crate::IpfsOptions::bootstrap`] and is changed with
+//! [`crate::Ipfs::add_bootstrapper`] and the related methods. With a repo kept on disk, see
+//! [`crate::repo::RepoTypes::PERSISTENT`], the list is kept in `ipfs_path/bootstrap`, one
+//! multiaddr per line. The file is seeded with the default [`BOOTSTRAP_NODES`] by
+//! [`crate::config::init_bootstrap_list`], and a node starting without it only begins with the
+//! configured nodes.
+//!
+//! While fewer than [`MIN_PEERS`] peers are connected, the node bootstraps again with the current
+//! list every [`crate::IpfsOptions::bootstrap_interval`].
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::path::PathBuf;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use libp2p_rs::core::{Multiaddr, PeerId};
+use tokio::sync::Mutex;
+use tracing::Span;
+use tracing_futures::Instrument;
+
+use crate::config::BOOTSTRAP_NODES;
+use crate::error::Error;
+use crate::ipns::dnslink::DnsLinkResolver;
+use crate::p2p::{dnsaddr, Controls, MultiaddrWithPeerId, MultiaddrWithoutPeerId};
+
+/// The number of connected peers below which the node bootstraps again, as in go-ipfs.
+const MIN_PEERS: usize = 4;
+
+/// The current list of bootstrap nodes, shared by the clones.
+#[derive(Clone, Debug)]
+pub(crate) struct Bootstrappers {
+    list: Arc<Mutex<Vec<MultiaddrWithPeerId>>>,
+    path: Option<PathBuf>,
+    defaults: Vec<MultiaddrWithPeerId>,
+}
+
+impl Bootstrappers {
+    /// Reads the persisted list from the `path`, or starts with the `configured` nodes when there
+    /// is no such file. The default nodes are only added by [`Bootstrappers::restore`].
+    pub(crate) async fn load(
+        configured: &[(PeerId, Multiaddr)],
+        path: Option<PathBuf>,
+    ) -> Result<Self, Error> {
+        let configured = configured
+            .iter()
+            .filter_map(|(peer, addr)| match with_peer(*peer, addr.clone()) {
+                Ok(addr) => Some(addr),
+                Err(e) => {
+                    warn!(peer = %peer, "ignoring the bootstrap address {}: {}", addr, e);
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut defaults = configured.clone();
+        for addr in BOOTSTRAP_NODES {
+            push_unique(&mut defaults, addr.parse()?);
+        }
+
+        let list = match &path {
+            Some(path) => match tokio::fs::read_to_string(path).await {
+                Ok(contents) => parse_list(&contents)?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => configured,
+                Err(e) => return Err(e.into()),
+            },
+            None => configured,
+        };
+
+        Ok(Bootstrappers {
+            list: Arc::new(Mutex::new(list)),
+            path,
+            defaults,
+        })
+    }
+
+    /// Returns the current list.
+    pub(crate) async fn list(&self) -> Vec<MultiaddrWithPeerId> {
+        self.list.lock().await.clone()
+    }
+
+    /// Adds the addresses missing from the list, returning them.
+    pub(crate) async fn add(
+        &self,
+        addrs: Vec<MultiaddrWithPeerId>,
+    ) -> Result<Vec<MultiaddrWithPeerId>, Error> {
+        let mut list = self.list.lock().await;
+        let added = addrs
+            .into_iter()
+            .filter(|addr| push_unique(&mut list, addr.clone()))
+            .collect::<Vec<_>>();
+
+        if !added.is_empty() {
+            self.persist(&list).await?;
+        }
+        Ok(added)
+    }
+
+    /// Removes the address from the list, returning true if it was there.
+    pub(crate) async fn remove(&self, addr: &MultiaddrWithPeerId) -> Result<bool, Error> {
+        let mut list = self.list.lock().await;
+        let before = list.len();
+        list.retain(|a| a != addr);
+
+        let removed = list.len() != before;
+        if removed {
+            self.persist(&list).await?;
+        }
+        Ok(removed)
+    }
+
+    /// Empties the list, returning the removed addresses.
+    pub(crate) async fn clear(&self) -> Result<Vec<MultiaddrWithPeerId>, Error> {
+        let mut list = self.list.lock().await;
+        let removed = std::mem::take(&mut *list);
+        self.persist(&list).await?;
+        Ok(removed)
+    }
+
+    /// Adds the configured and the default bootstrap nodes back to the list, returning them.
+    pub(crate) async fn restore(&self) -> Result<Vec<MultiaddrWithPeerId>, Error> {
+        self.add(self.defaults.clone()).await?;
+        Ok(self.defaults.clone())
+    }
+
+    /// Spawns the task bootstrapping again while few peers are connected. The task exits once
+    /// all of the clones have been dropped or the swarm has been closed.
+    pub(crate) fn start(
+        &self,
+        controls: Controls,
+        resolver: DnsLinkResolver,
+        interval: Duration,
+        span: Span,
+    ) {
+        let list = Arc::downgrade(&self.list);
+        tokio::spawn(rebootstrap(list, controls, resolver, interval).instrument(span));
+    }
+
+    async fn persist(&self, list: &[MultiaddrWithPeerId]) -> Result<(), Error> {
+        if let Some(path) = &self.path {
+            let contents = list.iter().fold(String::new(), |mut acc, addr| {
+                acc.push_str(&addr.to_string());
+                acc.push('\n');
+                acc
+            });
+            tokio::fs::write(path, contents).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Pairs up the peers and the addresses as expected by Kademlia.
+pub(crate) fn into_pairs(list: Vec<MultiaddrWithPeerId>) -> Vec<(PeerId, Multiaddr)> {
+    list.into_iter()
+        .map(|addr| (addr.peer_id, addr.multiaddr.into()))
+        .collect()
+}
+
+async fn rebootstrap(
+    list: Weak<Mutex<Vec<MultiaddrWithPeerId>>>,
+    controls: Controls,
+    resolver: DnsLinkResolver,
+    interval: Duration,
+) {
+    loop {
+        tokio::time::sleep(interval).await;
+
+        let list = match list.upgrade() {
+            Some(list) => list,
+            None => break,
+        };
+
+        let connections = match controls.swarm().dump_connections(None).await {
+            Ok(connections) => connections,
+            Err(e) => {
+                trace!("failed to list the connections: {}", e);
+                break;
+            }
+        };
+
+        let peers = connections
+            .iter()
+            .map(|c| c.info.remote_peer_id)
+            .collect::<HashSet<_>>();
+
+        if peers.len() >= MIN_PEERS {
+            continue;
+        }
+
+        let bootstrap = into_pairs(list.lock().await.clone());
+        if bootstrap.is_empty() {
+            continue;
+        }
+
+        debug!(
+            peers = peers.len(),
+            "bootstrapping again with {} nodes",
+            bootstrap.len()
+        );
+        let bootstrap = dnsaddr::resolve_bootstrap(&resolver, bootstrap).await;
+        controls.kad().bootstrap(bootstrap).await;
+    }
+
+    trace!("periodic bootstrap stopped");
+}
+
+/// Accepts the address of a bootstrap node with or without the `/p2p` suffix.
+fn with_peer(peer: PeerId, addr: Multiaddr) -> Result<MultiaddrWithPeerId, Error> {
+    let addr = match MultiaddrWithPeerId::try_from(addr.clone()) {
+        Ok(addr) if addr.peer_id == peer => addr,
+        Ok(addr) => {
+            return Err(anyhow::anyhow!(
+                "address of {} given for {}",
+                addr.peer_id,
+                peer
+            ))
+        }
+        Err(_) => MultiaddrWithoutPeerId::try_from(addr)?.with(peer),
+    };
+    Ok(addr)
+}
+
+fn parse_list(contents: &str) -> Result<Vec<MultiaddrWithPeerId>, Error> {
+    let mut list = Vec::new();
+    for line in contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+    {
+        push_unique(&mut list, line.parse()?);
+    }
+    Ok(list)
+}
+
+fn push_unique(list: &mut Vec<MultiaddrWithPeerId>, addr: MultiaddrWithPeerId) -> bool {
+    if list.contains(&addr) {
+        false
+    } else {
+        list.push(addr);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEER: &str = "QmaCpDMGvV2BGHeYERUEnRQAwe3N8SzbUtfsmvsqQLuvuJ";
+
+    #[tokio::test]
+    async fn persisted_list_survives_restarts() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("bootstrap");
+        let configured = vec![(
+            PEER.parse::<PeerId>().unwrap(),
+            "/ip4/10.0.0.1/tcp/4001".parse().unwrap(),
+        )];
+
+        let bootstrappers = Bootstrappers::load(&configured, Some(path.clone()))
+            .await
+            .unwrap();
+
+        // without the file only the configured nodes are used
+        let initial = bootstrappers.list().await;
+        assert_eq!(initial.len(), 1);
+        assert_eq!(
+            initial[0].to_string(),
+            format!("/ip4/10.0.0.1/tcp/4001/p2p/{}", PEER)
+        );
+
+        let removed = bootstrappers.clear().await.unwrap();
+        assert_eq!(removed, initial);
+
+        let added = format!("/ip4/10.0.0.2/tcp/4001/p2p/{}", PEER)
+            .parse::<MultiaddrWithPeerId>()
+            .unwrap();
+        bootstrappers.add(vec![added.clone()]).await.unwrap();
+
+        let reloaded = Bootstrappers::load(&configured, Some(path)).await.unwrap();
+        assert_eq!(reloaded.list().await, vec![added.clone()]);
+
+        assert!(reloaded.remove(&added).await.unwrap());
+        assert!(!reloaded.remove(&added).await.unwrap());
+
+        let defaults = reloaded.restore().await.unwrap();
+        assert_eq!(defaults.len(), BOOTSTRAP_NODES.len() + 1);
+        assert_eq!(defaults[0], initial[0]);
+        assert_eq!(reloaded.list().await, defaults);
+    }
+
+    #[tokio::test]
+    async fn in_memory_list_starts_with_the_configured_nodes() {
+        let configured = vec![(
+            PEER.parse::<PeerId>().unwrap(),
+            format!("/ip4/10.0.0.1/tcp/4001/p2p/{}", PEER)
+                .parse()
+                .unwrap(),
+        )];
+
+        let bootstrappers = Bootstrappers::load(&configured, None).await.unwrap();
+        let list = bootstrappers.list().await;
+        assert_eq!(list.len(), 1);
+        assert_eq!(
+            into_pairs(list),
+            vec![(configured[0].0, "/ip4/10.0.0.1/tcp/4001".parse().unwrap())]
+        );
+    }
+}
@@ -0,0 +1,540 @@
+//! CAR (Content Addressable aRchive) import and export.
+//!
+//! Archives are written in the [CARv1] format, which is a varint length prefixed dag-cbor header
+//! listing the roots followed by varint length prefixed sections of the binary `Cid` and the block
+//! data. Both [CARv1] and [CARv2] archives can be read; the CARv2 index is not used as the
+//! archive is always read from start to end.
+//!
+//! [CARv1]: https://ipld.io/specs/transport/car/carv1/
+//! [CARv2]: https://ipld.io/specs/transport/car/carv2/
+
+use crate::ipld::dag_cbor::DagCborCodec;
+use crate::ipld::{decode_ipld, encode_ipld, validate, BlockError, Ipld};
+use crate::{Block, Ipfs, IpfsTypes};
+use cid::{Cid, Codec};
+use futures::io::{AsyncRead, AsyncReadExt};
+use futures::stream::Stream;
+use std::borrow::Borrow;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+/// The fixed bytes which start every CARv2 archive: a CARv1 header with the version 2 and no
+/// roots.
+const CARV2_PRAGMA: [u8; 11] = [
+    0x0a, 0xa1, 0x67, 0x76, 0x65, 0x72, 0x73, 0x69, 0x6f, 0x6e, 0x02,
+];
+
+/// Length of the CARv2 header following the pragma: 16 bytes of characteristics, and the data
+/// offset, data size and index offset as little endian `u64`s.
+const CARV2_HEADER_LEN: usize = 40;
+
+/// The upper limit for the section length; the `Cid` is not expected to be larger than this over
+/// the maximum block size.
+const MAX_SECTION_LEN: u64 = (crate::ipld::MAX_BLOCK_SIZE + 1024) as u64;
+
+/// The upper limit for the CARv1 header length.
+const MAX_HEADER_LEN: u64 = 1024 * 1024;
+
+/// Failures which can happen while reading or writing an archive.
+#[derive(Debug, thiserror::Error)]
+pub enum CarError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid header: {0}")]
+    InvalidHeader(&'static str),
+    #[error("unsupported version: {0}")]
+    UnsupportedVersion(u64),
+    #[error("invalid varint")]
+    InvalidVarint,
+    #[error("section length {0} is out of range")]
+    SectionLength(u64),
+    #[error("invalid cid: {0}")]
+    InvalidCid(#[from] cid::Error),
+    #[error("invalid block: {0}")]
+    Block(#[from] BlockError),
+    #[error("loading failed: {0}")]
+    Loading(#[from] crate::Error),
+}
+
+/// The roots of the archive, along with the version the archive was read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CarHeader {
+    /// The version of the archive, either 1 or 2.
+    pub version: u64,
+    /// The root `Cid`s of the archive.
+    pub roots: Vec<Cid>,
+}
+
+/// Encodes the varint length prefixed CARv1 header for the given roots.
+pub fn encode_header(roots: &[Cid]) -> Vec<u8> {
+    let mut map = BTreeMap::new();
+    map.insert(
+        "roots".to_owned(),
+        Ipld::List(roots.iter().cloned().map(Ipld::Link).collect()),
+    );
+    map.insert("version".to_owned(), Ipld::Integer(1));
+
+    let header = encode_ipld(&Ipld::Map(map), Codec::DagCBOR)
+        .expect("encoding the header to dag-cbor cannot fail");
+
+    let mut out = Vec::with_capacity(header.len() + 10);
+    write_varint(&mut out, header.len() as u64);
+    out.extend_from_slice(&header);
+    out
+}
+
+/// Encodes the varint length prefixed section for the block.
+pub fn encode_section(block: &Block) -> Vec<u8> {
+    let cid = block.cid().to_bytes();
+    let data = block.data();
+
+    let mut out = Vec::with_capacity(cid.len() + data.len() + 10);
+    write_varint(&mut out, (cid.len() + data.len()) as u64);
+    out.extend_from_slice(&cid);
+    out.extend_from_slice(data);
+    out
+}
+
+/// Creates a stream of the CARv1 archive of the DAG under `root`, yielding the header first and
+/// then a section per block. Every unique block is written once, in the order they are found by
+/// walking the DAG breadth-first with [`crate::refs::iplds_refs`], fetching the missing blocks.
+///
+/// Depending on how this function is called, the lifetime will be tied to the lifetime of given
+/// `&Ipfs` or `'static` when given ownership of `Ipfs`.
+pub fn export<'a, Types, MaybeOwned>(
+    ipfs: MaybeOwned,
+    root: Cid,
+) -> impl Stream<Item = Result<Vec<u8>, CarError>> + Send + 'a
+where
+    Types: IpfsTypes,
+    MaybeOwned: Borrow<Ipfs<Types>> + Send + 'a,
+{
+    use futures::stream::TryStreamExt;
+
+    async_stream::try_stream! {
+        yield encode_header(std::slice::from_ref(&root));
+
+        // if this is not bound to a local variable it'll introduce a Sync requirement on
+        // `MaybeOwned` which we don't necessarily need.
+        let borrowed = ipfs.borrow();
+
+        let block = borrowed.get_block(&root).await?;
+        let ipld = decode_ipld(&root, block.data())?;
+
+        yield encode_section(&block);
+
+        let refs = crate::refs::iplds_refs(borrowed, Some((root.clone(), ipld)), None, true);
+        futures::pin_mut!(refs);
+
+        while let Some(edge) = refs.try_next().await? {
+            // the walk has already loaded the block
+            let block = borrowed.get_block(&edge.destination).await?;
+            yield encode_section(&block);
+        }
+    }
+}
+
+/// Reads blocks out of a CARv1 or CARv2 archive, verifying each of them against their `Cid`.
+pub struct CarReader<R> {
+    reader: R,
+    header: CarHeader,
+    /// For CARv2 the bytes remaining in the inner CARv1 payload.
+    remaining: Option<u64>,
+}
+
+impl<R: AsyncRead + Unpin> CarReader<R> {
+    /// Reads the header of the archive. For CARv2 archives, the reader is advanced to the start of
+    /// the inner CARv1 payload.
+    pub async fn new(mut reader: R) -> Result<Self, CarError> {
+        let mut pragma = [0u8; CARV2_PRAGMA.len()];
+        reader.read_exact(&mut pragma).await?;
+
+        if pragma == CARV2_PRAGMA {
+            let mut header = [0u8; CARV2_HEADER_LEN];
+            reader.read_exact(&mut header).await?;
+
+            let data_offset = u64::from_le_bytes(<[u8; 8]>::try_from(&header[16..24]).unwrap());
+            let data_size = u64::from_le_bytes(<[u8; 8]>::try_from(&header[24..32]).unwrap());
+
+            let consumed = (CARV2_PRAGMA.len() + CARV2_HEADER_LEN) as u64;
+            let padding = data_offset
+                .checked_sub(consumed)
+                .ok_or(CarError::InvalidHeader("data offset inside the header"))?;
+
+            skip(&mut reader, padding).await?;
+
+            let mut inner = CarReader {
+                reader,
+                header: CarHeader {
+                    version: 2,
+                    roots: Vec::new(),
+                },
+                remaining: Some(data_size),
+            };
+
+            let inner_header = inner.read_header(None).await?;
+
+            if inner_header.version != 1 {
+                return Err(CarError::InvalidHeader("CARv2 payload is not CARv1"));
+            }
+
+            inner.header.roots = inner_header.roots;
+            Ok(inner)
+        } else {
+            let mut reader = CarReader {
+                reader,
+                header: CarHeader {
+                    version: 1,
+                    roots: Vec::new(),
+                },
+                remaining: None,
+            };
+
+            let header = reader.read_header(Some(&pragma)).await?;
+
+            if header.version != 1 {
+                return Err(CarError::UnsupportedVersion(header.version));
+            }
+
+            reader.header.roots = header.roots;
+            Ok(reader)
+        }
+    }
+
+    /// Returns the header of the archive.
+    pub fn header(&self) -> &CarHeader {
+        &self.header
+    }
+
+    /// Returns the next block, or `None` when all of the blocks have been read.
+    pub async fn next_block(&mut self) -> Result<Option<Block>, CarError> {
+        if self.remaining == Some(0) {
+            return Ok(None);
+        }
+
+        let len = match self.read_varint().await? {
+            Some(len) => len,
+            None if self.remaining.is_none() => return Ok(None),
+            None => return Err(CarError::Io(std::io::ErrorKind::UnexpectedEof.into())),
+        };
+
+        if len == 0 || len > MAX_SECTION_LEN {
+            return Err(CarError::SectionLength(len));
+        }
+
+        let mut section = vec![0u8; len as usize];
+        self.read_exact(&mut section).await?;
+
+        let cid_len = cid_len(&section).ok_or(CarError::InvalidVarint)?;
+        if cid_len > section.len() {
+            return Err(CarError::SectionLength(len));
+        }
+
+        let cid = Cid::try_from(&section[..cid_len])?;
+        let data = section.split_off(cid_len);
+
+        validate(&cid, &data)?;
+
+        Ok(Some(Block::new(data.into_boxed_slice(), cid)))
+    }
+
+    /// Reads the CARv1 header, where the first bytes might have already been read.
+    async fn read_header(&mut self, prefix: Option<&[u8]>) -> Result<CarHeader, CarError> {
+        let mut buffer = prefix.map(|p| p.to_vec()).unwrap_or_default();
+
+        // the length is at most 10 bytes which the prefix has already enough for
+        let (len, varint_len) = loop {
+            match read_varint_from(&buffer) {
+                Some(Ok(parsed)) => break parsed,
+                Some(Err(())) => return Err(CarError::InvalidVarint),
+                None => {
+                    let mut one = [0u8; 1];
+                    self.read_exact(&mut one).await?;
+                    buffer.push(one[0]);
+                }
+            }
+        };
+
+        if len == 0 || len > MAX_HEADER_LEN {
+            return Err(CarError::InvalidHeader("length out of range"));
+        }
+
+        let total = varint_len + len as usize;
+        if buffer.len() < total {
+            let start = buffer.len();
+            buffer.resize(total, 0);
+            self.read_exact(&mut buffer[start..]).await?;
+        }
+
+        let header = DagCborCodec::decode(&buffer[varint_len..total]).map_err(BlockError::from)?;
+        let header = parse_header(header)?;
+
+        // the smallest valid header is longer than the prefix read for the CARv2 pragma
+        if buffer.len() > total {
+            return Err(CarError::InvalidHeader("length too short"));
+        }
+
+        Ok(header)
+    }
+
+    async fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), CarError> {
+        if let Some(remaining) = self.remaining.as_mut() {
+            if (buf.len() as u64) > *remaining {
+                return Err(CarError::Io(std::io::ErrorKind::UnexpectedEof.into()));
+            }
+            *remaining -= buf.len() as u64;
+        }
+
+        self.reader.read_exact(buf).await?;
+        Ok(())
+    }
+
+    /// Reads a varint, returning `None` on a clean end of file.
+    async fn read_varint(&mut self) -> Result<Option<u64>, CarError> {
+        let mut buffer = Vec::with_capacity(10);
+
+        loop {
+            let mut one = [0u8; 1];
+
+            if buffer.is_empty() && self.remaining.is_none() {
+                if self.reader.read(&mut one).await? == 0 {
+                    return Ok(None);
+                }
+            } else {
+                self.read_exact(&mut one).await?;
+            }
+
+            buffer.push(one[0]);
+
+            match read_varint_from(&buffer) {
+                Some(Ok((value, _))) => return Ok(Some(value)),
+                Some(Err(())) => return Err(CarError::InvalidVarint),
+                None => continue,
+            }
+        }
+    }
+}
+
+/// Converts the decoded header document into [`CarHeader`].
+fn parse_header(header: Ipld) -> Result<CarHeader, CarError> {
+    let mut map = match header {
+        Ipld::Map(map) => map,
+        _ => return Err(CarError::InvalidHeader("not a map")),
+    };
+
+    let version = match map.remove("version") {
+        Some(Ipld::Integer(version)) if version > 0 => version as u64,
+        _ => return Err(CarError::InvalidHeader("missing or invalid version")),
+    };
+
+    let roots = match map.remove("roots") {
+        Some(Ipld::List(roots)) => roots
+            .into_iter()
+            .map(|root| match root {
+                Ipld::Link(cid) => Ok(cid),
+                _ => Err(CarError::InvalidHeader("root is not a link")),
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        // CARv2 pragma and the CARv1 inside CARv2 might not have the roots
+        None if version != 1 => Vec::new(),
+        _ => return Err(CarError::InvalidHeader("missing or invalid roots")),
+    };
+
+    Ok(CarHeader { version, roots })
+}
+
+/// Reads and discards the given amount of bytes.
+async fn skip<R: AsyncRead + Unpin>(reader: &mut R, amount: u64) -> Result<(), CarError> {
+    let copied = futures::io::copy(reader.take(amount), &mut futures::io::sink()).await?;
+    if copied != amount {
+        return Err(CarError::Io(std::io::ErrorKind::UnexpectedEof.into()));
+    }
+    Ok(())
+}
+
+/// Returns the length of the binary `Cid` at the start of the section.
+fn cid_len(section: &[u8]) -> Option<usize> {
+    // CIDv0 is a bare sha2-256 multihash
+    if section.len() >= 34 && section[0] == 0x12 && section[1] == 0x20 {
+        return Some(34);
+    }
+
+    // version, codec, multihash code and digest length
+    let mut offset = 0;
+    let mut digest_len = 0;
+    for _ in 0..4 {
+        let (value, len) = read_varint_from(&section[offset..])?.ok()?;
+        offset += len;
+        digest_len = value;
+    }
+
+    offset.checked_add(usize::try_from(digest_len).ok()?)
+}
+
+/// Parses an unsigned varint from the start of the buffer, returning the value and the amount of
+/// bytes it took. Returns `None` if the buffer ends before the varint.
+fn read_varint_from(buffer: &[u8]) -> Option<Result<(u64, usize), ()>> {
+    let mut value = 0u64;
+
+    for (i, byte) in buffer.iter().enumerate() {
+        if i == 9 {
+            // u64 can fit at most 9 * 7 + 1 bits
+            return Some(Err(()));
+        }
+
+        value |= u64::from(byte & 0x7f) << (i * 7);
+
+        if byte & 0x80 == 0 {
+            return Some(Ok((value, i + 1)));
+        }
+    }
+
+    None
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{make_ipld, Node};
+    use futures::stream::TryStreamExt;
+    use hex_literal::hex;
+    use multihash::Sha2_256;
+
+    #[test]
+    fn varint_roundtrip() {
+        for value in &[
+            0u64,
+            1,
+            127,
+            128,
+            300,
+            16_384,
+            u32::MAX as u64,
+            u64::MAX >> 1,
+        ] {
+            let mut out = Vec::new();
+            write_varint(&mut out, *value);
+            assert_eq!(read_varint_from(&out), Some(Ok((*value, out.len()))));
+        }
+
+        assert_eq!(read_varint_from(&[0x80, 0x80]), None);
+    }
+
+    #[test]
+    fn cid_lengths() {
+        let v0 = Cid::try_from("QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL").unwrap();
+        let v1 =
+            Cid::try_from("bafyreihyrpefhacm6kkp4ql6j6udakdit7g3dmkzfriqfykhjw6cad5lrm").unwrap();
+
+        for cid in &[v0, v1] {
+            let mut section = cid.to_bytes();
+            let len = section.len();
+            section.extend_from_slice(b"data");
+            assert_eq!(cid_len(&section), Some(len));
+        }
+    }
+
+    #[test]
+    fn header_of_go_ipfs() {
+        // header written by go-car for the single root below
+        let root =
+            Cid::try_from("bafyreihyrpefhacm6kkp4ql6j6udakdit7g3dmkzfriqfykhjw6cad5lrm").unwrap();
+        let expected = hex!(
+            "3aa265726f6f747381d82a58250001711220f88bc853804cf294fe417e4fa83028689fcdb1b1592c5102e1474dbc200fab8b6776657273696f6e01"
+        );
+
+        assert_eq!(encode_header(&[root]), expected.to_vec());
+    }
+
+    #[tokio::test]
+    async fn export_and_import_roundtrip() {
+        let ipfs = Node::new("test_node").await;
+
+        let leaf = ipfs.put_dag(make_ipld!("leaf")).await.unwrap();
+        let root = ipfs
+            .put_dag(make_ipld!([leaf.clone(), leaf.clone()]))
+            .await
+            .unwrap();
+
+        let archive = export(&*ipfs, root.clone()).try_concat().await.unwrap();
+
+        let mut reader = CarReader::new(&archive[..]).await.unwrap();
+        assert_eq!(reader.header().roots, vec![root.clone()]);
+
+        let mut cids = Vec::new();
+        while let Some(block) = reader.next_block().await.unwrap() {
+            cids.push(block.cid);
+        }
+
+        // the duplicate link is only written once
+        assert_eq!(cids, vec![root.clone(), leaf.clone()]);
+
+        let other = Node::new("other_node").await;
+        let roots = other.import_car(&archive[..], true).await.unwrap();
+        assert_eq!(roots, vec![root.clone()]);
+        assert!(other.is_pinned(&root).await.unwrap());
+        assert!(other.get_block_now(&leaf).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn corrupted_block_is_rejected() {
+        let cid = Cid::try_from("QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL").unwrap();
+        let block = Block::new(b"not the data"[..].into(), cid.clone());
+
+        let mut archive = encode_header(&[cid]);
+        archive.extend(encode_section(&block));
+
+        let mut reader = CarReader::new(&archive[..]).await.unwrap();
+        match reader.next_block().await.unwrap_err() {
+            CarError::Block(BlockError::InvalidHash(_)) => {}
+            x => panic!("unexpected error: {}", x),
+        }
+    }
+
+    #[tokio::test]
+    async fn read_carv2() {
+        let data = b"some data";
+        let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(data));
+        let block = Block::new(data[..].into(), cid.clone());
+
+        let mut payload = encode_header(&[cid.clone()]);
+        payload.extend(encode_section(&block));
+
+        let padding = 5;
+        let data_offset = (CARV2_PRAGMA.len() + CARV2_HEADER_LEN + padding) as u64;
+
+        let mut archive = CARV2_PRAGMA.to_vec();
+        archive.extend_from_slice(&[0u8; 16]);
+        archive.extend_from_slice(&data_offset.to_le_bytes());
+        archive.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+        // no index
+        archive.extend_from_slice(&0u64.to_le_bytes());
+        archive.extend_from_slice(&[0u8; 5]);
+        archive.extend_from_slice(&payload);
+        // garbage in place of the index, which must not be read
+        archive.extend_from_slice(&[0xff; 16]);
+
+        let mut reader = CarReader::new(&archive[..]).await.unwrap();
+        assert_eq!(
+            reader.header(),
+            &CarHeader {
+                version: 2,
+                roots: vec![cid]
+            }
+        );
+        assert_eq!(reader.next_block().await.unwrap(), Some(block));
+        assert_eq!(reader.next_block().await.unwrap(), None);
+    }
+}
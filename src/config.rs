@@ -1,15 +1,54 @@
 //! Static configuration (the bootstrap node(s)).
 
-/// The supported bootstrap nodes (/dnsaddr is not yet supported). This will be updated to contain
-/// the latest known supported IPFS bootstrap peers.
+use std::io::Write;
+use std::path::Path;
+
+/// The file under the `ipfs_path` of a repo kept on disk holding the list of bootstrap nodes, one
+/// multiaddr per line.
+pub(crate) const BOOTSTRAP_FILE: &str = "bootstrap";
+
+/// The default bootstrap nodes of go-ipfs; the /dnsaddr ones are resolved when used. This will be
+/// updated to contain the latest known supported IPFS bootstrap peers.
 // FIXME: it would be nice to parse these into MultiaddrWithPeerId with const fn.
-pub const BOOTSTRAP_NODES: &[&str] =
-    &["/ip4/104.131.131.82/tcp/4001/p2p/QmaCpDMGvV2BGHeYERUEnRQAwe3N8SzbUtfsmvsqQLuvuJ"];
+pub const BOOTSTRAP_NODES: &[&str] = &[
+    "/dnsaddr/bootstrap.libp2p.io/p2p/QmNnooDu7bfjPFoTZYxMNLWUQJyrVwtbZg5gBMjTezGAJN",
+    "/dnsaddr/bootstrap.libp2p.io/p2p/QmQCU2EcMqAqQPR2i9bChDtGNJchTbq5TbXJJ16u19uLTa",
+    "/dnsaddr/bootstrap.libp2p.io/p2p/QmbLHAnMoJPWSCR5Zhtx6BHJX9KiKNN6tpvbUcqanj75Nb",
+    "/dnsaddr/bootstrap.libp2p.io/p2p/QmcZf59bWwK5XFi76CZX8cbJ4BhTzzA3gU1ZjYZcYW3dwt",
+    "/ip4/104.131.131.82/tcp/4001/p2p/QmaCpDMGvV2BGHeYERUEnRQAwe3N8SzbUtfsmvsqQLuvuJ",
+];
+
+/// Seeds the list of bootstrap nodes of a new repo at `ipfs_path` with [`BOOTSTRAP_NODES`], as
+/// go-ipfs does on `init`. Without the list the node only bootstraps with the configured
+/// [`crate::IpfsOptions::bootstrap`] nodes.
+pub fn init_bootstrap_list(ipfs_path: &Path) -> std::io::Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(ipfs_path.join(BOOTSTRAP_FILE))?;
+
+    for addr in BOOTSTRAP_NODES {
+        writeln!(file, "{}", addr)?;
+    }
+
+    file.sync_all()
+}
 
 #[cfg(test)]
 mod tests {
     use crate::p2p::MultiaddrWithPeerId;
 
+    #[test]
+    fn bootstrap_list_is_seeded_once() {
+        let tmp = tempfile::tempdir().unwrap();
+
+        super::init_bootstrap_list(tmp.path()).unwrap();
+        assert!(super::init_bootstrap_list(tmp.path()).is_err());
+
+        let contents = std::fs::read_to_string(tmp.path().join(super::BOOTSTRAP_FILE)).unwrap();
+        assert_eq!(contents.lines().collect::<Vec<_>>(), super::BOOTSTRAP_NODES);
+    }
+
     #[test]
     fn bootstrap_nodes_are_multiaddr_with_peerid() {
         super::BOOTSTRAP_NODES
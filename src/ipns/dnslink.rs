@@ -1,17 +1,26 @@
+//! DNSLink resolution: `/ipns/<domain>` paths point to the path in the `dnslink=<path>` TXT
+//! record of `_dnslink.<domain>`, or of the domain itself as a fallback.
+//!
+//! The [`DnsLinkResolver`] is owned by [`crate::Ipfs`] and caches the resolved paths for as long
+//! as the TTL of the TXT records allows, up to [`DnsLinkOptions::max_cache_ttl`].
+
 use crate::error::Error;
 use crate::path::IpfsPath;
 use bytes::Bytes;
 use domain::base::iana::Rtype;
 use domain::base::{Dname, Question};
 use domain::rdata::rfc1035::Txt;
+use domain::resolv::stub::conf::{ResolvConf, ServerConf, Transport};
 use domain::resolv::{stub::Answer, StubResolver};
-use futures::future::{select_ok, SelectOk};
-use futures::pin_mut;
-use std::future::Future;
-use std::pin::Pin;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
 use std::str::FromStr;
-use std::task::{Context, Poll};
-use std::{fmt, io};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The number of resolved domains kept in the cache.
+const CACHE_CAPACITY: usize = 1024;
 
 #[derive(Debug)]
 pub struct DnsLinkError(String);
@@ -24,46 +33,202 @@ impl fmt::Display for DnsLinkError {
 
 impl std::error::Error for DnsLinkError {}
 
-type FutureAnswer = Pin<Box<dyn Future<Output = Result<Answer, io::Error>> + Send>>;
+/// Configuration of the [`DnsLinkResolver`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DnsLinkOptions {
+    /// The nameservers queried over UDP, or the ones configured for the system when empty.
+    pub nameservers: Vec<SocketAddr>,
+    /// How long a single lookup, including the retries, may take.
+    pub timeout: Duration,
+    /// The longest chain of dnslink records pointing to other domains followed when resolving
+    /// recursively, as in `/ipns/a.example` → `/ipns/b.example` → `/ipfs/<cid>`.
+    pub max_depth: usize,
+    /// The longest time a resolved path is cached for regardless of the TTL of the record; zero
+    /// disables the cache.
+    pub max_cache_ttl: Duration,
+}
 
-pub struct DnsLinkFuture {
-    query: SelectOk<FutureAnswer>,
+impl Default for DnsLinkOptions {
+    /// The system nameservers, and the go-ipfs depth limit of 32.
+    fn default() -> Self {
+        DnsLinkOptions {
+            nameservers: Vec::new(),
+            timeout: Duration::from_secs(10),
+            max_depth: 32,
+            max_cache_ttl: Duration::from_secs(60 * 60),
+        }
+    }
 }
 
-impl Future for DnsLinkFuture {
-    type Output = Result<IpfsPath, Error>;
+struct CacheEntry {
+    path: IpfsPath,
+    expires: Instant,
+}
 
-    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
-        let _self = self.get_mut();
-        loop {
-            let query = &mut _self.query;
-            pin_mut!(query);
-            match query.poll(context) {
-                Poll::Ready(Ok((answer, rest))) => {
-                    for record in answer.answer()?.limit_to::<Txt<_>>() {
-                        let txt = record?;
-                        let bytes: &[u8] = txt.data().as_flat_slice().unwrap_or(b"");
-                        let string = String::from_utf8_lossy(&bytes).to_string();
-                        if let Some(path) = string.strip_prefix("dnslink=") {
-                            let path = IpfsPath::from_str(path)?;
-                            return Poll::Ready(Ok(path));
-                        }
-                    }
-                    if !rest.is_empty() {
-                        _self.query = select_ok(rest);
-                    } else {
-                        return Poll::Ready(Err(
-                            DnsLinkError("no DNS records found".to_owned()).into()
-                        ));
-                    }
+/// Resolves the dnslink records of domains, caching the results.
+#[derive(Clone)]
+pub struct DnsLinkResolver {
+    resolver: StubResolver,
+    options: DnsLinkOptions,
+    cache: Arc<Mutex<HashMap<String, CacheEntry>>>,
+}
+
+impl fmt::Debug for DnsLinkResolver {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("DnsLinkResolver")
+            .field("options", &self.options)
+            .finish()
+    }
+}
+
+impl DnsLinkResolver {
+    pub fn new(options: DnsLinkOptions) -> Result<Self, Error> {
+        let resolver = if options.nameservers.is_empty() {
+            create_resolver()?
+        } else {
+            let mut config = ResolvConf::new();
+            config.servers = options
+                .nameservers
+                .iter()
+                .map(|addr| ServerConf::new(*addr, Transport::Udp))
+                .collect();
+            config.options.timeout = options.timeout;
+            config.finalize();
+            StubResolver::from_conf(config)
+        };
+
+        Ok(DnsLinkResolver {
+            resolver,
+            options,
+            cache: Default::default(),
+        })
+    }
+
+    /// The longest chain of dnslink records followed, see [`DnsLinkOptions::max_depth`].
+    pub fn max_depth(&self) -> usize {
+        self.options.max_depth
+    }
+
+    /// Returns the path of the dnslink record of the domain, preferring the one of
+    /// `_dnslink.<domain>`.
+    pub async fn resolve(&self, domain: &str) -> Result<IpfsPath, Error> {
+        let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+
+        if let Some(path) = self.cached(&domain) {
+            return Ok(path);
+        }
+
+        let dnslink = format!("_dnslink.{}", domain);
+        let (prefixed, bare) = tokio::join!(self.lookup(&dnslink), self.lookup(&domain));
+
+        let (path, ttl) = match (prefixed, bare) {
+            (Ok(Some(found)), _) | (_, Ok(Some(found))) => found,
+            (Err(e), _) | (_, Err(e)) => return Err(e),
+            (Ok(None), Ok(None)) => {
+                return Err(DnsLinkError(format!("no dnslink record found for {}", domain)).into())
+            }
+        };
+
+        let ttl = ttl.min(self.options.max_cache_ttl);
+        if ttl > Duration::from_secs(0) {
+            self.cache_insert(domain, path.clone(), ttl);
+        }
+
+        Ok(path)
+    }
+
+    fn cached(&self, domain: &str) -> Option<IpfsPath> {
+        let mut cache = self.cache.lock().unwrap();
+        match cache.get(domain) {
+            Some(entry) if entry.expires > Instant::now() => Some(entry.path.clone()),
+            Some(_) => {
+                cache.remove(domain);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn cache_insert(&self, domain: String, path: IpfsPath, ttl: Duration) {
+        let now = Instant::now();
+        let mut cache = self.cache.lock().unwrap();
+
+        if cache.len() >= CACHE_CAPACITY {
+            cache.retain(|_, entry| entry.expires > now);
+        }
+
+        if cache.len() >= CACHE_CAPACITY {
+            let soonest = cache
+                .iter()
+                .min_by_key(|(_, entry)| entry.expires)
+                .map(|(domain, _)| domain.clone());
+            if let Some(soonest) = soonest {
+                cache.remove(&soonest);
+            }
+        }
+
+        cache.insert(
+            domain,
+            CacheEntry {
+                path,
+                expires: now + ttl,
+            },
+        );
+    }
+
+    /// Queries the TXT records of the name, returning the path of the first valid dnslink record
+    /// in the sorted order along with its TTL.
+    async fn lookup(&self, name: &str) -> Result<Option<(IpfsPath, Duration)>, Error> {
+        let mut found = Vec::new();
+
+        for (text, ttl) in self.txt_records(name).await? {
+            if let Some(path) = text.strip_prefix("dnslink=") {
+                match IpfsPath::from_str(path.trim()) {
+                    Ok(path) => found.push((path, ttl)),
+                    Err(e) => debug!("invalid dnslink record {:?}: {}", text, e),
                 }
-                Poll::Pending => return Poll::Pending,
-                Poll::Ready(Err(e)) => return Poll::Ready(Err(DnsLinkError(e.to_string()).into())),
             }
         }
+
+        // multiple records are not really supported; pick one regardless of the order of the answer
+        found.sort_by_key(|(path, _)| path.to_string());
+        Ok(found.into_iter().next())
+    }
+
+    /// Queries the TXT records of the name within the configured timeout, returning their texts
+    /// along with their TTLs. Also used for the `_dnsaddr.` records of `/dnsaddr` multiaddrs.
+    pub(crate) async fn txt_records(&self, name: &str) -> Result<Vec<(String, Duration)>, Error> {
+        let qname = Dname::<Bytes>::from_chars(name.chars())?;
+        let question = Question::new_in(qname, Rtype::Txt);
+
+        let answer = tokio::time::timeout(self.options.timeout, self.resolver.query(question))
+            .await
+            .map_err(|_| DnsLinkError(format!("lookup of {} timed out", name)))?
+            .map_err(|e| DnsLinkError(e.to_string()))?;
+
+        txt_records(&answer)
     }
 }
 
+fn txt_records(answer: &Answer) -> Result<Vec<(String, Duration)>, Error> {
+    let mut found = Vec::new();
+
+    for record in answer.answer()?.limit_to::<Txt<_>>() {
+        let record = record?;
+
+        // the record can be split into multiple character strings
+        let text = record.data().iter().fold(Vec::new(), |mut acc, part| {
+            acc.extend_from_slice(part);
+            acc
+        });
+        let text = String::from_utf8_lossy(&text).into_owned();
+
+        found.push((text, Duration::from_secs(record.ttl().into())));
+    }
+
+    Ok(found)
+}
+
 #[cfg(not(target_os = "windows"))]
 fn create_resolver() -> Result<StubResolver, Error> {
     Ok(StubResolver::default())
@@ -71,7 +236,6 @@ fn create_resolver() -> Result<StubResolver, Error> {
 
 #[cfg(target_os = "windows")]
 fn create_resolver() -> Result<StubResolver, Error> {
-    use domain::resolv::stub::conf::ResolvConf;
     use std::{collections::HashSet, io::Cursor};
 
     let mut config = ResolvConf::new();
@@ -95,42 +259,188 @@ fn create_resolver() -> Result<StubResolver, Error> {
     Ok(StubResolver::from_conf(config))
 }
 
-pub async fn resolve(domain: &str) -> Result<IpfsPath, Error> {
-    let mut dnslink = "_dnslink.".to_string();
-    dnslink.push_str(domain);
-    let resolver = create_resolver()?;
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A DNS server answering TXT queries from a fixed set of records, counting the queries.
+    pub(crate) struct StubServer {
+        addr: SocketAddr,
+        queries: Arc<AtomicUsize>,
+    }
 
-    let qname = Dname::<Bytes>::from_chars(domain.chars())?;
-    let question = Question::new_in(qname, Rtype::Txt);
-    let resolver1 = resolver.clone();
-    let query1 = Box::pin(async move { resolver1.query(question).await });
+    impl StubServer {
+        pub(crate) fn start(records: &[(&str, &str, u32)]) -> Self {
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let addr = socket.local_addr().unwrap();
+            let queries = Arc::new(AtomicUsize::new(0));
 
-    let qname = Dname::<Bytes>::from_chars(dnslink.chars())?;
-    let question = Question::new_in(qname, Rtype::Txt);
-    let resolver2 = resolver;
-    let query2 = Box::pin(async move { resolver2.query(question).await });
+            let mut zone: HashMap<String, Vec<(String, u32)>> = HashMap::new();
+            for (name, text, ttl) in records {
+                zone.entry(name.to_string())
+                    .or_default()
+                    .push((text.to_string(), *ttl));
+            }
 
-    Ok(DnsLinkFuture {
-        query: select_ok(vec![query1 as FutureAnswer, query2]),
+            let counter = Arc::clone(&queries);
+            std::thread::spawn(move || {
+                let mut buf = [0u8; 512];
+                while let Ok((len, from)) = socket.recv_from(&mut buf) {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    if let Some(response) = respond(&buf[..len], &zone) {
+                        let _ = socket.send_to(&response, from);
+                    }
+                }
+            });
+
+            StubServer { addr, queries }
+        }
+
+        pub(crate) fn queries(&self) -> usize {
+            self.queries.load(Ordering::SeqCst)
+        }
     }
-    .await?)
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    fn respond(query: &[u8], zone: &HashMap<String, Vec<(String, u32)>>) -> Option<Vec<u8>> {
+        let mut labels = Vec::new();
+        let mut pos = 12;
+        loop {
+            let len = *query.get(pos)? as usize;
+            pos += 1;
+            if len == 0 {
+                break;
+            }
+            labels.push(String::from_utf8_lossy(query.get(pos..pos + len)?).to_lowercase());
+            pos += len;
+        }
+        // the type and the class of the question
+        let question = query.get(12..pos + 4)?;
+        let answers = zone
+            .get(&labels.join("."))
+            .map(Vec::as_slice)
+            .unwrap_or(&[]);
+
+        let mut response = query[..2].to_vec();
+        // a recursive response, NXDOMAIN when there are no records
+        response.extend_from_slice(&[0x81, if answers.is_empty() { 0x83 } else { 0x80 }]);
+        response.extend_from_slice(&[0, 1]);
+        response.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+        response.extend_from_slice(&[0, 0, 0, 0]);
+        response.extend_from_slice(question);
+
+        for (text, ttl) in answers {
+            // pointer to the name of the question, TXT, IN
+            response.extend_from_slice(&[0xc0, 0x0c, 0, 16, 0, 1]);
+            response.extend_from_slice(&ttl.to_be_bytes());
+            response.extend_from_slice(&(text.len() as u16 + 1).to_be_bytes());
+            response.push(text.len() as u8);
+            response.extend_from_slice(text.as_bytes());
+        }
+
+        Some(response)
+    }
+
+    pub(crate) fn resolver(server: &StubServer) -> DnsLinkResolver {
+        DnsLinkResolver::new(DnsLinkOptions {
+            nameservers: vec![server.addr],
+            timeout: Duration::from_secs(2),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn prefers_dnslink_subdomain() {
+        let server = StubServer::start(&[
+            (
+                "_dnslink.example.com",
+                "dnslink=/ipns/other.example.com",
+                60,
+            ),
+            ("example.com", "dnslink=/ipns/bare.example.com", 60),
+            ("example.com", "v=spf1 -all", 60),
+            ("bare.example.com", "dnslink=/ipns/fallback.example.com", 60),
+        ]);
+        let resolver = resolver(&server);
+
+        assert_eq!(
+            resolver.resolve("example.com").await.unwrap().to_string(),
+            "/ipns/other.example.com"
+        );
+        assert_eq!(
+            resolver
+                .resolve("bare.example.com")
+                .await
+                .unwrap()
+                .to_string(),
+            "/ipns/fallback.example.com"
+        );
+        assert!(resolver.resolve("missing.example.com").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn caches_for_the_ttl() {
+        let server = StubServer::start(&[
+            (
+                "_dnslink.cached.example.com",
+                "dnslink=/ipns/a.example.com",
+                60,
+            ),
+            (
+                "_dnslink.uncached.example.com",
+                "dnslink=/ipns/b.example.com",
+                0,
+            ),
+        ]);
+        let resolver = resolver(&server);
+
+        resolver.resolve("cached.example.com").await.unwrap();
+        let queries = server.queries();
+        resolver.resolve("cached.example.com").await.unwrap();
+        assert_eq!(server.queries(), queries);
+
+        resolver.resolve("uncached.example.com").await.unwrap();
+        let queries = server.queries();
+        resolver.resolve("uncached.example.com").await.unwrap();
+        assert!(server.queries() > queries);
+    }
+
+    #[tokio::test]
+    async fn lookups_time_out() {
+        // bound but never answering
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let resolver = DnsLinkResolver::new(DnsLinkOptions {
+            nameservers: vec![socket.local_addr().unwrap()],
+            timeout: Duration::from_millis(200),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let started = Instant::now();
+        assert!(resolver.resolve("example.com").await.is_err());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
 
     #[tokio::test]
     #[ignore]
     async fn test_resolve1() {
-        let res = resolve("ipfs.io").await.unwrap().to_string();
+        let resolver = DnsLinkResolver::new(Default::default()).unwrap();
+        let res = resolver.resolve("ipfs.io").await.unwrap().to_string();
         assert_eq!(res, "/ipns/website.ipfs.io");
     }
 
     #[tokio::test]
     #[ignore]
     async fn test_resolve2() {
-        let res = resolve("website.ipfs.io").await.unwrap().to_string();
+        let resolver = DnsLinkResolver::new(Default::default()).unwrap();
+        let res = resolver
+            .resolve("website.ipfs.io")
+            .await
+            .unwrap()
+            .to_string();
         assert_eq!(
             res,
             "/ipfs/bafybeiayvrj27f65vbecspbnuavehcb3znvnt2strop2rfbczupudoizya"
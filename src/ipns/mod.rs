@@ -4,8 +4,58 @@ use crate::error::Error;
 use crate::path::{IpfsPath, PathRoot};
 use crate::repo::RepoTypes;
 use crate::Ipfs;
+use libp2p_rs::core::identity::Keypair;
+use libp2p_rs::core::PeerId;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
-mod dnslink;
+pub mod dnslink;
+pub mod pubsub;
+pub mod record;
+
+use record::{record_key, select_best, IpnsRecord};
+
+/// The time the resolvers are told to cache the published records for.
+const PUBLISHED_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// The longest time a stored record is used without looking it up again, whatever its TTL.
+const MAX_CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How the records of a peer are looked up when resolving an IPNS name.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IpnsResolveOptions {
+    /// Looks the records up from the DHT even when the stored record is within its TTL.
+    pub nocache: bool,
+    /// How long the DHT lookup may take, or as long as the DHT allows when `None`.
+    pub dht_timeout: Option<Duration>,
+}
+
+/// The times until which the stored records of peers are used without a DHT lookup, set from the
+/// TTL of the record when it is found from the DHT, received over pubsub or published.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RecordCache(Arc<Mutex<HashMap<PeerId, Instant>>>);
+
+impl RecordCache {
+    /// Keeps the record of the peer fresh for its TTL.
+    pub(crate) fn refresh(&self, peer: &PeerId, record: &IpnsRecord) {
+        let expires = Instant::now() + record.ttl().min(MAX_CACHE_TTL);
+        self.0.lock().unwrap().insert(*peer, expires);
+    }
+
+    fn is_fresh(&self, peer: &PeerId) -> bool {
+        let mut expirations = self.0.lock().unwrap();
+        match expirations.get(peer) {
+            Some(expires) if *expires > Instant::now() => true,
+            Some(_) => {
+                expirations.remove(peer);
+                false
+            }
+            None => false,
+        }
+    }
+}
 
 /// IPNS facade around [`Ipns`].
 #[derive(Clone)]
@@ -19,12 +69,165 @@ impl<Types: RepoTypes> Ipns<Types> {
     }
 
     /// Resolves a ipns path to an ipld path.
-    pub async fn resolve(&self, path: &IpfsPath) -> Result<IpfsPath, Error> {
+    pub async fn resolve(
+        &self,
+        path: &IpfsPath,
+        options: &IpnsResolveOptions,
+    ) -> Result<IpfsPath, Error> {
         let path = path.to_owned();
         match path.root() {
             PathRoot::Ipld(_) => Ok(path),
-            PathRoot::Ipns(_) => Err(anyhow::anyhow!("unimplemented")),
-            PathRoot::Dns(domain) => Ok(dnslink::resolve(domain).await?),
+            PathRoot::Ipns(peer) => {
+                let record = self.resolve_record(peer, options).await?;
+                let value = std::str::from_utf8(record.value())?;
+                with_rest(IpfsPath::from_str(value)?, &path)
+            }
+            PathRoot::Dns(domain) => with_rest(self.ipfs.dnslink.resolve(domain).await?, &path),
         }
     }
+
+    /// Returns the valid record stored earlier while it is within its TTL, unless `nocache` is
+    /// set. Otherwise the best valid record of the peer is found from the DHT and the record
+    /// stored earlier, which is used alone if the lookup fails. The best record is stored in the
+    /// repo.
+    ///
+    /// With IPNS over pubsub, the name is subscribed to, and the subscription keeps the stored
+    /// record up to date.
+    async fn resolve_record(
+        &self,
+        peer: &PeerId,
+        options: &IpnsResolveOptions,
+    ) -> Result<IpnsRecord, Error> {
+        if let Some(pubsub) = self.ipfs.ipns_pubsub.as_ref() {
+            if !pubsub.is_subscribed(peer) {
+                if let Err(e) = pubsub.subscribe(&self.ipfs, peer).await {
+                    debug!(peer = %peer, "failed to subscribe to ipns records: {}", e);
+                }
+            }
+        }
+
+        let stored = self.ipfs.repo.get_ipns(peer).await?;
+
+        if !options.nocache && self.ipfs.ipns_cache.is_fresh(peer) {
+            if let Some(best) = select_best(peer, stored.iter()) {
+                return Ok(best.clone());
+            }
+        }
+
+        let lookup = self.ipfs.dht_get(record_key(peer));
+        let found = match options.dht_timeout {
+            Some(limit) => tokio::time::timeout(limit, lookup)
+                .await
+                .unwrap_or_else(|_| Err(anyhow::anyhow!("timed out after {:?}", limit))),
+            None => lookup.await,
+        };
+
+        let found = match found {
+            Ok(bytes) => match IpnsRecord::from_bytes(&bytes) {
+                Ok(record) => Some(record),
+                Err(e) => {
+                    debug!(peer = %peer, "invalid ipns record from the dht: {}", e);
+                    None
+                }
+            },
+            Err(e) => {
+                debug!(peer = %peer, "ipns record not found from the dht: {}", e);
+                None
+            }
+        };
+
+        let best = select_best(peer, stored.iter().chain(found.iter()))
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("no valid ipns record found for {}", peer))?;
+
+        if stored.as_ref() != Some(&best) {
+            self.ipfs.repo.put_ipns(peer, &best).await?;
+        }
+
+        if found.is_some() {
+            self.ipfs.ipns_cache.refresh(peer, &best);
+        } else {
+            debug!(peer = %peer, "using the stored ipns record");
+        }
+
+        Ok(best)
+    }
+
+    /// Publishes a record pointing to the `path` under the peer id of the `keypair`, valid for the
+    /// `lifetime`. The record is stored in the repo, broadcast over pubsub when IPNS over pubsub
+    /// is enabled, and put into the DHT.
+    ///
+    /// Returns the published name, `/ipns/<peer-id>`.
+    pub async fn publish(
+        &self,
+        keypair: &Keypair,
+        path: &IpfsPath,
+        lifetime: Duration,
+    ) -> Result<IpfsPath, Error> {
+        let peer = keypair.public().into_peer_id();
+
+        let sequence = match self.ipfs.repo.get_ipns(&peer).await? {
+            Some(previous) => previous.sequence() + 1,
+            None => 0,
+        };
+
+        let record = IpnsRecord::create(
+            keypair,
+            path.to_string().as_bytes(),
+            sequence,
+            SystemTime::now() + lifetime,
+            PUBLISHED_TTL.min(lifetime),
+        )?;
+
+        self.ipfs.repo.put_ipns(&peer, &record).await?;
+        self.ipfs.ipns_cache.refresh(&peer, &record);
+
+        if self.ipfs.ipns_pubsub.is_some() {
+            self.ipfs
+                .pubsub_publish(pubsub::topic(&peer), record.to_bytes())
+                .await?;
+        }
+
+        self.ipfs
+            .dht_put(record_key(&peer), record.to_bytes())
+            .await?;
+
+        Ok(IpfsPath::from(peer))
+    }
+}
+
+/// Appends the segments of the `path` after its root to the `resolved` path.
+fn with_rest(resolved: IpfsPath, path: &IpfsPath) -> Result<IpfsPath, Error> {
+    let rest = path.iter().collect::<Vec<_>>();
+    if rest.is_empty() {
+        Ok(resolved)
+    } else {
+        resolved.sub_path(&rest.join("/"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(keypair: &Keypair, ttl: Duration) -> IpnsRecord {
+        let eol = SystemTime::now() + Duration::from_secs(60 * 60);
+        IpnsRecord::create(keypair, b"/ipfs/bafkqaaa", 0, eol, ttl).unwrap()
+    }
+
+    #[test]
+    fn stored_records_are_fresh_for_their_ttl() {
+        let keypair = Keypair::generate_ed25519();
+        let peer = keypair.public().into_peer_id();
+        let cache = RecordCache::default();
+
+        assert!(!cache.is_fresh(&peer));
+
+        cache.refresh(&peer, &record(&keypair, Duration::from_secs(60)));
+        assert!(cache.is_fresh(&peer));
+
+        // a record with no ttl is looked up again every time
+        cache.refresh(&peer, &record(&keypair, Duration::from_secs(0)));
+        assert!(!cache.is_fresh(&peer));
+    }
 }
@@ -0,0 +1,149 @@
+//! IPNS over pubsub: the records are broadcast on a per-name topic when published, and the
+//! resolved names are subscribed to so that the updates arrive without a DHT lookup.
+//!
+//! The topic of a name is `/record/` followed by the unpadded base64url encoding of the DHT key
+//! of the record, as in go-ipfs.
+
+use crate::error::Error;
+use crate::repo::{Repo, RepoTypes};
+use crate::Ipfs;
+use futures::future::{abortable, AbortHandle};
+use futures::stream::StreamExt;
+use libp2p_rs::core::PeerId;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tracing_futures::Instrument;
+
+use super::record::{record_key, select_best, IpnsRecord};
+use super::RecordCache;
+
+/// Returns the pubsub topic the records of the peer are published on.
+pub fn topic(peer: &PeerId) -> String {
+    format!(
+        "/record/{}",
+        base64::encode_config(record_key(peer), base64::URL_SAFE_NO_PAD)
+    )
+}
+
+/// The names subscribed to, each with a background task storing the received records. The task
+/// is `None` while the subscription is being made.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct IpnsPubsub {
+    subscriptions: Arc<Mutex<HashMap<PeerId, Option<AbortHandle>>>>,
+}
+
+impl IpnsPubsub {
+    pub(crate) fn is_subscribed(&self, peer: &PeerId) -> bool {
+        matches!(self.subscriptions.lock().unwrap().get(peer), Some(Some(_)))
+    }
+
+    /// Subscribes to the records of the peer unless already subscribed or being subscribed to.
+    pub(crate) async fn subscribe<T: RepoTypes>(
+        &self,
+        ipfs: &Ipfs<T>,
+        peer: &PeerId,
+    ) -> Result<(), Error> {
+        {
+            let mut subscriptions = self.subscriptions.lock().unwrap();
+            if subscriptions.contains_key(peer) {
+                return Ok(());
+            }
+            subscriptions.insert(*peer, None);
+        }
+
+        let stream = match ipfs.pubsub_subscribe(topic(peer)).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                let mut subscriptions = self.subscriptions.lock().unwrap();
+                if let Some(None) = subscriptions.get(peer) {
+                    subscriptions.remove(peer);
+                }
+                return Err(e);
+            }
+        };
+
+        let (task, handle) = abortable(receive(
+            ipfs.repo.clone(),
+            ipfs.ipns_cache.clone(),
+            *peer,
+            stream,
+        ));
+
+        match self.subscriptions.lock().unwrap().get_mut(peer) {
+            Some(slot @ None) => *slot = Some(handle),
+            // cancelled while subscribing; dropping the task drops the stream
+            _ => return Ok(()),
+        }
+
+        tokio::spawn(task.instrument(ipfs.span.clone()));
+
+        Ok(())
+    }
+
+    /// Returns the subscribed names.
+    pub(crate) fn subscriptions(&self) -> Vec<PeerId> {
+        self.subscriptions
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, handle)| handle.is_some())
+            .map(|(peer, _)| *peer)
+            .collect()
+    }
+
+    /// Stops receiving the records of the peer. Returns false if the peer was not subscribed to.
+    pub(crate) fn cancel(&self, peer: &PeerId) -> bool {
+        match self.subscriptions.lock().unwrap().remove(peer) {
+            Some(handle) => {
+                if let Some(handle) = handle {
+                    handle.abort();
+                }
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Stores the received records of the peer when they are valid and newer than the stored one,
+/// keeping the stored record fresh for the TTL of the latest one received.
+async fn receive<T: RepoTypes>(
+    repo: Repo<T>,
+    cache: RecordCache,
+    peer: PeerId,
+    mut stream: crate::SubscriptionStream,
+) {
+    while let Some(message) = stream.next().await {
+        let record = match IpnsRecord::from_bytes(&message.data) {
+            Ok(record) => record,
+            Err(e) => {
+                debug!(peer = %peer, from = %message.source, "invalid ipns record from pubsub: {}", e);
+                continue;
+            }
+        };
+
+        let stored = match repo.get_ipns(&peer).await {
+            Ok(stored) => stored,
+            Err(e) => {
+                warn!(peer = %peer, "failed to read the stored ipns record: {}", e);
+                None
+            }
+        };
+
+        if select_best(&peer, stored.iter().chain(Some(&record))) != Some(&record) {
+            continue;
+        }
+
+        if stored.as_ref() != Some(&record) {
+            trace!(peer = %peer, sequence = record.sequence(), "ipns record from pubsub");
+            if let Err(e) = repo.put_ipns(&peer, &record).await {
+                warn!(peer = %peer, "failed to store the ipns record: {}", e);
+                continue;
+            }
+        }
+
+        cache.refresh(&peer, &record);
+    }
+
+    trace!(peer = %peer, "ipns pubsub subscription ended");
+}
@@ -0,0 +1,597 @@
+//! IPNS records: creation, signing, validation and selection.
+//!
+//! Records are created with both the V1 signature over the value, validity and validity type, and
+//! the V2 signature over the dag-cbor encoded `data` field, as described in the [IPNS spec]. Both
+//! kinds of records are accepted when validating; when the V2 signature is present, it is checked
+//! along with the `data` matching the other fields of the record.
+//!
+//! [IPNS spec]: https://github.com/ipfs/specs/blob/master/IPNS.md
+
+use crate::ipld::dag_cbor::{write_u64, CborError, DagCborCodec, WriteCbor};
+use crate::ipld::Ipld;
+use libp2p_rs::core::identity::Keypair;
+use libp2p_rs::core::{PeerId, PublicKey};
+use prost::Message;
+use std::cmp::Ordering;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+mod pb {
+    include!(concat!(env!("OUT_DIR"), "/ipns_pb.rs"));
+}
+
+use pb::{ipns_entry::ValidityType, IpnsEntry};
+
+/// The largest accepted record, as in go-ipfs.
+pub const MAX_RECORD_SIZE: usize = 10 * 1024;
+
+/// The prefix of the data signed by the V2 signature.
+const SIGNATURE_V2_PREFIX: &[u8] = b"ipns-signature:";
+
+/// Errors from creating, reading or validating records.
+#[derive(Debug, Error)]
+pub enum RecordError {
+    #[error("record is larger than {} bytes", MAX_RECORD_SIZE)]
+    TooLarge,
+    #[error("invalid record: {0}")]
+    Protobuf(#[from] prost::DecodeError),
+    #[error("invalid record data: {0}")]
+    Cbor(#[from] CborError),
+    #[error("signing failed: {0}")]
+    Signing(String),
+    #[error("record has no signature")]
+    MissingSignature,
+    #[error("record signature is invalid")]
+    InvalidSignature,
+    #[error("record data does not match the record")]
+    DataMismatch,
+    #[error("unsupported validity type: {0}")]
+    UnsupportedValidityType(i32),
+    #[error("invalid validity: {0:?}")]
+    InvalidValidity(String),
+    #[error("record has expired")]
+    Expired,
+    #[error("public key cannot be found for the record")]
+    MissingPublicKey,
+    #[error("invalid public key")]
+    InvalidPublicKey,
+    #[error("public key does not belong to the peer")]
+    KeyMismatch,
+}
+
+/// A signed IPNS record pointing to a path.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IpnsRecord {
+    entry: IpnsEntry,
+}
+
+impl IpnsRecord {
+    /// Creates a record pointing to the `value` (usually a path such as `/ipfs/<cid>`), signed
+    /// with the `keypair`. The record is valid until `eol`, and `ttl` is the time the resolvers
+    /// should cache it for.
+    ///
+    /// The public key is embedded into the record when it cannot be extracted from the peer id,
+    /// which is the case with RSA keys.
+    pub fn create(
+        keypair: &Keypair,
+        value: &[u8],
+        sequence: u64,
+        eol: SystemTime,
+        ttl: Duration,
+    ) -> Result<Self, RecordError> {
+        let validity = format_rfc3339(eol).into_bytes();
+        let ttl = ttl.as_nanos() as u64;
+
+        let mut entry = IpnsEntry {
+            value: value.to_vec(),
+            validity_type: ValidityType::Eol as i32,
+            validity,
+            sequence,
+            ttl,
+            ..Default::default()
+        };
+
+        entry.data = encode_data(&entry)?;
+
+        entry.signature_v1 = keypair
+            .sign(&signed_data_v1(&entry))
+            .map_err(|e| RecordError::Signing(e.to_string()))?;
+
+        entry.signature_v2 = keypair
+            .sign(&signed_data_v2(&entry))
+            .map_err(|e| RecordError::Signing(e.to_string()))?;
+
+        let public = keypair.public();
+        if extract_public_key(&public.clone().into_peer_id()).is_none() {
+            entry.pub_key = public.into_protobuf_encoding();
+        }
+
+        Ok(IpnsRecord { entry })
+    }
+
+    /// Reads a record from its protobuf encoding. The record is not validated.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, RecordError> {
+        if bytes.len() > MAX_RECORD_SIZE {
+            return Err(RecordError::TooLarge);
+        }
+
+        let entry = IpnsEntry::decode(bytes)?;
+        Ok(IpnsRecord { entry })
+    }
+
+    /// Returns the protobuf encoding of the record.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.entry.encoded_len());
+        self.entry
+            .encode(&mut bytes)
+            .expect("there is no situation in which the protobuf message can be invalid");
+        bytes
+    }
+
+    /// The value the record points to.
+    pub fn value(&self) -> &[u8] {
+        &self.entry.value
+    }
+
+    /// The sequence number of the record; a newer record has a larger one.
+    pub fn sequence(&self) -> u64 {
+        self.entry.sequence
+    }
+
+    /// The time the record should be cached for by the resolvers.
+    pub fn ttl(&self) -> Duration {
+        Duration::from_nanos(self.entry.ttl)
+    }
+
+    /// The time after which the record is no longer valid.
+    pub fn eol(&self) -> Result<SystemTime, RecordError> {
+        if self.entry.validity_type != ValidityType::Eol as i32 {
+            return Err(RecordError::UnsupportedValidityType(
+                self.entry.validity_type,
+            ));
+        }
+
+        let validity = std::str::from_utf8(&self.entry.validity)
+            .map_err(|_| RecordError::InvalidValidity(format!("{:?}", self.entry.validity)))?;
+
+        parse_rfc3339(validity).ok_or_else(|| RecordError::InvalidValidity(validity.to_owned()))
+    }
+
+    /// Checks that the record has been signed by the key of the `peer` and is still valid.
+    pub fn validate(&self, peer: &PeerId) -> Result<(), RecordError> {
+        self.validate_at(peer, SystemTime::now())
+    }
+
+    /// Checks that the record has been signed by the key of the `peer` and is valid at `now`.
+    pub fn validate_at(&self, peer: &PeerId, now: SystemTime) -> Result<(), RecordError> {
+        let public = self.public_key(peer)?;
+
+        if !self.entry.signature_v2.is_empty() {
+            if !public.verify(&signed_data_v2(&self.entry), &self.entry.signature_v2) {
+                return Err(RecordError::InvalidSignature);
+            }
+            self.check_data()?;
+        } else if !self.entry.signature_v1.is_empty() {
+            if !public.verify(&signed_data_v1(&self.entry), &self.entry.signature_v1) {
+                return Err(RecordError::InvalidSignature);
+            }
+        } else {
+            return Err(RecordError::MissingSignature);
+        }
+
+        if self.eol()? <= now {
+            return Err(RecordError::Expired);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the key of the `peer`, either embedded in the record or in the peer id.
+    fn public_key(&self, peer: &PeerId) -> Result<PublicKey, RecordError> {
+        if self.entry.pub_key.is_empty() {
+            return extract_public_key(peer).ok_or(RecordError::MissingPublicKey);
+        }
+
+        let public = PublicKey::from_protobuf_encoding(&self.entry.pub_key)
+            .map_err(|_| RecordError::InvalidPublicKey)?;
+
+        if &public.clone().into_peer_id() != peer {
+            return Err(RecordError::KeyMismatch);
+        }
+
+        Ok(public)
+    }
+
+    /// Checks that the fields of the signed `data` match the rest of the record.
+    fn check_data(&self) -> Result<(), RecordError> {
+        let data = match DagCborCodec::decode(&self.entry.data)? {
+            Ipld::Map(map) => map,
+            _ => return Err(RecordError::DataMismatch),
+        };
+
+        let matches = data.len() == 5
+            && data.get("Value") == Some(&Ipld::Bytes(self.entry.value.clone()))
+            && data.get("Validity") == Some(&Ipld::Bytes(self.entry.validity.clone()))
+            && data.get("ValidityType") == Some(&Ipld::Integer(self.entry.validity_type.into()))
+            && data.get("Sequence") == Some(&Ipld::Integer(self.entry.sequence.into()))
+            && data.get("TTL") == Some(&Ipld::Integer(self.entry.ttl.into()));
+
+        if matches {
+            Ok(())
+        } else {
+            Err(RecordError::DataMismatch)
+        }
+    }
+
+    /// Orders the records so that the better record is greater: the one with the larger sequence
+    /// number, or with the later end of life when the sequence numbers are equal.
+    pub fn compare(&self, other: &IpnsRecord) -> Ordering {
+        self.sequence()
+            .cmp(&other.sequence())
+            .then_with(|| match (self.eol(), other.eol()) {
+                (Ok(a), Ok(b)) => a.cmp(&b),
+                (Ok(_), Err(_)) => Ordering::Greater,
+                (Err(_), Ok(_)) => Ordering::Less,
+                (Err(_), Err(_)) => Ordering::Equal,
+            })
+    }
+}
+
+/// The key under which the records of the peer are stored in the DHT: `/ipns/` followed by the
+/// binary peer id.
+pub fn record_key(peer: &PeerId) -> Vec<u8> {
+    let mut key = b"/ipns/".to_vec();
+    key.extend_from_slice(&peer.to_bytes());
+    key
+}
+
+/// Picks the best of the records of the `peer` which are valid: the one with the largest sequence
+/// number, or the one valid for the longest of those.
+pub fn select_best<'a, I>(peer: &PeerId, records: I) -> Option<&'a IpnsRecord>
+where
+    I: IntoIterator<Item = &'a IpnsRecord>,
+{
+    let now = SystemTime::now();
+
+    records
+        .into_iter()
+        .filter(|record| record.validate_at(peer, now).is_ok())
+        .max_by(|a, b| a.compare(b))
+}
+
+/// The bytes signed by the V1 signature: the value, the validity and the name of the validity
+/// type concatenated.
+fn signed_data_v1(entry: &IpnsEntry) -> Vec<u8> {
+    let mut data = Vec::with_capacity(entry.value.len() + entry.validity.len() + 3);
+    data.extend_from_slice(&entry.value);
+    data.extend_from_slice(&entry.validity);
+    if entry.validity_type == ValidityType::Eol as i32 {
+        data.extend_from_slice(b"EOL");
+    }
+    data
+}
+
+fn signed_data_v2(entry: &IpnsEntry) -> Vec<u8> {
+    let mut data = Vec::with_capacity(SIGNATURE_V2_PREFIX.len() + entry.data.len());
+    data.extend_from_slice(SIGNATURE_V2_PREFIX);
+    data.extend_from_slice(&entry.data);
+    data
+}
+
+/// Encodes the fields of the record as a dag-cbor map for the V2 signature. The map is written by
+/// hand, as dag-cbor orders the keys by length first, unlike the `BTreeMap` of [`Ipld::Map`].
+fn encode_data(entry: &IpnsEntry) -> Result<Vec<u8>, CborError> {
+    let fields = [
+        ("TTL", Ipld::Integer(entry.ttl.into())),
+        ("Value", Ipld::Bytes(entry.value.clone())),
+        ("Sequence", Ipld::Integer(entry.sequence.into())),
+        ("Validity", Ipld::Bytes(entry.validity.clone())),
+        ("ValidityType", Ipld::Integer(entry.validity_type.into())),
+    ];
+
+    let mut data = Vec::new();
+    write_u64(&mut data, 5, fields.len() as u64)?;
+    for (key, value) in fields.iter() {
+        key.write_cbor(&mut data)?;
+        value.write_cbor(&mut data)?;
+    }
+    Ok(data)
+}
+
+/// Returns the public key inlined into the peer id with the identity multihash, which is done for
+/// keys of up to 42 bytes such as ed25519 and secp256k1 keys.
+fn extract_public_key(peer: &PeerId) -> Option<PublicKey> {
+    let bytes = peer.to_bytes();
+
+    match bytes.as_slice() {
+        // identity multihash code, and the single byte varint length of at most 42
+        [0x00, len, key @ ..] if *len as usize == key.len() => {
+            PublicKey::from_protobuf_encoding(key).ok()
+        }
+        _ => None,
+    }
+}
+
+/// Formats the time as in go-ipfs: RFC 3339 in UTC with nanoseconds.
+fn format_rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs() as i64;
+
+    let (year, month, day) = civil_from_days(secs.div_euclid(86_400));
+    let secs_of_day = secs.rem_euclid(86_400);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:09}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_nanos()
+    )
+}
+
+/// Parses an RFC 3339 time, with optional fractional seconds and either `Z` or a numeric offset.
+/// Times before the unix epoch are not supported.
+fn parse_rfc3339(s: &str) -> Option<SystemTime> {
+    fn number(s: &str) -> Option<i64> {
+        if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        s.parse().ok()
+    }
+
+    if s.len() < 20 || !s.is_ascii() {
+        return None;
+    }
+
+    let (date, rest) = s.split_at(10);
+    let (sep, rest) = rest.split_at(1);
+
+    if !sep.eq_ignore_ascii_case("t") || &date[4..5] != "-" || &date[7..8] != "-" {
+        return None;
+    }
+
+    let year = number(&date[0..4])?;
+    let month = number(&date[5..7])?;
+    let day = number(&date[8..10])?;
+
+    let (time, rest) = rest.split_at(8);
+    if &time[2..3] != ":" || &time[5..6] != ":" {
+        return None;
+    }
+
+    let hour = number(&time[0..2])?;
+    let minute = number(&time[3..5])?;
+    let second = number(&time[6..8])?;
+
+    let (nanos, offset) = match rest.strip_prefix('.') {
+        Some(rest) => {
+            let end = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            let (fraction, offset) = rest.split_at(end);
+            if fraction.is_empty() || fraction.len() > 9 {
+                return None;
+            }
+            let nanos = number(fraction)? * 10i64.pow(9 - fraction.len() as u32);
+            (nanos, offset)
+        }
+        None => (0, rest),
+    };
+
+    let offset_secs = if offset.eq_ignore_ascii_case("z") {
+        0
+    } else if offset.len() == 6 && &offset[3..4] == ":" {
+        let sign = match &offset[0..1] {
+            "+" => 1,
+            "-" => -1,
+            _ => return None,
+        };
+        sign * (number(&offset[1..3])? * 3600 + number(&offset[4..6])? * 60)
+    } else {
+        return None;
+    };
+
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+
+    let secs = days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second
+        - offset_secs;
+
+    if secs < 0 {
+        return None;
+    }
+
+    Some(UNIX_EPOCH + Duration::new(secs as u64, nanos as u32))
+}
+
+/// Days since the unix epoch of the proleptic Gregorian date.
+///
+/// See <http://howardhinnant.github.io/date_algorithms.html#days_from_civil>.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// The proleptic Gregorian date of the days since the unix epoch.
+///
+/// See <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    fn record(keypair: &Keypair, sequence: u64, valid_for: Duration) -> IpnsRecord {
+        IpnsRecord::create(
+            keypair,
+            b"/ipfs/bafkqaaa",
+            sequence,
+            SystemTime::now() + valid_for,
+            HOUR,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn rfc3339_round_trip() {
+        let time = UNIX_EPOCH + Duration::new(1_609_459_200, 123_456_789);
+        let formatted = format_rfc3339(time);
+
+        assert_eq!(formatted, "2021-01-01T00:00:00.123456789Z");
+        assert_eq!(parse_rfc3339(&formatted), Some(time));
+
+        assert_eq!(
+            parse_rfc3339("2020-02-29T23:59:60+01:00"),
+            Some(UNIX_EPOCH + Duration::from_secs(1_583_017_200))
+        );
+        assert_eq!(
+            parse_rfc3339("1970-01-01T00:00:00.5Z"),
+            Some(UNIX_EPOCH + Duration::from_millis(500))
+        );
+        assert_eq!(parse_rfc3339("2021-01-01 00:00:00Z"), None);
+        assert_eq!(parse_rfc3339("2021-13-01T00:00:00Z"), None);
+    }
+
+    #[test]
+    fn created_record_is_valid() {
+        let keypair = Keypair::generate_ed25519();
+        let peer = keypair.public().into_peer_id();
+
+        let record = record(&keypair, 1, HOUR);
+        let record = IpnsRecord::from_bytes(&record.to_bytes()).unwrap();
+
+        record.validate(&peer).unwrap();
+        assert_eq!(record.value(), b"/ipfs/bafkqaaa");
+        assert_eq!(record.sequence(), 1);
+        assert_eq!(record.ttl(), HOUR);
+
+        // the ed25519 key is in the peer id
+        assert!(record.entry.pub_key.is_empty());
+    }
+
+    #[test]
+    fn record_data_is_canonical_dag_cbor() {
+        let keypair = Keypair::generate_ed25519();
+        let record = IpnsRecord::create(
+            &keypair,
+            b"/ipfs/bafkqaaa",
+            1,
+            UNIX_EPOCH + Duration::from_secs(1_609_459_200),
+            Duration::from_nanos(1),
+        )
+        .unwrap();
+
+        let mut expected = vec![0xa5];
+        expected.extend_from_slice(b"\x63TTL\x01");
+        expected.extend_from_slice(b"\x65Value\x4e/ipfs/bafkqaaa");
+        expected.extend_from_slice(b"\x68Sequence\x01");
+        expected.extend_from_slice(b"\x68Validity\x58\x1e2021-01-01T00:00:00.000000000Z");
+        expected.extend_from_slice(b"\x6cValidityType\x00");
+
+        assert_eq!(record.entry.data, expected);
+    }
+
+    #[test]
+    fn record_of_another_peer_is_rejected() {
+        let keypair = Keypair::generate_ed25519();
+        let other = Keypair::generate_ed25519().public().into_peer_id();
+
+        assert!(matches!(
+            record(&keypair, 1, HOUR).validate(&other),
+            Err(RecordError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn modified_record_is_rejected() {
+        let keypair = Keypair::generate_ed25519();
+        let peer = keypair.public().into_peer_id();
+
+        let mut modified = record(&keypair, 1, HOUR);
+        modified.entry.value = b"/ipfs/bafkqaab".to_vec();
+        assert!(matches!(
+            modified.validate(&peer),
+            Err(RecordError::DataMismatch)
+        ));
+
+        let mut modified = record(&keypair, 1, HOUR);
+        modified.entry.data = encode_data(&IpnsEntry {
+            sequence: 2,
+            ..modified.entry.clone()
+        })
+        .unwrap();
+        assert!(matches!(
+            modified.validate(&peer),
+            Err(RecordError::InvalidSignature)
+        ));
+
+        // without the V2 signature the V1 one is checked
+        let mut modified = record(&keypair, 1, HOUR);
+        modified.entry.signature_v2.clear();
+        modified.validate(&peer).unwrap();
+        modified.entry.validity = format_rfc3339(SystemTime::now() + 2 * HOUR).into_bytes();
+        assert!(matches!(
+            modified.validate(&peer),
+            Err(RecordError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn expired_record_is_rejected() {
+        let keypair = Keypair::generate_ed25519();
+        let peer = keypair.public().into_peer_id();
+
+        let record = record(&keypair, 1, HOUR);
+
+        assert!(matches!(
+            record.validate_at(&peer, SystemTime::now() + 2 * HOUR),
+            Err(RecordError::Expired)
+        ));
+    }
+
+    #[test]
+    fn best_record_is_selected() {
+        let keypair = Keypair::generate_ed25519();
+        let peer = keypair.public().into_peer_id();
+
+        let old = record(&keypair, 1, 10 * HOUR);
+        let new = record(&keypair, 2, HOUR);
+        let longer = record(&keypair, 2, 2 * HOUR);
+        let mut invalid = record(&keypair, 3, HOUR);
+        invalid.entry.signature_v2[0] ^= 1;
+
+        let records = vec![old.clone(), new.clone(), longer.clone(), invalid];
+
+        assert_eq!(select_best(&peer, &records), Some(&longer));
+        assert_eq!(select_best(&peer, &[old.clone(), new.clone()]), Some(&new));
+        assert_eq!(select_best(&peer, &[old.clone()]), Some(&old));
+        assert_eq!(select_best(&peer, &[]), None);
+    }
+}
@@ -22,6 +22,7 @@
 // the docs better.
 //#![allow(private_intra_doc_links)]
 
+pub mod car;
 pub mod config;
 pub mod dag;
 pub mod error;
@@ -29,12 +30,15 @@ pub mod error;
 pub mod ipld;
 pub mod cli;
 pub mod ipns;
+pub mod keystore;
 pub mod p2p;
 pub mod path;
 pub mod refs;
 pub mod repo;
+pub mod reprovider;
 pub mod unixfs;
 
+mod bootstrap;
 mod exchange;
 
 #[macro_use]
@@ -42,6 +46,7 @@ extern crate tracing;
 
 use anyhow::anyhow;
 use cid::Codec;
+use futures::future::Future;
 use futures::stream::Stream;
 use tracing::Span;
 use tracing_futures::Instrument;
@@ -53,14 +58,18 @@ use std::{
     env, fmt,
     ops::{Deref, DerefMut, Range},
     path::PathBuf,
-    sync::atomic::Ordering,
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
 };
 
 use self::{
+    bootstrap::Bootstrappers,
     dag::IpldDag,
-    ipns::Ipns,
-    p2p::SwarmOptions,
+    ipns::{dnslink::DnsLinkResolver, pubsub::IpnsPubsub, Ipns, RecordCache},
+    p2p::{dnsaddr, pubsub::Subscriptions, SwarmOptions},
+    path::PathRoot,
     repo::{Repo, RepoOptions},
+    reprovider::Reprovider,
 };
 
 use libp2p_rs::floodsub::Topic;
@@ -76,15 +85,19 @@ use crate::repo::BlockPut;
 pub use self::{
     error::Error,
     ipld::Ipld,
+    ipns::{dnslink::DnsLinkOptions, IpnsResolveOptions},
+    keystore::{KeyFormat, KeyInfo, KeyType, Keystore},
     p2p::{
-        pubsub::PubsubMessage, pubsub::SubscriptionStream, Connection, MultiaddrWithPeerId,
-        MultiaddrWithoutPeerId,
+        pubsub::PubsubMessage, pubsub::SubscriptionStream, pubsub::ValidationResult, Connection,
+        MultiaddrWithPeerId, MultiaddrWithoutPeerId,
     },
     path::IpfsPath,
     repo::{PinKind, PinMode, RepoTypes},
+    reprovider::{ReprovideStats, ReprovideStrategy, ReproviderOptions},
 };
 pub use bitswap::Block;
 pub use bitswap::BsBlockStore;
+pub use bitswap::Session as BitswapSession;
 pub use cid::Cid;
 
 pub use libp2p_rs::{
@@ -113,6 +126,19 @@ impl RepoTypes for Types {
     type TLock = repo::fs::FsLock;
 }
 
+/// Persistent node configuration with both the block store and the data store backed by [`sled`]
+/// databases.
+///
+/// [`sled`]: https://github.com/spacejam/sled
+#[derive(Debug)]
+pub struct KvTypes;
+
+impl RepoTypes for KvTypes {
+    type TBlockStore = repo::kv::KvBlockStore;
+    type TDataStore = repo::kv::KvDataStore;
+    type TLock = repo::fs::FsLock;
+}
+
 /// In-memory testing configuration used in tests.
 #[derive(Debug)]
 pub struct TestTypes;
@@ -140,9 +166,13 @@ pub struct IpfsOptions {
     /// The keypair used with libp2p, the identity of the node.
     pub keypair: Keypair,
 
-    /// Nodes used as bootstrap peers.
+    /// Nodes used as bootstrap peers; the initial list of [`Ipfs::get_bootstrappers`].
     pub bootstrap: Vec<(PeerId, Multiaddr)>,
 
+    /// How often the connected peers are checked, bootstrapping again while only a few are
+    /// connected. `None` disables the periodic bootstrap.
+    pub bootstrap_interval: Option<Duration>,
+
     /// Enables mdns for peer discovery and announcement when true.
     pub mdns: bool,
 
@@ -157,6 +187,16 @@ pub struct IpfsOptions {
     /// Bound listening addresses; by default the node will not listen on any address.
     pub listening_addrs: Vec<Multiaddr>,
 
+    /// Broadcasts the published IPNS records over pubsub and subscribes to the resolved names to
+    /// receive their updates without DHT lookups, see [`Ipfs::ipns_pubsub_subscriptions`].
+    pub ipns_pubsub: bool,
+
+    /// The nameservers, timeouts, caching and recursion limits of DNSLink resolution.
+    pub dnslink: DnsLinkOptions,
+
+    /// Which blocks are announced to the DHT and how often, see [`Ipfs::reprovide`].
+    pub reprovider: ReproviderOptions,
+
     /// The span for tracing purposes, `None` value is converted to `tracing::trace_span!("ipfs")`.
     ///
     /// All futures returned by `Ipfs`, background task actions and swarm actions are instrumented
@@ -172,10 +212,14 @@ impl fmt::Debug for IpfsOptions {
         fmt.debug_struct("IpfsOptions")
             .field("ipfs_path", &self.ipfs_path)
             .field("bootstrap", &self.bootstrap)
+            .field("bootstrap_interval", &self.bootstrap_interval)
             .field("keypair", &DebuggableKeypair(&self.keypair))
             .field("mdns", &self.mdns)
             .field("kad_protocol", &self.kad_protocol)
             .field("listening_addrs", &self.listening_addrs)
+            .field("ipns_pubsub", &self.ipns_pubsub)
+            .field("dnslink", &self.dnslink)
+            .field("reprovider", &self.reprovider)
             .field("span", &self.span)
             .finish()
     }
@@ -191,9 +235,17 @@ impl IpfsOptions {
             keypair: Keypair::generate_ed25519(),
             mdns: Default::default(),
             bootstrap: Default::default(),
+            bootstrap_interval: None,
             // default to lan kad for go-ipfs use in tests
             kad_protocol: Some("/ipfs/kad/1.0.0".to_owned()),
             listening_addrs: vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()],
+            ipns_pubsub: false,
+            dnslink: Default::default(),
+            // only reprovide when asked to in tests
+            reprovider: ReproviderOptions {
+                interval: None,
+                ..Default::default()
+            },
             span: None,
         }
     }
@@ -208,9 +260,13 @@ impl IpfsOptions {
                     keypair: Keypair::generate_ed25519(),
                     mdns: Default::default(),
                     bootstrap: Default::default(),
+                    bootstrap_interval: None,
                     // default to lan kad for go-ipfs use in tests
                     kad_protocol: Some("/ipfs/lan/kad/1.0.0".to_owned()),
                     listening_addrs: vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()],
+                    ipns_pubsub: false,
+                    dnslink: Default::default(),
+                    reprovider: Default::default(),
                     span: None,
                 }
             }
@@ -254,6 +310,13 @@ pub struct Ipfs<Types: IpfsTypes> {
     repo: Repo<Types>,
     keys: DebuggableKeypair<Keypair>,
     controls: Controls,
+    reprovider: Reprovider,
+    keystore: Keystore,
+    ipns_pubsub: Option<IpnsPubsub>,
+    ipns_cache: RecordCache,
+    dnslink: DnsLinkResolver,
+    bootstrappers: Bootstrappers,
+    pubsub_subscriptions: Subscriptions,
 }
 
 impl<Types: IpfsTypes> Clone for Ipfs<Types> {
@@ -263,6 +326,13 @@ impl<Types: IpfsTypes> Clone for Ipfs<Types> {
             repo: self.repo.clone(),
             keys: self.keys.clone(),
             controls: self.controls.clone(),
+            reprovider: self.reprovider.clone(),
+            keystore: self.keystore.clone(),
+            ipns_pubsub: self.ipns_pubsub.clone(),
+            ipns_cache: self.ipns_cache.clone(),
+            dnslink: self.dnslink.clone(),
+            bootstrappers: self.bootstrappers.clone(),
+            pubsub_subscriptions: self.pubsub_subscriptions.clone(),
         }
     }
 }
@@ -320,18 +390,65 @@ impl<Types: IpfsTypes> UninitializedIpfs<Types> {
 
         repo.init().instrument(init_span.clone()).await?;
 
+        let dnslink = DnsLinkResolver::new(options.dnslink.clone())?;
+
+        // the state other than the blocks and pins is kept next to a repo kept on disk
+        let persistent_path = |name: &str| {
+            if Types::PERSISTENT {
+                Some(options.ipfs_path.join(name))
+            } else {
+                None
+            }
+        };
+
+        let bootstrappers =
+            Bootstrappers::load(&options.bootstrap, persistent_path(config::BOOTSTRAP_FILE))
+                .instrument(init_span.clone())
+                .await?;
+
         // FIXME: mutating options above is an unfortunate side-effect of this call, which could be
         // reordered for less error prone code.
-        let swarm_options = SwarmOptions::from(&options);
-        let controls = Controls::build(repo.clone(), swarm_options)
+        let mut swarm_options = SwarmOptions::from(&options);
+        swarm_options.bootstrap = bootstrap::into_pairs(bootstrappers.list().await);
+        swarm_options.dht_store_path = persistent_path("dht");
+        let controls = Controls::build(repo.clone(), swarm_options, &dnslink)
             .instrument(tracing::trace_span!(parent: &init_span, "swarm"))
             .await;
 
+        let reprovider = Reprovider::start(
+            repo.clone(),
+            controls.kad(),
+            options.reprovider.clone(),
+            tracing::trace_span!(parent: &root_span, "reprovider"),
+        );
+
+        if let Some(interval) = options.bootstrap_interval {
+            bootstrappers.start(
+                controls.clone(),
+                dnslink.clone(),
+                interval,
+                tracing::trace_span!(parent: &root_span, "bootstrap"),
+            );
+        }
+
+        let keystore = Keystore::new(persistent_path("keystore"));
+
         let ipfs = Ipfs {
             span: facade_span,
             repo,
             keys: DebuggableKeypair(keys),
             controls,
+            reprovider,
+            keystore,
+            ipns_pubsub: if options.ipns_pubsub {
+                Some(IpnsPubsub::default())
+            } else {
+                None
+            },
+            ipns_cache: Default::default(),
+            dnslink,
+            bootstrappers,
+            pubsub_subscriptions: Default::default(),
         };
 
         Ok(ipfs)
@@ -393,6 +510,32 @@ impl<Types: IpfsTypes> Ipfs<Types> {
         }
     }
 
+    /// Creates a new bitswap session, which should be used to fetch the blocks of a single DAG
+    /// with [`Ipfs::get_block_in_session`].
+    pub fn new_bitswap_session(&self) -> BitswapSession {
+        self.controls.bitswap().new_session()
+    }
+
+    /// Retrieves a block like [`Ipfs::get_block`], but fetches a missing block within the
+    /// session: the block is first asked from the peers which have provided the earlier blocks of
+    /// the session.
+    pub async fn get_block_in_session(
+        &self,
+        cid: &Cid,
+        session: &BitswapSession,
+    ) -> Result<Block, Error> {
+        if let Some(block) = self
+            .repo
+            .get_block(cid)
+            .instrument(self.span.clone())
+            .await?
+        {
+            Ok(block)
+        } else {
+            session.want_block(cid.clone()).await.map_err(Error::from)
+        }
+    }
+
     pub async fn put_block_now(&self, block: Block) -> Result<Cid, Error> {
         let (cid, _res) = self
             .repo
@@ -415,6 +558,84 @@ impl<Types: IpfsTypes> Ipfs<Types> {
             .await
     }
 
+    /// Removes all blocks which are not pinned from the ipfs repo, yielding the `Cid`s of the
+    /// removed blocks as they are removed.
+    ///
+    /// Putting and pinning wait for a running collection to complete, and a collection waits for
+    /// the holders of a [`Ipfs::gc_permit`] to release it. See [`Repo::gc`] for more information.
+    pub fn gc(&self) -> impl Stream<Item = Result<Cid, Error>> + Send + '_ {
+        let span = debug_span!(parent: &self.span, "gc");
+        self.repo.gc().instrument(span)
+    }
+
+    /// Keeps [`Ipfs::gc`] from starting until the returned permit is dropped. Hold one over
+    /// putting blocks and pinning them, as otherwise the blocks can be collected in between.
+    /// See [`Repo::gc_permit`] for more information.
+    pub async fn gc_permit(&self) -> tokio::sync::SemaphorePermit<'_> {
+        self.repo.gc_permit().await
+    }
+
+    /// Writes the DAG under `root` as a CARv1 archive to the `writer`, fetching any missing blocks.
+    /// See [`car::export`] for a streaming version.
+    pub async fn export_car<W>(&self, root: Cid, mut writer: W) -> Result<(), Error>
+    where
+        W: futures::io::AsyncWrite + Unpin,
+    {
+        use futures::io::AsyncWriteExt;
+        use futures::stream::TryStreamExt;
+
+        let span = debug_span!(parent: &self.span, "export_car", root = %root);
+
+        async move {
+            let sections = car::export(self, root);
+            futures::pin_mut!(sections);
+
+            while let Some(section) = sections.try_next().await? {
+                writer.write_all(&section).await?;
+            }
+
+            writer.flush().await?;
+            Ok(())
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Reads a CARv1 or CARv2 archive from the `reader`, storing all of the blocks after verifying
+    /// them against their `Cid`s. Returns the roots of the archive, which are pinned recursively
+    /// if `pin_roots` is true.
+    ///
+    /// The roots are only pinned after all of the blocks have been read, and pinning a root which
+    /// was not included in the archive will attempt to fetch it.
+    pub async fn import_car<R>(&self, reader: R, pin_roots: bool) -> Result<Vec<Cid>, Error>
+    where
+        R: futures::io::AsyncRead + Unpin,
+    {
+        let span = debug_span!(parent: &self.span, "import_car", pin_roots);
+
+        async move {
+            // the blocks are kept until the roots have been pinned
+            let _permit = self.repo.gc_permit().await;
+            let mut reader = car::CarReader::new(reader).await?;
+
+            while let Some(block) = reader.next_block().await? {
+                self.put_block(block).await?;
+            }
+
+            let roots = reader.header().roots.clone();
+
+            if pin_roots {
+                for root in &roots {
+                    self.insert_pin(root, true).await?;
+                }
+            }
+
+            Ok(roots)
+        }
+        .instrument(span)
+        .await
+    }
+
     /// Pins a given Cid recursively or directly (non-recursively).
     ///
     /// Pins on a block are additive in sense that a previously directly (non-recursively) pinned
@@ -437,6 +658,8 @@ impl<Types: IpfsTypes> Ipfs<Types> {
         use futures::stream::{StreamExt, TryStreamExt};
         let span = debug_span!(parent: &self.span, "insert_pin", cid = %cid, recursive);
         let refs_span = debug_span!(parent: &span, "insert_pin refs");
+        // the blocks fetched for the pin are kept until it has been written
+        let _permit = self.repo.gc_permit().await;
 
         async move {
             // this needs to download everything but /pin/ls does not
@@ -471,6 +694,7 @@ impl<Types: IpfsTypes> Ipfs<Types> {
     pub async fn remove_pin(&self, cid: &Cid, recursive: bool) -> Result<(), Error> {
         use futures::stream::{StreamExt, TryStreamExt};
         let span = debug_span!(parent: &self.span, "remove_pin", cid = %cid, recursive);
+        let _permit = self.repo.gc_permit().await;
         async move {
             if !recursive {
                 self.repo.remove_direct_pin(cid).await
@@ -555,7 +779,8 @@ impl<Types: IpfsTypes> Ipfs<Types> {
 
     /// Puts an ipld node into the ipfs repo using `dag-cbor` codec and Sha2_256 hash.
     ///
-    /// Returns Cid version 1 for the document
+    /// Returns Cid version 1 for the document. Hold a [`Ipfs::gc_permit`] until the document has
+    /// been pinned to keep [`Ipfs::gc`] from removing it in between.
     pub async fn put_dag(&self, ipld: Ipld) -> Result<Cid, Error> {
         self.dag()
             .put(ipld, Codec::DagCBOR)
@@ -594,48 +819,169 @@ impl<Types: IpfsTypes> Ipfs<Types> {
             .await
     }
 
-    /// Resolves a ipns path to an ipld path; currently only supports dnslink resolution.
+    /// Resolves a ipns path to an ipld path, either through the IPNS records of a peer or
+    /// dnslink. With `recursive` the resolved paths are resolved again until an ipld path is
+    /// reached, following at most [`DnsLinkOptions::max_depth`] dnslink records. Resolving the
+    /// same name twice on the way is an error.
+    ///
+    /// The records stored earlier are used within their TTL; see [`Ipfs::resolve_ipns_with`] for
+    /// looking the records up from the DHT regardless.
     pub async fn resolve_ipns(&self, path: &IpfsPath, recursive: bool) -> Result<IpfsPath, Error> {
+        self.resolve_ipns_with(path, recursive, IpnsResolveOptions::default())
+            .await
+    }
+
+    /// Resolves a ipns path like [`Ipfs::resolve_ipns`], looking the IPNS records up as
+    /// configured by the `options`.
+    pub async fn resolve_ipns_with(
+        &self,
+        path: &IpfsPath,
+        recursive: bool,
+        options: IpnsResolveOptions,
+    ) -> Result<IpfsPath, Error> {
         async move {
             let ipns = self.ipns();
-            let mut resolved = ipns.resolve(path).await;
+            let mut resolved = ipns.resolve(path, &options).await?;
 
             if recursive {
                 let mut seen = HashSet::with_capacity(1);
-                while let Ok(ref res) = resolved {
-                    if !seen.insert(res.clone()) {
-                        break;
+                seen.insert(path.root().clone());
+                let mut dnslinks = matches!(path.root(), PathRoot::Dns(_)) as usize;
+
+                while !matches!(resolved.root(), PathRoot::Ipld(_)) {
+                    if !seen.insert(resolved.root().clone()) {
+                        return Err(anyhow!(
+                            "cycle in resolving {}: {} was already resolved",
+                            path,
+                            resolved.root()
+                        ));
+                    }
+                    if let PathRoot::Dns(_) = resolved.root() {
+                        dnslinks += 1;
+                        if dnslinks > self.dnslink.max_depth() {
+                            return Err(anyhow!(
+                                "dnslink recursion limit of {} exceeded",
+                                self.dnslink.max_depth()
+                            ));
+                        }
                     }
-                    resolved = ipns.resolve(&res).await;
+                    resolved = ipns.resolve(&resolved, &options).await?;
                 }
+            }
 
-                resolved
+            Ok(resolved)
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Publishes an IPNS record pointing to the `path` under the node's peer id, valid for the
+    /// `lifetime`. The record is signed with the node's keypair, stored in the repo and put into
+    /// the DHT; it is stored in the repo even if putting it into the DHT fails.
+    ///
+    /// Returns the published name, `/ipns/<peer-id>`.
+    pub async fn publish_ipns(
+        &self,
+        path: &IpfsPath,
+        lifetime: Duration,
+    ) -> Result<IpfsPath, Error> {
+        self.ipns()
+            .publish(self.keys.get_ref(), path, lifetime)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Publishes an IPNS record like [`Ipfs::publish_ipns`] but under the peer id of the named
+    /// key from the [`Keystore`], or the node's own key when the name is `self`.
+    ///
+    /// Returns the published name, `/ipns/<peer-id of the key>`.
+    pub async fn publish_ipns_with_key(
+        &self,
+        key: &str,
+        path: &IpfsPath,
+        lifetime: Duration,
+    ) -> Result<IpfsPath, Error> {
+        async move {
+            if key == keystore::SELF_KEY {
+                self.ipns()
+                    .publish(self.keys.get_ref(), path, lifetime)
+                    .await
             } else {
-                resolved
+                let keypair = self.keystore.get(key).await?;
+                self.ipns().publish(&keypair, path, lifetime).await
             }
         }
         .instrument(self.span.clone())
         .await
     }
 
-    /// Bootstraps the Kad-DHT.
-    ///
-    /// Assumes the bootstrap nodes have been added to the routing table already.
-    /// Check [SwarmOptions::bootstrap] for details.
+    /// Returns true if IPNS over pubsub is enabled with [`IpfsOptions::ipns_pubsub`].
+    pub fn ipns_pubsub_enabled(&self) -> bool {
+        self.ipns_pubsub.is_some()
+    }
+
+    /// Returns the names subscribed to with IPNS over pubsub, which are the names resolved since
+    /// the node was started and not cancelled.
+    pub fn ipns_pubsub_subscriptions(&self) -> Vec<PeerId> {
+        self.ipns_pubsub
+            .as_ref()
+            .map(IpnsPubsub::subscriptions)
+            .unwrap_or_default()
+    }
+
+    /// Cancels the IPNS over pubsub subscription to the name. Returns false if the name was not
+    /// subscribed to.
+    pub fn ipns_pubsub_cancel(&self, name: &PeerId) -> bool {
+        self.ipns_pubsub
+            .as_ref()
+            .map(|pubsub| pubsub.cancel(name))
+            .unwrap_or(false)
+    }
+
+    /// Returns the keystore holding the named keys of the node.
+    pub fn keystore(&self) -> &Keystore {
+        &self.keystore
+    }
+
+    /// Lists the node's own key as `self` followed by the named keys of the [`Keystore`].
+    pub async fn list_keys(&self) -> Result<Vec<KeyInfo>, Error> {
+        let own = KeyInfo {
+            name: keystore::SELF_KEY.to_owned(),
+            id: self.keys.get_ref().public().into_peer_id(),
+        };
+
+        let mut keys = vec![own];
+        keys.extend(self.keystore.list().instrument(self.span.clone()).await?);
+        Ok(keys)
+    }
+
+    /// Bootstraps the Kad-DHT with the current list of bootstrap nodes, see
+    /// [`Ipfs::get_bootstrappers`].
     pub async fn bootstrap(&self) {
-        self.controls.kad().bootstrap(vec![]).await;
+        async move {
+            let bootstrap = bootstrap::into_pairs(self.bootstrappers.list().await);
+            let bootstrap = dnsaddr::resolve_bootstrap(&self.dnslink, bootstrap).await;
+            self.controls.kad().bootstrap(bootstrap).await;
+        }
+        .instrument(self.span.clone())
+        .await
     }
 
     /// Connects to the peer at the given Multiaddress.
     ///
-    /// Accepts only multiaddresses with the PeerId to authenticate the connection.
+    /// Accepts only multiaddresses with the PeerId to authenticate the connection. A `/dnsaddr`
+    /// multiaddress is resolved to the addresses of the peer in its TXT records.
     ///
     /// Returns a future which will complete when the connection has been successfully made or
     /// failed for whatever reason.
     pub async fn connect(&self, target: MultiaddrWithPeerId) -> Result<(), Error> {
+        let addrs = dnsaddr::resolve_peer(&self.dnslink, &target.peer_id, target.multiaddr.into())
+            .instrument(self.span.clone())
+            .await?;
+
         self.controls
             .swarm()
-            .connect_with_addrs(target.peer_id, vec![target.multiaddr.into()])
+            .connect_with_addrs(target.peer_id, addrs)
             .instrument(self.span.clone())
             .await
             .map_err(Error::from)
@@ -717,17 +1063,14 @@ impl<Types: IpfsTypes> Ipfs<Types> {
         Ok((ii.public_key, ii.listen_addrs))
     }
 
-    /// Subscribes to a given topic. Can be done at most once without unsubscribing in the between.
-    /// The subscription can be unsubscribed by dropping the stream or calling
-    /// [`Ipfs::pubsub_unsubscribe`].
+    /// Subscribes to a given topic. The topic can be subscribed to any number of times, with each
+    /// stream receiving all of the messages. The topic is unsubscribed from once all of its
+    /// streams have been dropped or [`Ipfs::pubsub_unsubscribe`] is called.
     pub async fn pubsub_subscribe(&self, topic: String) -> Result<SubscriptionStream, Error> {
-        self.controls
-            .pubsub()
-            .subscribe(Topic::new(topic))
+        self.pubsub_subscriptions
+            .subscribe(self.controls.pubsub(), topic)
             .instrument(self.span.clone())
             .await
-            .map(SubscriptionStream::from)
-            .map_err(Error::from)
     }
 
     /// Publishes to the topic which may have been subscribed to earlier
@@ -740,12 +1083,31 @@ impl<Types: IpfsTypes> Ipfs<Types> {
             .map_err(Error::from)
     }
 
-    /// Forcibly unsubscribes a previously made [`Subscription`], which could also be
-    /// unsubscribed by dropping the [`Subscription`].
+    /// Forcibly unsubscribes from the topic, ending all of its [`SubscriptionStream`]s. The topic
+    /// is also unsubscribed from when all of its streams have been dropped.
+    ///
+    /// Returns true if the topic was subscribed to.
+    pub async fn pubsub_unsubscribe(&self, topic: &str) -> Result<bool, Error> {
+        Ok(self.pubsub_subscriptions.unsubscribe(topic))
+    }
+
+    /// Sets the async validator of the messages received on the topic, replacing any previous
+    /// one. Only the messages the validator accepts are delivered to the [`SubscriptionStream`]s
+    /// of the topic; duplicate messages are dropped before validation.
     ///
-    /// Returns true if unsubscription was successful
-    pub async fn pubsub_unsubscribe(&self, _topic: &str) -> Result<bool, Error> {
-        Err(anyhow!("not implemented"))
+    /// The validator only decides the local delivery: the floodsub router of libp2p-rs relays
+    /// the messages to the other peers before they are validated, and has no hook to stop it.
+    pub fn pubsub_set_validator<F, Fut>(&self, topic: String, validator: F)
+    where
+        F: Fn(Arc<PubsubMessage>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ValidationResult> + Send + 'static,
+    {
+        self.pubsub_subscriptions.set_validator(topic, validator)
+    }
+
+    /// Removes the validator of the topic. Returns false if the topic had no validator.
+    pub fn pubsub_remove_validator(&self, topic: &str) -> bool {
+        self.pubsub_subscriptions.remove_validator(topic)
     }
 
     /// Returns all known pubsub peers with the optional topic filter
@@ -766,17 +1128,7 @@ impl<Types: IpfsTypes> Ipfs<Types> {
 
     /// Returns all currently subscribed topics
     pub async fn pubsub_subscribed(&self) -> Result<Vec<String>, Error> {
-        let topics = self
-            .controls
-            .pubsub()
-            .ls()
-            .instrument(self.span.clone())
-            .await
-            .map_err(Error::from)?;
-
-        let r = topics.into_iter().map(String::from).collect::<Vec<_>>();
-
-        Ok(r)
+        Ok(self.pubsub_subscriptions.topics())
     }
 
     /// Returns a list of local blocks
@@ -800,6 +1152,17 @@ impl<Types: IpfsTypes> Ipfs<Types> {
             .map_err(Error::from)
     }
 
+    /// Returns the accounting of the blocks exchanged with the given `peer`, which decides the
+    /// order in which the peers are sent blocks they want.
+    pub async fn bitswap_ledger(&self, peer: PeerId) -> Result<bitswap::LedgerInfo, Error> {
+        self.controls
+            .bitswap()
+            .ledger(peer)
+            .instrument(self.span.clone())
+            .await
+            .map_err(Error::from)
+    }
+
     /// Returns the statisctics of bitswap.
     pub async fn bitswap_stats(&self) -> Result<BitswapStats, Error> {
         let stats = self
@@ -879,6 +1242,23 @@ impl<Types: IpfsTypes> Ipfs<Types> {
             .map_err(Error::from)
     }
 
+    /// Announces the local blocks to the DHT right away, as selected by the
+    /// [`ReprovideStrategy`] in [`IpfsOptions::reprovider`]. This is also done periodically in
+    /// the background when an interval has been configured.
+    ///
+    /// Returns the number of blocks announced by this run.
+    pub async fn reprovide(&self) -> Result<ReprovideStats, Error> {
+        self.reprovider
+            .reprovide()
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Returns the number of blocks announced by the reprovider since the node was started.
+    pub fn reprovider_stats(&self) -> ReprovideStats {
+        self.reprovider.stats()
+    }
+
     /// Returns a list of peers closest to the given `PeerId`, as suggested by the DHT. The
     /// node must have at least one known peer in its routing table in order for the query
     /// to return any values.
@@ -934,35 +1314,95 @@ impl<Types: IpfsTypes> Ipfs<Types> {
 
     /// Obtain the list of addresses of bootstrapper nodes that are currently used.
     pub async fn get_bootstrappers(&self) -> Result<Vec<Multiaddr>, Error> {
-        Err(anyhow!("not implemented"))
+        let list = self
+            .bootstrappers
+            .list()
+            .instrument(self.span.clone())
+            .await;
+        Ok(list.into_iter().map(Multiaddr::from).collect())
     }
 
     /// Extend the list of used bootstrapper nodes with an additional address.
     /// Return value cannot be used to determine if the `addr` was a new bootstrapper, subject to
     /// change.
-    pub async fn add_bootstrapper(&self, _addr: MultiaddrWithPeerId) -> Result<Multiaddr, Error> {
-        Err(anyhow!("not implemented"))
+    pub async fn add_bootstrapper(&self, addr: MultiaddrWithPeerId) -> Result<Multiaddr, Error> {
+        async move {
+            let added = self.bootstrappers.add(vec![addr.clone()]).await?;
+            self.add_bootstrap_nodes(added).await;
+            Ok(addr.into())
+        }
+        .instrument(self.span.clone())
+        .await
     }
 
     /// Remove an address from the currently used list of bootstrapper nodes.
     /// Return value cannot be used to determine if the `addr` was an actual bootstrapper, subject to
     /// change.
-    pub async fn remove_bootstrapper(
-        &self,
-        _addr: MultiaddrWithPeerId,
-    ) -> Result<Multiaddr, Error> {
-        Err(anyhow!("not implemented"))
+    pub async fn remove_bootstrapper(&self, addr: MultiaddrWithPeerId) -> Result<Multiaddr, Error> {
+        async move {
+            if self.bootstrappers.remove(&addr).await? {
+                self.remove_bootstrap_nodes(vec![addr.clone()]).await;
+            }
+            Ok(addr.into())
+        }
+        .instrument(self.span.clone())
+        .await
     }
 
     /// Clear the currently used list of bootstrapper nodes, returning the removed addresses.
     pub async fn clear_bootstrappers(&self) -> Result<Vec<Multiaddr>, Error> {
-        Err(anyhow!("not implemented"))
+        async move {
+            let removed = self.bootstrappers.clear().await?;
+            self.remove_bootstrap_nodes(removed.clone()).await;
+            Ok(removed.into_iter().map(Multiaddr::from).collect())
+        }
+        .instrument(self.span.clone())
+        .await
     }
 
     /// Restore the originally configured bootstrapper node list by adding them to the list of the
     /// currently used bootstrapper node address list; returns the restored addresses.
+    ///
+    /// The original list consists of the [`IpfsOptions::bootstrap`] nodes and the default
+    /// [`config::BOOTSTRAP_NODES`].
     pub async fn restore_bootstrappers(&self) -> Result<Vec<Multiaddr>, Error> {
-        Err(anyhow!("not implemented"))
+        async move {
+            let restored = self.bootstrappers.restore().await?;
+            self.add_bootstrap_nodes(restored.clone()).await;
+            Ok(restored.into_iter().map(Multiaddr::from).collect())
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Adds the bootstrap nodes to the routing table, resolving their `/dnsaddr` addresses.
+    async fn add_bootstrap_nodes(&self, addrs: Vec<MultiaddrWithPeerId>) {
+        let nodes = dnsaddr::resolve_bootstrap(&self.dnslink, bootstrap::into_pairs(addrs)).await;
+        for (peer, addr) in nodes {
+            self.controls.kad().add_node(peer, vec![addr]).await;
+        }
+    }
+
+    /// Removes the peers of the removed bootstrap nodes from the routing table, unless they are
+    /// still in the list with another address.
+    async fn remove_bootstrap_nodes(&self, removed: Vec<MultiaddrWithPeerId>) {
+        let remaining = self
+            .bootstrappers
+            .list()
+            .await
+            .into_iter()
+            .map(|addr| addr.peer_id)
+            .collect::<HashSet<_>>();
+
+        let peers = removed
+            .into_iter()
+            .map(|addr| addr.peer_id)
+            .filter(|peer| !remaining.contains(peer))
+            .collect::<HashSet<_>>();
+
+        for peer in peers {
+            self.controls.kad().remove_node(peer).await;
+        }
     }
 
     /// Exit daemon.
@@ -1211,4 +1651,78 @@ mod tests {
         ipfs.remove_pin(&cid, false).await.unwrap();
         assert!(!ipfs.is_pinned(&cid).await.unwrap());
     }
+
+    #[tokio::test]
+    async fn gc_removes_only_unpinned() {
+        use futures::stream::TryStreamExt;
+
+        let ipfs = Node::new("test_node").await;
+
+        let leaf = ipfs.put_dag(make_ipld!("leaf")).await.unwrap();
+        let root = ipfs.put_dag(make_ipld!([leaf.clone()])).await.unwrap();
+        let garbage = ipfs.put_dag(make_ipld!("garbage")).await.unwrap();
+
+        ipfs.insert_pin(&root, true).await.unwrap();
+
+        let removed = ipfs.gc().try_collect::<Vec<_>>().await.unwrap();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].hash(), garbage.hash());
+
+        assert!(ipfs.get_block_now(&root).await.unwrap().is_some());
+        assert!(ipfs.get_block_now(&leaf).await.unwrap().is_some());
+        assert!(ipfs.get_block_now(&garbage).await.unwrap().is_none());
+
+        // nothing left to collect
+        let removed = ipfs.gc().try_collect::<Vec<_>>().await.unwrap();
+        assert!(removed.is_empty());
+    }
+
+    #[tokio::test]
+    async fn gc_waits_for_puts_to_be_pinned() {
+        use futures::stream::TryStreamExt;
+        use std::time::Duration;
+
+        let ipfs = Node::new("test_node").await;
+
+        let permit = ipfs.gc_permit().await;
+        let cid = ipfs.put_dag(make_ipld!("pinned soon")).await.unwrap();
+
+        let mut gc = ipfs.gc();
+
+        // the mark phase cannot start before the block has been pinned
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), gc.try_next())
+                .await
+                .is_err()
+        );
+
+        // while more permits can be taken by the holder of one
+        ipfs.insert_pin(&cid, false).await.unwrap();
+        drop(permit);
+
+        assert!(gc.try_collect::<Vec<_>>().await.unwrap().is_empty());
+        assert!(ipfs.get_block_now(&cid).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn bootstrapper_list_changes() {
+        let ipfs = Node::new("test_node").await;
+        assert!(ipfs.get_bootstrappers().await.unwrap().is_empty());
+
+        let addr = "/ip4/127.0.0.1/tcp/4001/p2p/QmaCpDMGvV2BGHeYERUEnRQAwe3N8SzbUtfsmvsqQLuvuJ"
+            .parse::<MultiaddrWithPeerId>()
+            .unwrap();
+
+        let added = ipfs.add_bootstrapper(addr.clone()).await.unwrap();
+        assert_eq!(added, Multiaddr::from(addr.clone()));
+        ipfs.add_bootstrapper(addr.clone()).await.unwrap();
+        assert_eq!(ipfs.get_bootstrappers().await.unwrap(), vec![added.clone()]);
+
+        ipfs.remove_bootstrapper(addr.clone()).await.unwrap();
+        assert!(ipfs.get_bootstrappers().await.unwrap().is_empty());
+
+        ipfs.add_bootstrapper(addr).await.unwrap();
+        assert_eq!(ipfs.clear_bootstrappers().await.unwrap(), vec![added]);
+        assert!(ipfs.get_bootstrappers().await.unwrap().is_empty());
+    }
 }
@@ -0,0 +1,232 @@
+//! Resolution of `/dnsaddr/<domain>` multiaddrs: the addresses are the `dnsaddr=<multiaddr>` TXT
+//! records of `_dnsaddr.<domain>`, which can in turn be `/dnsaddr` multiaddrs. The protocols
+//! following the domain, usually `/p2p/<peer>`, select the records ending in them as in
+//! go-multiaddr-dns.
+//!
+//! The lookups go through the [`DnsLinkResolver`] of the node, sharing its nameservers and
+//! timeouts.
+
+use crate::error::Error;
+use crate::ipns::dnslink::DnsLinkResolver;
+use libp2p_rs::core::{Multiaddr, PeerId};
+use libp2p_rs::multiaddr::protocol::Protocol;
+use std::collections::HashSet;
+
+/// The longest chain of `/dnsaddr` records followed.
+const MAX_DEPTH: usize = 32;
+
+/// Returns true if the multiaddr needs to be resolved with [`resolve`].
+pub(crate) fn is_dnsaddr(addr: &Multiaddr) -> bool {
+    matches!(addr.iter().next(), Some(Protocol::Dnsaddr(_)))
+}
+
+/// Resolves a `/dnsaddr` multiaddr recursively to the multiaddrs ending in the protocols after
+/// the domain. Other multiaddrs are returned as is.
+pub(crate) async fn resolve(
+    resolver: &DnsLinkResolver,
+    addr: Multiaddr,
+) -> Result<Vec<Multiaddr>, Error> {
+    let mut pending = vec![(addr, 0)];
+    let mut seen = HashSet::new();
+    let mut resolved = Vec::new();
+
+    while let Some((addr, depth)) = pending.pop() {
+        if !seen.insert(addr.clone()) {
+            continue;
+        }
+
+        let domain = match addr.iter().next() {
+            Some(Protocol::Dnsaddr(domain)) => Some(domain.into_owned()),
+            _ => None,
+        };
+
+        let domain = match domain {
+            Some(domain) => domain,
+            None => {
+                resolved.push(addr);
+                continue;
+            }
+        };
+
+        if depth >= MAX_DEPTH {
+            return Err(anyhow::anyhow!(
+                "/dnsaddr recursion limit of {} exceeded at {}",
+                MAX_DEPTH,
+                addr
+            ));
+        }
+
+        let suffix = addr.iter().skip(1).collect::<Vec<_>>();
+        let records = resolver
+            .txt_records(&format!("_dnsaddr.{}", domain))
+            .await?;
+
+        for (text, _) in records {
+            let found = match text.strip_prefix("dnsaddr=") {
+                Some(found) => found,
+                None => continue,
+            };
+
+            match found.trim().parse::<Multiaddr>() {
+                Ok(found) if ends_with(&found, &suffix) => pending.push((found, depth + 1)),
+                Ok(_) => {}
+                Err(e) => debug!("invalid dnsaddr record {:?}: {}", text, e),
+            }
+        }
+    }
+
+    Ok(resolved)
+}
+
+/// Resolves an address of the peer to the addresses to dial it at, without the `/p2p` suffix.
+/// Only the `/dnsaddr` records of the peer are used.
+pub(crate) async fn resolve_peer(
+    resolver: &DnsLinkResolver,
+    peer: &PeerId,
+    addr: Multiaddr,
+) -> Result<Vec<Multiaddr>, Error> {
+    if !is_dnsaddr(&addr) {
+        return Ok(vec![addr]);
+    }
+
+    let mut target = addr.clone();
+    if !matches!(target.iter().last(), Some(Protocol::P2p(_))) {
+        target.push(Protocol::P2p((*peer).into()));
+    }
+
+    let resolved = resolve(resolver, target)
+        .await?
+        .into_iter()
+        .map(|addr| {
+            addr.iter()
+                .filter(|p| !matches!(p, Protocol::P2p(_)))
+                .collect()
+        })
+        .collect::<Vec<_>>();
+
+    if resolved.is_empty() {
+        return Err(anyhow::anyhow!(
+            "no addresses of {} found at {}",
+            peer,
+            addr
+        ));
+    }
+
+    Ok(resolved)
+}
+
+/// Resolves the `/dnsaddr` addresses of the bootstrap nodes, leaving out the ones which cannot be
+/// resolved.
+pub(crate) async fn resolve_bootstrap(
+    resolver: &DnsLinkResolver,
+    bootstrap: Vec<(PeerId, Multiaddr)>,
+) -> Vec<(PeerId, Multiaddr)> {
+    let mut resolved = Vec::with_capacity(bootstrap.len());
+
+    for (peer, addr) in bootstrap {
+        match resolve_peer(resolver, &peer, addr.clone()).await {
+            Ok(addrs) => resolved.extend(addrs.into_iter().map(|addr| (peer, addr))),
+            Err(e) => {
+                warn!(peer = %peer, "failed to resolve the bootstrap address {}: {}", addr, e)
+            }
+        }
+    }
+
+    resolved
+}
+
+fn ends_with(addr: &Multiaddr, suffix: &[Protocol<'_>]) -> bool {
+    let protocols = addr.iter().collect::<Vec<_>>();
+    protocols.len() >= suffix.len() && protocols[protocols.len() - suffix.len()..] == *suffix
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipns::dnslink::tests::{resolver, StubServer};
+
+    const PEER_A: &str = "QmNnooDu7bfjPFoTZYxMNLWUQJyrVwtbZg5gBMjTezGAJN";
+    const PEER_B: &str = "QmQCU2EcMqAqQPR2i9bChDtGNJchTbq5TbXJJ16u19uLTa";
+
+    #[tokio::test]
+    async fn resolves_recursively_for_the_peer() {
+        let server = StubServer::start(&[
+            (
+                "_dnsaddr.bootstrap.example.com",
+                &format!("dnsaddr=/dnsaddr/a.example.com/p2p/{}", PEER_A),
+                60,
+            ),
+            (
+                "_dnsaddr.bootstrap.example.com",
+                &format!("dnsaddr=/dnsaddr/b.example.com/p2p/{}", PEER_B),
+                60,
+            ),
+            (
+                "_dnsaddr.a.example.com",
+                &format!("dnsaddr=/ip4/10.0.0.1/tcp/4001/p2p/{}", PEER_A),
+                60,
+            ),
+            (
+                "_dnsaddr.a.example.com",
+                &format!("dnsaddr=/ip6/::1/tcp/4001/p2p/{}", PEER_A),
+                60,
+            ),
+            (
+                "_dnsaddr.b.example.com",
+                &format!("dnsaddr=/ip4/10.0.0.2/tcp/4001/p2p/{}", PEER_B),
+                60,
+            ),
+        ]);
+        let resolver = resolver(&server);
+        let peer = PEER_A.parse::<PeerId>().unwrap();
+
+        let mut addrs = resolve_peer(
+            &resolver,
+            &peer,
+            "/dnsaddr/bootstrap.example.com".parse().unwrap(),
+        )
+        .await
+        .unwrap();
+        addrs.sort_by_key(|addr| addr.to_string());
+
+        assert_eq!(
+            addrs,
+            vec![
+                "/ip4/10.0.0.1/tcp/4001".parse::<Multiaddr>().unwrap(),
+                "/ip6/::1/tcp/4001".parse().unwrap(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn other_addresses_are_returned_as_is() {
+        let server = StubServer::start(&[]);
+        let resolver = resolver(&server);
+        let addr = "/ip4/10.0.0.1/tcp/4001".parse::<Multiaddr>().unwrap();
+
+        assert_eq!(resolve(&resolver, addr.clone()).await.unwrap(), vec![addr]);
+        assert_eq!(server.queries(), 0);
+    }
+
+    #[tokio::test]
+    async fn loops_terminate() {
+        let server = StubServer::start(&[
+            (
+                "_dnsaddr.a.example.com",
+                "dnsaddr=/dnsaddr/b.example.com",
+                60,
+            ),
+            (
+                "_dnsaddr.b.example.com",
+                "dnsaddr=/dnsaddr/a.example.com",
+                60,
+            ),
+        ]);
+        let resolver = resolver(&server);
+
+        let addrs = resolve(&resolver, "/dnsaddr/a.example.com".parse().unwrap())
+            .await
+            .unwrap();
+        assert!(addrs.is_empty());
+    }
+}
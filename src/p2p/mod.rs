@@ -1,14 +1,20 @@
 //! P2P handling for IPFS nodes.
+use std::path::PathBuf;
 use std::time::Duration;
 
+use crate::ipns::dnslink::DnsLinkResolver;
 use crate::repo::Repo;
 use crate::{IpfsOptions, RepoTypes};
+use tracing_futures::Instrument;
 
 pub(crate) mod addr;
+pub(crate) mod dnsaddr;
 pub(crate) mod pubsub;
+mod store;
 mod swarm;
 
 pub use addr::{MultiaddrWithPeerId, MultiaddrWithoutPeerId};
+pub use store::DhtStore;
 pub use swarm::Connection;
 
 use libp2p_rs::core::identity::Keypair;
@@ -20,8 +26,6 @@ use libp2p_rs::swarm::{Control as SwarmControl, Swarm};
 use bitswap::Control as BitswapControl;
 use libp2p_rs::floodsub::control::Control as FloodsubControl;
 
-use libp2p_rs::kad::store::MemoryStore;
-
 use bitswap::Bitswap;
 
 use libp2p_rs::core::transport::upgrade::TransportUpgrade;
@@ -61,12 +65,15 @@ pub struct SwarmOptions {
     pub keypair: Keypair,
     /// Bound listening addresses; by default the node will not listen on any address.
     pub listening_addrs: Vec<Multiaddr>,
-    /// The peers to connect to on startup.
+    /// The peers to connect to on startup; `/dnsaddr` addresses are resolved in the background.
     pub bootstrap: Vec<(PeerId, Multiaddr)>,
     /// Enables mdns for peer discovery and announcement when true.
     pub mdns: bool,
     /// Custom Kademlia protocol name, see [`IpfsOptions::kad_protocol`].
     pub kad_protocol: Option<String>,
+    /// The path of the persistent DHT record store, see [`crate::repo::RepoTypes::PERSISTENT`].
+    /// The records are only kept in memory when `None`.
+    pub dht_store_path: Option<PathBuf>,
 }
 
 impl From<&IpfsOptions> for SwarmOptions {
@@ -83,12 +90,17 @@ impl From<&IpfsOptions> for SwarmOptions {
             bootstrap,
             mdns,
             kad_protocol,
+            dht_store_path: None,
         }
     }
 }
 
 impl Controls {
-    pub(crate) async fn build<T: RepoTypes>(repo: Repo<T>, options: SwarmOptions) -> Self {
+    pub(crate) async fn build<T: RepoTypes>(
+        repo: Repo<T>,
+        options: SwarmOptions,
+        resolver: &DnsLinkResolver,
+    ) -> Self {
         // start with security layer
         let sec_secio = secio::Config::new(options.keypair.clone());
         // Set up an encrypted TCP transport over the Yamux or Mplex protocol.
@@ -131,7 +143,18 @@ impl Controls {
             kad_config = kad_config.with_protocol_name(ProtocolId::from(s.as_bytes()));
         }
 
-        let store = MemoryStore::new(*swarm.local_peer_id());
+        let local_peer_id = *swarm.local_peer_id();
+        let store = match options.dht_store_path {
+            Some(path) => DhtStore::open(local_peer_id, &path).unwrap_or_else(|e| {
+                log::warn!(
+                    "failed to open the dht store at {:?}, keeping records in memory: {}",
+                    path,
+                    e
+                );
+                DhtStore::in_memory(local_peer_id)
+            }),
+            None => DhtStore::in_memory(local_peer_id),
+        };
         let kad = Kademlia::with_config(*swarm.local_peer_id(), store, kad_config);
 
         let mut kad_control = kad.control();
@@ -160,9 +183,27 @@ impl Controls {
         // To start Swarm/Kad/... main loops
         swarm.start();
 
-        // handle bootstrap nodes
-        if !options.bootstrap.is_empty() {
-            kad_control.bootstrap(options.bootstrap.clone()).await;
+        // handle bootstrap nodes; the /dnsaddr ones are resolved in the background so that the
+        // startup does not wait for the lookups
+        let (dnsaddrs, bootstrap): (Vec<_>, Vec<_>) = options
+            .bootstrap
+            .into_iter()
+            .partition(|(_, addr)| dnsaddr::is_dnsaddr(addr));
+
+        if !bootstrap.is_empty() {
+            kad_control.bootstrap(bootstrap).await;
+        }
+
+        if !dnsaddrs.is_empty() {
+            let resolver = resolver.clone();
+            let mut kad_control = kad_control.clone();
+            let resolved = async move {
+                let bootstrap = dnsaddr::resolve_bootstrap(&resolver, dnsaddrs).await;
+                if !bootstrap.is_empty() {
+                    kad_control.bootstrap(bootstrap).await;
+                }
+            };
+            tokio::spawn(resolved.instrument(tracing::Span::current()));
         }
 
         Controls {
@@ -1,9 +1,30 @@
+use futures::channel::mpsc;
+use futures::future::{BoxFuture, Future, FutureExt};
+use futures::stream::Stream;
 use libp2p_rs::core::PeerId;
+use libp2p_rs::floodsub::control::Control as FloodsubControl;
 use libp2p_rs::floodsub::protocol::FloodsubMessage;
 use libp2p_rs::floodsub::subscription::Subscription;
-use std::sync::Arc;
+use libp2p_rs::floodsub::Topic;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tracing_futures::Instrument;
+
+use crate::error::Error;
+
+/// How long the received messages are remembered to drop the duplicates, as in go-libp2p-pubsub.
+const SEEN_TTL: Duration = Duration::from_secs(120);
 
 /// Adaptation hopefully supporting both Floodsub and Gossipsub Messages in the future
+///
+/// The messages are neither signed nor verified, as the floodsub of libp2p-rs does not carry the
+/// signature and the key of the message; [`crate::Ipfs::pubsub_set_validator`] can be used to
+/// check the messages before they are delivered locally, though not before they are relayed.
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct PubsubMessage {
     /// Peer address of the message sender.
@@ -38,20 +59,340 @@ impl From<FloodsubMessage> for PubsubMessage {
     }
 }
 
-/// Stream of a pubsub messages.
-pub struct SubscriptionStream(Subscription);
+/// The outcome of validating a received message with the validator of its topic, see
+/// [`crate::Ipfs::pubsub_set_validator`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValidationResult {
+    /// The message is delivered to the subscription streams.
+    Accept,
+    /// The message is dropped.
+    Ignore,
+    /// The message is dropped as invalid, which is logged.
+    Reject,
+}
+
+/// An async validator of the messages of a topic.
+#[derive(Clone)]
+struct Validator(
+    Arc<dyn Fn(Arc<PubsubMessage>) -> BoxFuture<'static, ValidationResult> + Send + Sync>,
+);
+
+impl fmt::Debug for Validator {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("Validator")
+    }
+}
+
+/// The local subscriptions. The [`SubscriptionStream`]s of a topic share a single subscription
+/// with the router, which is only made for the first stream and cancelled once the last one is
+/// dropped or [`Subscriptions::unsubscribe`] is called, announcing the change to the peers.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Subscriptions {
+    inner: Arc<Mutex<Inner>>,
+    // serializes the subscribing with the router so that a topic is only subscribed to once
+    subscribing: Arc<tokio::sync::Mutex<()>>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    next_id: u64,
+    topics: HashMap<String, TopicSubscription>,
+    validators: HashMap<String, Validator>,
+    // shared by the topics, as a message on several topics is received once for each of them
+    seen: SeenCache,
+}
+
+#[derive(Debug)]
+struct TopicSubscription {
+    id: u64,
+    streams: HashMap<u64, mpsc::UnboundedSender<Arc<PubsubMessage>>>,
+    // forwards the messages of the router subscription, which is dropped when aborted
+    task: JoinHandle<()>,
+}
+
+impl Inner {
+    fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn join(&mut self, topic: &str, inner: &Arc<Mutex<Inner>>) -> Option<SubscriptionStream> {
+        let id = self.next_id();
+        let subscription = self.topics.get_mut(topic)?;
+
+        let (tx, rx) = mpsc::unbounded();
+        subscription.streams.insert(id, tx);
+
+        Some(SubscriptionStream {
+            topic: topic.to_owned(),
+            id,
+            rx,
+            subscriptions: Arc::downgrade(inner),
+        })
+    }
+
+    fn leave(&mut self, topic: &str, id: u64) {
+        let last = match self.topics.get_mut(topic) {
+            Some(subscription) => {
+                subscription.streams.remove(&id).is_some() && subscription.streams.is_empty()
+            }
+            None => false,
+        };
+
+        if last {
+            if let Some(subscription) = self.topics.remove(topic) {
+                subscription.task.abort();
+            }
+        }
+    }
+}
+
+impl Subscriptions {
+    /// Returns a new stream of the messages on the topic.
+    pub(crate) async fn subscribe(
+        &self,
+        mut pubsub: FloodsubControl,
+        topic: String,
+    ) -> Result<SubscriptionStream, Error> {
+        let _guard = self.subscribing.lock().await;
+
+        let joined = self.inner.lock().unwrap().join(&topic, &self.inner);
+        if let Some(stream) = joined {
+            return Ok(stream);
+        }
+
+        let subscription = pubsub.subscribe(Topic::new(topic.clone())).await?;
+
+        let mut inner = self.inner.lock().unwrap();
+        let id = inner.next_id();
+        let task = tokio::spawn(
+            forward(Arc::downgrade(&self.inner), topic.clone(), id, subscription).in_current_span(),
+        );
+        inner.topics.insert(
+            topic.clone(),
+            TopicSubscription {
+                id,
+                streams: HashMap::new(),
+                task,
+            },
+        );
+
+        Ok(inner
+            .join(&topic, &self.inner)
+            .expect("the subscription was just inserted"))
+    }
+
+    /// Ends all of the streams of the topic and cancels the subscription with the router. Returns
+    /// false if the topic was not subscribed to.
+    pub(crate) fn unsubscribe(&self, topic: &str) -> bool {
+        match self.inner.lock().unwrap().topics.remove(topic) {
+            Some(subscription) => {
+                subscription.task.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Sets the validator of the messages received on the topic, replacing any previous one.
+    pub(crate) fn set_validator<F, Fut>(&self, topic: String, validator: F)
+    where
+        F: Fn(Arc<PubsubMessage>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ValidationResult> + Send + 'static,
+    {
+        let validator = Validator(Arc::new(move |message| validator(message).boxed()));
+        self.inner
+            .lock()
+            .unwrap()
+            .validators
+            .insert(topic, validator);
+    }
+
+    /// Removes the validator of the topic, returning true if there was one.
+    pub(crate) fn remove_validator(&self, topic: &str) -> bool {
+        self.inner
+            .lock()
+            .unwrap()
+            .validators
+            .remove(topic)
+            .is_some()
+    }
+
+    /// Returns the subscribed topics in sorted order.
+    pub(crate) fn topics(&self) -> Vec<String> {
+        let mut topics = self
+            .inner
+            .lock()
+            .unwrap()
+            .topics
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        topics.sort();
+        topics
+    }
+}
+
+/// Forwards the new and valid messages of the router subscription to the streams of the topic
+/// until the subscription `id` is cancelled or the router stops. A message on several subscribed
+/// topics is delivered to the streams of all of them by whichever subscription receives it first.
+async fn forward(
+    inner: Weak<Mutex<Inner>>,
+    topic: String,
+    id: u64,
+    mut subscription: Subscription,
+) {
+    'messages: while let Some(message) = subscription.next().await {
+        let message = Arc::new(PubsubMessage::from(message.as_ref().clone()));
+
+        let inner = match inner.upgrade() {
+            Some(inner) => inner,
+            None => return,
+        };
+
+        let validators = {
+            let mut inner = inner.lock().unwrap();
+            if !inner
+                .seen
+                .insert(message.source, message.sequence_number.clone())
+            {
+                trace!(topic = %topic, source = %message.source, "dropping a duplicate message");
+                continue;
+            }
+
+            message
+                .topics
+                .iter()
+                .filter(|topic| inner.topics.contains_key(*topic))
+                .filter_map(|topic| inner.validators.get(topic).cloned())
+                .collect::<Vec<_>>()
+        };
+
+        // as in go-libp2p-pubsub, the validators of all of the subscribed topics must accept
+        for Validator(validator) in validators {
+            match validator(Arc::clone(&message)).await {
+                ValidationResult::Accept => {}
+                ValidationResult::Ignore => continue 'messages,
+                ValidationResult::Reject => {
+                    debug!(topic = %topic, source = %message.source, "rejected a message");
+                    continue 'messages;
+                }
+            }
+        }
+
+        let inner = inner.lock().unwrap();
+        if !matches!(inner.topics.get(&topic), Some(subscription) if subscription.id == id) {
+            // unsubscribed while the message was being received
+            return;
+        }
+
+        for subscription in message
+            .topics
+            .iter()
+            .filter_map(|topic| inner.topics.get(topic))
+        {
+            for tx in subscription.streams.values() {
+                let _ = tx.unbounded_send(Arc::clone(&message));
+            }
+        }
+    }
+
+    trace!(topic = %topic, "pubsub subscription ended");
+
+    // the router stopped; end the streams unless the topic was subscribed to again
+    if let Some(inner) = inner.upgrade() {
+        let mut inner = inner.lock().unwrap();
+        if matches!(inner.topics.get(&topic), Some(subscription) if subscription.id == id) {
+            inner.topics.remove(&topic);
+        }
+    }
+}
+
+/// The (source, sequence number) pairs of the messages received within the [`SEEN_TTL`].
+#[derive(Debug, Default)]
+struct SeenCache {
+    ids: HashSet<(PeerId, Vec<u8>)>,
+    expiring: VecDeque<(Instant, (PeerId, Vec<u8>))>,
+}
+
+impl SeenCache {
+    /// Returns false if the message has been seen already.
+    fn insert(&mut self, source: PeerId, sequence_number: Vec<u8>) -> bool {
+        let now = Instant::now();
+        while let Some((at, _)) = self.expiring.front() {
+            if now.duration_since(*at) < SEEN_TTL {
+                break;
+            }
+            if let Some((_, id)) = self.expiring.pop_front() {
+                self.ids.remove(&id);
+            }
+        }
+
+        let id = (source, sequence_number);
+        if self.ids.contains(&id) {
+            return false;
+        }
+
+        self.ids.insert(id.clone());
+        self.expiring.push_back((now, id));
+        true
+    }
+}
+
+/// Stream of a pubsub messages. The topic stays subscribed to until all of its streams have been
+/// dropped or [`crate::Ipfs::pubsub_unsubscribe`] is called, which ends the streams.
+pub struct SubscriptionStream {
+    topic: String,
+    id: u64,
+    rx: mpsc::UnboundedReceiver<Arc<PubsubMessage>>,
+    subscriptions: Weak<Mutex<Inner>>,
+}
 
 impl SubscriptionStream {
-    pub async fn next(&mut self) -> Option<Arc<PubsubMessage>> {
-        self.0
-            .next()
-            .await
-            .map(|m| Arc::new(m.as_ref().clone().into()))
+    /// Returns the topic of the messages.
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+}
+
+impl Stream for SubscriptionStream {
+    type Item = Arc<PubsubMessage>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.rx).poll_next(cx)
+    }
+}
+
+impl Drop for SubscriptionStream {
+    fn drop(&mut self) {
+        if let Some(inner) = self.subscriptions.upgrade() {
+            inner.lock().unwrap().leave(&self.topic, self.id);
+        }
     }
 }
 
-impl From<Subscription> for SubscriptionStream {
-    fn from(s: Subscription) -> Self {
-        Self(s)
+impl fmt::Debug for SubscriptionStream {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("SubscriptionStream")
+            .field("topic", &self.topic)
+            .field("id", &self.id)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SeenCache;
+    use libp2p_rs::core::PeerId;
+
+    #[test]
+    fn seen_cache_drops_duplicates() {
+        let source = PeerId::random();
+        let mut seen = SeenCache::default();
+
+        assert!(seen.insert(source, vec![1]));
+        assert!(!seen.insert(source, vec![1]));
+        assert!(seen.insert(source, vec![2]));
+        assert!(seen.insert(PeerId::random(), vec![1]));
     }
 }
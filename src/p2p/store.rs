@@ -0,0 +1,438 @@
+//! Kademlia record store which keeps the records on disk over restarts.
+use std::borrow::Cow;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use libp2p_rs::core::{Multiaddr, PeerId};
+use libp2p_rs::kad::record::{Key, ProviderRecord, Record};
+use libp2p_rs::kad::store::{MemoryStore, RecordStore, Result as StoreResult};
+use prost::Message;
+use sled::{Db, Tree};
+
+use crate::error::Error;
+
+// the records are stored protobuf encoded, with the expiry times as milliseconds since the unix
+// epoch as `Instant` cannot outlive the process
+mod dht_store {
+    include!(concat!(env!("OUT_DIR"), "/dht_store.rs"));
+}
+
+use dht_store::{Provider as StoredProvider, Record as StoredRecord};
+
+/// Name of the tree holding the value records.
+const RECORDS_TREE: &str = "records";
+
+/// Name of the tree holding the provider records.
+const PROVIDERS_TREE: &str = "providers";
+
+/// The on-disk half of the [`DhtStore`].
+#[derive(Debug)]
+struct Persistence {
+    // kept open for the lifetime of the trees
+    _db: Db,
+    records: Tree,
+    providers: Tree,
+}
+
+/// Record store for the Kademlia DHT.
+///
+/// The records are served from a [`MemoryStore`], which also enforces the limits on the amount of
+/// records. When opened on a path, every change is mirrored to a [`sled`] database and the
+/// unexpired records are loaded back on startup, so that the values and provider records stored
+/// for the network, including our own provider records which are republished, survive restarts.
+/// The expiry times and publishers of the records are preserved.
+pub struct DhtStore {
+    memory: MemoryStore,
+    persistence: Option<Persistence>,
+}
+
+impl DhtStore {
+    /// Creates a store which only keeps the records in memory.
+    pub fn in_memory(local_id: PeerId) -> Self {
+        DhtStore {
+            memory: MemoryStore::new(local_id),
+            persistence: None,
+        }
+    }
+
+    /// Opens or creates a store at the given path, loading the unexpired records stored earlier.
+    pub fn open(local_id: PeerId, path: &Path) -> Result<Self, Error> {
+        let db = sled::open(path)?;
+        let records = db.open_tree(RECORDS_TREE)?;
+        let providers = db.open_tree(PROVIDERS_TREE)?;
+
+        let mut memory = MemoryStore::new(local_id);
+        let now = SystemTime::now();
+
+        for item in records.iter() {
+            let (key, value) = item?;
+
+            let record = StoredRecord::decode(&*value)
+                .map_err(Error::from)
+                .and_then(|stored| stored.into_record(key.to_vec(), now));
+
+            match record {
+                Ok(Some(record)) => {
+                    if let Err(e) = memory.put(record) {
+                        log::warn!("failed to load a dht record: {:?}", e);
+                    }
+                }
+                Ok(None) => {
+                    records.remove(key)?;
+                }
+                Err(e) => {
+                    log::warn!("removing an unreadable dht record: {}", e);
+                    records.remove(key)?;
+                }
+            }
+        }
+
+        for item in providers.iter() {
+            let (key, value) = item?;
+
+            let record = StoredProvider::decode(&*value)
+                .map_err(Error::from)
+                .and_then(|stored| stored.into_record(now));
+
+            match record {
+                Ok(Some(record)) => {
+                    if let Err(e) = memory.add_provider(record) {
+                        log::warn!("failed to load a dht provider record: {:?}", e);
+                    }
+                }
+                Ok(None) => {
+                    providers.remove(key)?;
+                }
+                Err(e) => {
+                    log::warn!("removing an unreadable dht provider record: {}", e);
+                    providers.remove(key)?;
+                }
+            }
+        }
+
+        Ok(DhtStore {
+            memory,
+            persistence: Some(Persistence {
+                _db: db,
+                records,
+                providers,
+            }),
+        })
+    }
+
+    /// Writes the current provider records of the key over the ones on disk. The memory store may
+    /// have replaced earlier providers of the key when adding a new one.
+    fn persist_providers(&self, key: &Key) {
+        let persistence = match self.persistence.as_ref() {
+            Some(persistence) => persistence,
+            None => return,
+        };
+
+        if let Err(e) = replace_providers(&persistence.providers, key, self.memory.providers(key)) {
+            log::warn!("failed to persist the dht provider records: {}", e);
+        }
+    }
+}
+
+impl<'a> RecordStore<'a> for DhtStore {
+    type RecordsIter = <MemoryStore as RecordStore<'a>>::RecordsIter;
+    type ProvidedIter = <MemoryStore as RecordStore<'a>>::ProvidedIter;
+
+    fn get(&'a self, k: &Key) -> Option<Cow<'_, Record>> {
+        self.memory.get(k)
+    }
+
+    fn put(&'a mut self, r: Record) -> StoreResult<()> {
+        let stored = StoredRecord::from(&r);
+        let key = r.key.to_vec();
+
+        self.memory.put(r)?;
+
+        if let Some(persistence) = self.persistence.as_ref() {
+            if let Err(e) = persistence.records.insert(key, encode(&stored)) {
+                log::warn!("failed to persist a dht record: {}", e);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remove(&'a mut self, k: &Key) {
+        self.memory.remove(k);
+
+        if let Some(persistence) = self.persistence.as_ref() {
+            if let Err(e) = persistence.records.remove(k.to_vec()) {
+                log::warn!("failed to remove a persisted dht record: {}", e);
+            }
+        }
+    }
+
+    fn records(&'a self) -> Self::RecordsIter {
+        self.memory.records()
+    }
+
+    fn add_provider(&'a mut self, record: ProviderRecord) -> StoreResult<()> {
+        let key = record.key.clone();
+
+        self.memory.add_provider(record)?;
+        self.persist_providers(&key);
+
+        Ok(())
+    }
+
+    fn providers(&'a self, key: &Key) -> Vec<ProviderRecord> {
+        self.memory.providers(key)
+    }
+
+    fn provided(&'a self) -> Self::ProvidedIter {
+        self.memory.provided()
+    }
+
+    fn remove_provider(&'a mut self, k: &Key, p: &PeerId) {
+        self.memory.remove_provider(k, p);
+
+        if let Some(persistence) = self.persistence.as_ref() {
+            if let Err(e) = persistence.providers.remove(provider_key(k, p)) {
+                log::warn!("failed to remove a persisted dht provider record: {}", e);
+            }
+        }
+    }
+}
+
+impl From<&Record> for StoredRecord {
+    fn from(record: &Record) -> Self {
+        StoredRecord {
+            value: record.value.clone(),
+            publisher: record.publisher.as_ref().map(|peer| peer.to_string()),
+            expires: record.expires.map(instant_to_millis),
+        }
+    }
+}
+
+impl StoredRecord {
+    /// Returns `None` when the record has expired.
+    fn into_record(self, key: Vec<u8>, now: SystemTime) -> Result<Option<Record>, Error> {
+        let expires = match self.expires.map(|millis| millis_to_instant(millis, now)) {
+            Some(None) => return Ok(None),
+            Some(Some(instant)) => Some(instant),
+            None => None,
+        };
+
+        let publisher = self
+            .publisher
+            .map(|peer| peer.parse::<PeerId>())
+            .transpose()
+            .map_err(|e| anyhow::anyhow!("invalid publisher: {:?}", e))?;
+
+        Ok(Some(Record {
+            key: Key::from(key),
+            value: self.value,
+            publisher,
+            expires,
+        }))
+    }
+}
+
+impl From<&ProviderRecord> for StoredProvider {
+    fn from(record: &ProviderRecord) -> Self {
+        StoredProvider {
+            key: record.key.to_vec(),
+            provider: record.provider.to_string(),
+            addresses: record
+                .addresses
+                .iter()
+                .map(|addr| addr.to_string())
+                .collect(),
+            expires: record.expires.map(instant_to_millis),
+        }
+    }
+}
+
+impl StoredProvider {
+    /// Returns `None` when the record has expired.
+    fn into_record(self, now: SystemTime) -> Result<Option<ProviderRecord>, Error> {
+        let expires = match self.expires.map(|millis| millis_to_instant(millis, now)) {
+            Some(None) => return Ok(None),
+            Some(Some(instant)) => Some(instant),
+            None => None,
+        };
+
+        let provider = self
+            .provider
+            .parse::<PeerId>()
+            .map_err(|e| anyhow::anyhow!("invalid provider: {:?}", e))?;
+
+        let addresses = self
+            .addresses
+            .iter()
+            .map(|addr| addr.parse::<Multiaddr>())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Some(ProviderRecord {
+            key: Key::from(self.key),
+            provider,
+            expires,
+            addresses,
+        }))
+    }
+}
+
+fn replace_providers(tree: &Tree, key: &Key, records: Vec<ProviderRecord>) -> Result<(), Error> {
+    for item in tree.scan_prefix(provider_prefix(key)) {
+        let (db_key, _) = item?;
+        tree.remove(db_key)?;
+    }
+
+    for record in records {
+        let stored = encode(&StoredProvider::from(&record));
+        tree.insert(provider_key(&record.key, &record.provider), stored)?;
+    }
+
+    Ok(())
+}
+
+fn encode(message: &impl Message) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(message.encoded_len());
+    message
+        .encode(&mut buf)
+        .expect("cannot produce more bytes than encoded_len");
+    buf
+}
+
+/// The provider records are keyed by the length prefixed record key followed by the provider, so
+/// that the providers of a key can be found by prefix.
+fn provider_prefix(key: &Key) -> Vec<u8> {
+    let key = key.to_vec();
+    let mut prefix = Vec::with_capacity(4 + key.len());
+    prefix.extend_from_slice(&(key.len() as u32).to_be_bytes());
+    prefix.extend_from_slice(&key);
+    prefix
+}
+
+fn provider_key(key: &Key, provider: &PeerId) -> Vec<u8> {
+    let mut db_key = provider_prefix(key);
+    db_key.extend_from_slice(provider.to_string().as_bytes());
+    db_key
+}
+
+fn instant_to_millis(instant: Instant) -> u64 {
+    let remaining = instant.saturating_duration_since(Instant::now());
+    let at = SystemTime::now() + remaining;
+    at.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Returns `None` if the time has already passed.
+fn millis_to_instant(millis: u64, now: SystemTime) -> Option<Instant> {
+    let at = UNIX_EPOCH + Duration::from_millis(millis);
+    match at.duration_since(now) {
+        Ok(remaining) if remaining > Duration::from_secs(0) => Some(Instant::now() + remaining),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_survive_reopening() {
+        let tmp = tempfile::tempdir().unwrap();
+        let local_id = PeerId::random();
+        let key = Key::from(b"/v/some-key".to_vec());
+        let expires = Instant::now() + Duration::from_secs(3600);
+
+        {
+            let mut store = DhtStore::open(local_id, tmp.path()).unwrap();
+
+            store
+                .put(Record {
+                    key: key.clone(),
+                    value: b"some-value".to_vec(),
+                    publisher: Some(local_id),
+                    expires: Some(expires),
+                })
+                .unwrap();
+
+            store
+                .add_provider(ProviderRecord {
+                    key: key.clone(),
+                    provider: local_id,
+                    expires: Some(expires),
+                    addresses: vec!["/ip4/127.0.0.1/tcp/4001".parse().unwrap()],
+                })
+                .unwrap();
+        }
+
+        let store = DhtStore::open(local_id, tmp.path()).unwrap();
+
+        let record = store.get(&key).unwrap();
+        assert_eq!(record.value, b"some-value");
+        assert_eq!(record.publisher, Some(local_id));
+
+        // millisecond precision is kept on disk
+        let expiry = record.expires.unwrap();
+        let drift = if expiry > expires {
+            expiry - expires
+        } else {
+            expires - expiry
+        };
+        assert!(drift < Duration::from_secs(1), "{:?}", drift);
+
+        let providers = store.providers(&key);
+        assert_eq!(providers.len(), 1);
+        assert_eq!(providers[0].provider, local_id);
+        assert_eq!(providers[0].addresses.len(), 1);
+
+        // our own provider records are the ones to be republished
+        assert_eq!(store.provided().count(), 1);
+    }
+
+    #[test]
+    fn removed_and_expired_records_are_not_loaded() {
+        let tmp = tempfile::tempdir().unwrap();
+        let local_id = PeerId::random();
+        let removed = Key::from(b"/v/removed".to_vec());
+        let expired = Key::from(b"/v/expired".to_vec());
+
+        {
+            let mut store = DhtStore::open(local_id, tmp.path()).unwrap();
+
+            store
+                .put(Record {
+                    key: removed.clone(),
+                    value: b"value".to_vec(),
+                    publisher: None,
+                    expires: None,
+                })
+                .unwrap();
+            store.remove(&removed);
+
+            store
+                .put(Record {
+                    key: expired.clone(),
+                    value: b"value".to_vec(),
+                    publisher: None,
+                    expires: Some(Instant::now()),
+                })
+                .unwrap();
+
+            store
+                .add_provider(ProviderRecord {
+                    key: removed.clone(),
+                    provider: local_id,
+                    expires: None,
+                    addresses: Vec::new(),
+                })
+                .unwrap();
+            store.remove_provider(&removed, &local_id);
+        }
+
+        let store = DhtStore::open(local_id, tmp.path()).unwrap();
+
+        assert!(store.get(&removed).is_none());
+        assert!(store.get(&expired).is_none());
+        assert!(store.providers(&removed).is_empty());
+    }
+}
@@ -180,6 +180,13 @@ where
             return;
         }
 
+        // the whole walk is done in a single session, unless blocks are only loaded locally
+        let session = if download_blocks {
+            Some(ipfs.borrow().new_bitswap_session())
+        } else {
+            None
+        };
+
         while let Some((depth, cid, source, link_name)) = work.pop_front() {
             let traverse_links = match max_depth {
                 Some(d) if d <= depth => {
@@ -195,8 +202,8 @@ where
             // `MaybeOwned` which we don't necessarily need.
             let borrowed = ipfs.borrow();
 
-            let data = if download_blocks {
-                match borrowed.get_block(&cid).await {
+            let data = if let Some(session) = session.as_ref() {
+                match borrowed.get_block_in_session(&cid, session).await {
                     Ok(Block { data, .. }) => data,
                     Err(e) => {
                         warn!("failed to load {}, linked from {}: {}", cid, source, e);
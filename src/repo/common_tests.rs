@@ -1,4 +1,4 @@
-///! "Interface" tests for pin store, maybe more later
+///! "Interface" tests for pin store and the column operations of datastore, maybe more later
 use crate::repo::DataStore;
 use std::path::PathBuf;
 use std::sync::Arc;
@@ -332,3 +332,94 @@ macro_rules! pinstore_interface_tests {
         }
     };
 }
+
+/// Generates the "common interface" tests for the column operations of DataStore implementations
+/// as a given module using a types factory method, similar to `pinstore_interface_tests`.
+#[macro_export]
+macro_rules! datastore_interface_tests {
+    ($module_name:ident, $factory:expr) => {
+        #[cfg(test)]
+        mod $module_name {
+
+            use crate::repo::common_tests::DSTestContext;
+            use crate::repo::{Column, DataStore, PinStore};
+            use cid::Cid;
+            use std::convert::TryFrom;
+
+            #[tokio::test]
+            async fn column_put_get_remove() {
+                let store = DSTestContext::with($factory).await;
+                let col = Column::Ipns;
+                let key = [1, 2, 3, 4];
+                let value = [5, 6, 7, 8];
+
+                assert_eq!(store.contains(col, &key).await.unwrap(), false);
+                assert_eq!(store.get(col, &key).await.unwrap(), None);
+                store
+                    .remove(col, &key)
+                    .await
+                    .expect("removing a missing key is not an error");
+
+                store.put(col, &key, &value).await.unwrap();
+                assert_eq!(store.contains(col, &key).await.unwrap(), true);
+                assert_eq!(store.get(col, &key).await.unwrap(), Some(value.to_vec()));
+
+                store.remove(col, &key).await.unwrap();
+                assert_eq!(store.contains(col, &key).await.unwrap(), false);
+                assert_eq!(store.get(col, &key).await.unwrap(), None);
+            }
+
+            #[tokio::test]
+            async fn column_put_overwrites() {
+                let store = DSTestContext::with($factory).await;
+                let col = Column::Ipns;
+                let key = b"/ipns/key";
+
+                store.put(col, key, b"first").await.unwrap();
+                store.put(col, key, b"second").await.unwrap();
+
+                assert_eq!(
+                    store.get(col, key).await.unwrap(),
+                    Some(b"second".to_vec())
+                );
+            }
+
+            #[tokio::test]
+            async fn column_keys_are_separate() {
+                let store = DSTestContext::with($factory).await;
+                let col = Column::Ipns;
+
+                store.put(col, &[1], &[1]).await.unwrap();
+                store.put(col, &[1, 1], &[2]).await.unwrap();
+
+                assert_eq!(store.get(col, &[1]).await.unwrap(), Some(vec![1]));
+                assert_eq!(store.get(col, &[1, 1]).await.unwrap(), Some(vec![2]));
+                assert_eq!(store.get(col, &[1, 1, 1]).await.unwrap(), None);
+            }
+
+            #[tokio::test]
+            async fn wipe_clears_columns_and_pins() {
+                let store = DSTestContext::with($factory).await;
+                let col = Column::Ipns;
+                let key = [1, 2, 3, 4];
+
+                let empty =
+                    Cid::try_from("QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH").unwrap();
+
+                store.put(col, &key, &[5, 6, 7, 8]).await.unwrap();
+                store.insert_direct_pin(&empty).await.unwrap();
+
+                store.wipe().await;
+
+                assert_eq!(store.contains(col, &key).await.unwrap(), false);
+                assert_eq!(store.is_pinned(&empty).await.unwrap(), false);
+
+                // the store should be usable after wiping
+                store.put(col, &key, &[9]).await.unwrap();
+                assert_eq!(store.get(col, &key).await.unwrap(), Some(vec![9]));
+                store.insert_direct_pin(&empty).await.unwrap();
+                assert_eq!(store.is_pinned(&empty).await.unwrap(), true);
+            }
+        }
+    };
+}
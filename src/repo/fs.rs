@@ -5,9 +5,8 @@
 use crate::error::Error;
 use async_trait::async_trait;
 use std::fs::File;
-use std::path::PathBuf;
-use std::sync::{atomic::AtomicU64, Arc};
-use tokio::sync::Semaphore;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use super::{BlockRm, BlockRmError, Column, DataStore, Lock, LockError, RepoCid};
 
@@ -20,13 +19,19 @@ pub use blocks::FsBlockStore;
 
 /// Path mangling done for pins and blocks
 mod paths;
-use paths::{block_path, filestem_to_block_cid, filestem_to_pin_cid, pin_path};
+use paths::{block_path, column_path, filestem_to_block_cid, filestem_to_pin_cid, pin_path};
 
 /// FsDataStore which uses the filesystem as a lockable key-value store. Maintains a similar to
 /// [`FsBlockStore`] sharded two level storage. Direct have empty files, recursive pins record all of
 /// their indirect descendants. Pin files are separated by their file extensions.
 ///
-/// When modifying, single lock is used.
+/// Each [`Column`] is a directory of its own next to the pins, with one file per key. See
+/// `column_path` for the naming.
+///
+/// The writers are not serialized with each other: the values and the recursive pins are written
+/// to uniquely named tempfiles and renamed into place, and a direct pin left over next to a
+/// recursive one is ignored by the readers. Writing and garbage collection are kept apart by the
+/// single lock of the [`crate::repo::Repo`], see [`crate::repo::Repo::gc`].
 ///
 /// For the [`crate::repo::PinStore`] implementation see `fs/pinstore.rs`.
 #[derive(Debug)]
@@ -35,32 +40,30 @@ pub struct FsDataStore {
     /// blocks are stored under the shard. See unixfs/examples/cat.rs for read example.
     path: PathBuf,
 
-    /// Start with simple, conservative solution, allows concurrent queries but single writer.
-    /// It is assumed the reads do not require permit as non-empty writes are done through
-    /// tempfiles and the consistency regarding reads is not a concern right now. For garbage
-    /// collection implementation, it might be needed to hold this permit for the duration of
-    /// garbage collection, or something similar.
-    lock: Arc<Semaphore>,
+    /// The base directory of the datastore, under which the column directories are.
+    root: PathBuf,
 
     /// Not really needed
     written_bytes: AtomicU64,
 }
 
-/// The column operations are all unimplemented pending at least downscoping of the
-/// DataStore trait itself.
 #[async_trait]
 impl DataStore for FsDataStore {
-    fn new(mut root: PathBuf) -> Self {
-        root.push("pins");
+    const PERSISTENT: bool = true;
+
+    fn new(root: PathBuf) -> Self {
         FsDataStore {
-            path: root,
-            lock: Arc::new(Semaphore::new(1)),
+            path: root.join("pins"),
+            root,
             written_bytes: Default::default(),
         }
     }
 
     async fn init(&self) -> Result<(), Error> {
         tokio::fs::create_dir_all(&self.path).await?;
+        for col in Column::ALL {
+            tokio::fs::create_dir_all(self.root.join(col.name())).await?;
+        }
         Ok(())
     }
 
@@ -68,27 +71,99 @@ impl DataStore for FsDataStore {
         Ok(())
     }
 
-    async fn contains(&self, _col: Column, _key: &[u8]) -> Result<bool, Error> {
-        Err(anyhow::anyhow!("not implemented"))
+    async fn contains(&self, col: Column, key: &[u8]) -> Result<bool, Error> {
+        let path = column_path(self.root.clone(), col, key);
+
+        match tokio::fs::metadata(path).await {
+            Ok(m) => Ok(m.is_file()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
     }
 
-    async fn get(&self, _col: Column, _key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
-        Err(anyhow::anyhow!("not implemented"))
+    async fn get(&self, col: Column, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        let path = column_path(self.root.clone(), col, key);
+
+        // the values are written through tempfiles so there is no need to synchronize with writes
+        match tokio::fs::read(path).await {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
     }
 
-    async fn put(&self, _col: Column, _key: &[u8], _value: &[u8]) -> Result<(), Error> {
-        Err(anyhow::anyhow!("not implemented"))
+    async fn put(&self, col: Column, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        let path = column_path(self.root.clone(), col, key);
+        let value = value.to_owned();
+
+        let span = tracing::Span::current();
+
+        tokio::task::spawn_blocking(move || {
+            use std::io::Write;
+
+            let _entered = span.enter();
+
+            std::fs::create_dir_all(path.parent().expect("column directory has to exist"))?;
+
+            let temp_path = temp_path(&path, "tmp");
+            let mut temp = File::create(&temp_path)?;
+
+            let written = temp.write_all(&value).and_then(|_| temp.sync_all());
+            drop(temp);
+
+            match written {
+                Ok(()) => std::fs::rename(&temp_path, &path)?,
+                Err(e) => {
+                    if let Err(e) = std::fs::remove_file(&temp_path) {
+                        warn!("failed to cleanup temporary file: {}", e);
+                    }
+                    return Err(e.into());
+                }
+            }
+
+            Ok(())
+        })
+        .await?
     }
 
-    async fn remove(&self, _col: Column, _key: &[u8]) -> Result<(), Error> {
-        Err(anyhow::anyhow!("not implemented"))
+    async fn remove(&self, col: Column, key: &[u8]) -> Result<(), Error> {
+        let path = column_path(self.root.clone(), col, key);
+
+        match tokio::fs::remove_file(path).await {
+            Ok(()) => Ok(()),
+            // like with the other implementations, removing a missing key is not an error
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
     }
 
     async fn wipe(&self) {
-        todo!()
+        let dirs = std::iter::once(self.path.clone())
+            .chain(Column::ALL.iter().map(|col| self.root.join(col.name())));
+
+        for dir in dirs {
+            match tokio::fs::remove_dir_all(&dir).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => warn!("failed to wipe {:?}: {}", dir, e),
+            }
+
+            // keep the datastore usable after wiping
+            if let Err(e) = tokio::fs::create_dir_all(&dir).await {
+                warn!("failed to recreate {:?}: {}", dir, e);
+            }
+        }
     }
 }
 
+/// Returns a path next to the `path` for writing it through a tempfile, unique within the process
+/// so that concurrent writers of the same key do not write to the same tempfile.
+fn temp_path(path: &Path, extension: &str) -> PathBuf {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let n = NEXT.fetch_add(1, Ordering::Relaxed);
+    path.with_extension(format!("{}{}", extension, n))
+}
+
 #[derive(Debug)]
 pub struct FsLock {
     file: Option<File>,
@@ -133,6 +208,9 @@ impl Lock for FsLock {
 #[cfg(test)]
 crate::pinstore_interface_tests!(common_tests, crate::repo::fs::FsDataStore::new);
 
+#[cfg(test)]
+crate::datastore_interface_tests!(datastore_common_tests, crate::repo::fs::FsDataStore::new);
+
 #[cfg(test)]
 mod tests {
     use super::{FsLock, Lock};
@@ -1,3 +1,4 @@
+use crate::repo::Column;
 use cid::Cid;
 use core::convert::TryFrom;
 use std::path::PathBuf;
@@ -57,6 +58,14 @@ pub fn filestem_to_pin_cid(file_stem: Option<&std::ffi::OsStr>) -> Option<Cid> {
     })
 }
 
+/// Path for the value stored under the `key` in the given column. The columns are expected to have
+/// only a few keys, so they are not sharded.
+pub fn column_path(mut base: PathBuf, col: Column, key: &[u8]) -> PathBuf {
+    base.push(col.name());
+    base.push(multibase::encode(multibase::Base::Base32Lower, key));
+    base
+}
+
 /// second-to-last/2 sharding, just by taking the two characters from suffix ignoring the last
 /// character from an ASCII encoded key string to be prepended as the directory or "shard".
 ///
@@ -78,6 +87,7 @@ fn shard(path: &mut PathBuf, key: &str) {
 mod tests {
 
     use super::shard;
+    use crate::repo::Column;
     use cid::Cid;
     use std::convert::TryFrom;
     use std::path::{Path, PathBuf};
@@ -164,6 +174,13 @@ mod tests {
         assert_eq!(super::filestem_to_block_cid(pin_path.file_stem()), None);
     }
 
+    #[test]
+    fn column_key_to_path() {
+        let path = super::column_path(PathBuf::from("some_root"), Column::Ipns, b"foobar");
+
+        assert_eq!(path, Path::new("some_root/ipns/bmzxw6ytboi"));
+    }
+
     #[test]
     fn shard_example() {
         let mut path = PathBuf::from("some_root");
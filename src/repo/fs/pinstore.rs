@@ -1,5 +1,5 @@
 //! Persistent filesystem backed pin store. See [`FsDataStore`] for more information.
-use super::{filestem_to_pin_cid, pin_path, FsDataStore};
+use super::{filestem_to_pin_cid, pin_path, temp_path, FsDataStore};
 use crate::error::Error;
 use crate::repo::{PinKind, PinMode, PinModeRequirement, PinStore, References};
 use async_trait::async_trait;
@@ -8,9 +8,7 @@ use core::convert::TryFrom;
 use futures::stream::TryStreamExt;
 use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
-use std::sync::Arc;
 use tokio::fs;
-use tokio::sync::Semaphore;
 use tokio_stream::{empty, wrappers::ReadDirStream, StreamExt};
 use tokio_util::either::Either;
 
@@ -51,15 +49,11 @@ impl PinStore for FsDataStore {
     }
 
     async fn insert_direct_pin(&self, target: &Cid) -> Result<(), Error> {
-        let permit = Semaphore::acquire_owned(Arc::clone(&self.lock)).await;
-
         let mut path = pin_path(self.path.clone(), target);
 
         let span = tracing::Span::current();
 
         tokio::task::spawn_blocking(move || {
-            // move the permit to the blocking thread to ensure we keep it as long as needed
-            let _permit = permit;
             let _entered = span.enter();
 
             std::fs::create_dir_all(path.parent().expect("shard parent has to exist"))?;
@@ -87,31 +81,28 @@ impl PinStore for FsDataStore {
             .try_collect::<std::collections::BTreeSet<_>>()
             .await?;
 
-        let permit = Semaphore::acquire_owned(Arc::clone(&self.lock)).await;
-
         let mut path = pin_path(self.path.clone(), target);
 
         let span = tracing::Span::current();
 
         tokio::task::spawn_blocking(move || {
-            let _permit = permit; // again move to the threadpool thread
             let _entered = span.enter();
 
             std::fs::create_dir_all(path.parent().expect("shard parent has to exist"))?;
             let count = set.len();
             let cids = set.into_iter().map(|cid| cid.to_string());
 
-            path.set_extension("recursive_temp");
+            let temp_path = temp_path(&path, "recursive_temp");
 
-            let file = std::fs::File::create(&path)?;
+            let file = std::fs::File::create(&temp_path)?;
 
             match sync_write_recursive_pin(file, count, cids) {
                 Ok(_) => {
                     let final_path = path.with_extension("recursive");
-                    std::fs::rename(&path, final_path)?
+                    std::fs::rename(&temp_path, final_path)?
                 }
                 Err(e) => {
-                    let removed = std::fs::remove_file(&path);
+                    let removed = std::fs::remove_file(&temp_path);
 
                     match removed {
                         Ok(_) => debug!("cleaned up ok after botched recursive pin write"),
@@ -146,14 +137,11 @@ impl PinStore for FsDataStore {
     }
 
     async fn remove_direct_pin(&self, target: &Cid) -> Result<(), Error> {
-        let permit = Semaphore::acquire_owned(Arc::clone(&self.lock)).await;
-
         let mut path = pin_path(self.path.clone(), target);
 
         let span = tracing::Span::current();
 
         tokio::task::spawn_blocking(move || {
-            let _permit = permit; // move in to threadpool thread
             let _entered = span.enter();
 
             path.set_extension("recursive");
@@ -181,14 +169,11 @@ impl PinStore for FsDataStore {
     }
 
     async fn remove_recursive_pin(&self, target: &Cid, _: References<'_>) -> Result<(), Error> {
-        let permit = Semaphore::acquire_owned(Arc::clone(&self.lock)).await;
-
         let mut path = pin_path(self.path.clone(), target);
 
         let span = tracing::Span::current();
 
         tokio::task::spawn_blocking(move || {
-            let _permit = permit; // move into threadpool thread
             let _entered = span.enter();
 
             path.set_extension("direct");
@@ -527,7 +512,7 @@ fn sync_read_direct_or_recursive(block_path: &mut PathBuf) -> Option<PinMode> {
     ] {
         block_path.set_extension(ext);
         // Path::is_file calls fstat and coerces errors to false; this might be enough, as
-        // we are holding the lock
+        // the pin files are written through tempfiles
         if block_path.is_file() {
             return Some(*mode);
         }
@@ -11,20 +11,25 @@ use sled::{
         ConflictableTransactionError, TransactionError, TransactionResult, TransactionalTree,
         UnabortableTransactionError,
     },
-    Config as DbConfig, Db, Mode as DbMode,
+    Config as DbConfig, Db, Mode as DbMode, Tree,
 };
 use std::collections::BTreeSet;
 use std::convert::Infallible;
 use std::path::PathBuf;
 use std::str::{self, FromStr};
 
-/// [`sled`] based pinstore implementation. Implements datastore which errors for each call.
-/// Currently feature-gated behind `sled_data_store` feature in the [`crate::Types`], usable
-/// directly in custom type configurations.
+/// The KvBlockStore implementation
+mod blocks;
+pub use blocks::KvBlockStore;
+
+/// [`sled`] based pinstore and datastore implementation. Currently feature-gated behind
+/// `sled_data_store` feature in the [`crate::Types`], usable directly in custom type
+/// configurations.
 ///
 /// Current schema is to use the the default tree for storing pins, which are serialized as
 /// [`get_pin_key`]. Depending on the kind of pin values are generated by [`direct_value`],
-/// [`recursive_value`], and [`indirect_value`].
+/// [`recursive_value`], and [`indirect_value`]. Each [`Column`] is stored in a tree of its own,
+/// named after the column, keeping the keys and values as they are given.
 ///
 /// [`sled`]: https://github.com/spacejam/sled
 #[derive(Debug)]
@@ -38,10 +43,18 @@ impl KvDataStore {
     fn get_db(&self) -> &Db {
         self.db.get().unwrap()
     }
+
+    /// Returns the tree used for the column. The trees are opened already in `init` so this is
+    /// only a lookup.
+    fn get_tree(&self, col: Column) -> Result<Tree, Error> {
+        Ok(self.get_db().open_tree(col.name())?)
+    }
 }
 
 #[async_trait]
 impl DataStore for KvDataStore {
+    const PERSISTENT: bool = true;
+
     fn new(root: PathBuf) -> KvDataStore {
         KvDataStore {
             path: root,
@@ -57,6 +70,10 @@ impl DataStore for KvDataStore {
             .path(self.path.as_path())
             .open()?;
 
+        for col in Column::ALL {
+            db.open_tree(col.name())?;
+        }
+
         match self.db.set(db) {
             Ok(()) => Ok(()),
             Err(_) => Err(anyhow::anyhow!("failed to init sled")),
@@ -68,28 +85,80 @@ impl DataStore for KvDataStore {
     }
 
     /// Checks if a key is present in the datastore.
-    async fn contains(&self, _col: Column, _key: &[u8]) -> Result<bool, Error> {
-        Err(anyhow::anyhow!("not implemented"))
+    async fn contains(&self, col: Column, key: &[u8]) -> Result<bool, Error> {
+        let tree = self.get_tree(col)?;
+        let key = key.to_owned();
+
+        tokio::task::spawn_blocking(move || Ok(tree.contains_key(key)?)).await?
     }
 
     /// Returns the value associated with a key from the datastore.
-    async fn get(&self, _col: Column, _key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
-        Err(anyhow::anyhow!("not implemented"))
+    async fn get(&self, col: Column, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        let tree = self.get_tree(col)?;
+        let key = key.to_owned();
+
+        tokio::task::spawn_blocking(move || Ok(tree.get(key)?.map(|value| value.to_vec())))
+            .await?
     }
 
     /// Puts the value under the key in the datastore.
-    async fn put(&self, _col: Column, _key: &[u8], _value: &[u8]) -> Result<(), Error> {
-        Err(anyhow::anyhow!("not implemented"))
+    async fn put(&self, col: Column, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        let tree = self.get_tree(col)?;
+        let key = key.to_owned();
+        let value = value.to_owned();
+
+        let span = tracing::Span::current();
+
+        tokio::task::spawn_blocking(move || {
+            let span = tracing::trace_span!(parent: &span, "blocking");
+            let _g = span.enter();
+
+            tree.insert(key, value)?;
+            tree.flush()?;
+            Ok(())
+        })
+        .await?
     }
 
     /// Removes a key-value pair from the datastore.
-    async fn remove(&self, _col: Column, _key: &[u8]) -> Result<(), Error> {
-        Err(anyhow::anyhow!("not implemented"))
+    async fn remove(&self, col: Column, key: &[u8]) -> Result<(), Error> {
+        let tree = self.get_tree(col)?;
+        let key = key.to_owned();
+
+        let span = tracing::Span::current();
+
+        tokio::task::spawn_blocking(move || {
+            let span = tracing::trace_span!(parent: &span, "blocking");
+            let _g = span.enter();
+
+            tree.remove(key)?;
+            tree.flush()?;
+            Ok(())
+        })
+        .await?
     }
 
     /// Wipes the datastore.
     async fn wipe(&self) {
-        todo!()
+        let db = self.get_db().to_owned();
+
+        let res = tokio::task::spawn_blocking(move || {
+            for col in Column::ALL {
+                db.open_tree(col.name())?.clear()?;
+            }
+
+            // the pins are stored in the default tree
+            db.clear()?;
+            db.flush()?;
+            Ok::<_, Error>(())
+        })
+        .await;
+
+        match res {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!("failed to wipe the datastore: {}", e),
+            Err(e) => warn!("failed to wipe the datastore: {}", e),
+        }
     }
 }
 
@@ -524,3 +593,6 @@ fn is_not_pinned_or_pinned_indirectly(
 
 #[cfg(test)]
 crate::pinstore_interface_tests!(common_tests, crate::repo::kv::KvDataStore::new);
+
+#[cfg(test)]
+crate::datastore_interface_tests!(datastore_common_tests, crate::repo::kv::KvDataStore::new);
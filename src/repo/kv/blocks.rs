@@ -0,0 +1,338 @@
+//! [`sled`] backed block store. See [`KvBlockStore`] for more information.
+use crate::error::Error;
+use crate::repo::{BlockPut, BlockRm, BlockRmError, BlockStore};
+use crate::Block;
+use async_trait::async_trait;
+use cid::Cid;
+use futures::stream::{BoxStream, StreamExt, TryStreamExt};
+use once_cell::sync::OnceCell;
+use sled::{Config as DbConfig, Db, Mode as DbMode};
+use std::convert::TryFrom;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio_stream::wrappers::ReceiverStream;
+
+/// How many listed blocks can be buffered ahead of the consumer of [`KvBlockStore::list_stream`].
+const LIST_BATCH: usize = 1024;
+
+/// [`sled`] based block store, storing all of the blocks in the default tree of a database of its
+/// own. Suited better than [`crate::repo::fs::FsBlockStore`] for large amounts of small blocks.
+///
+/// Blocks are keyed by the binary representation of the Cid converted to version 1, so that the
+/// same block stored as CIDv0 and CIDv1 will be found with either one, similar to how
+/// `FsBlockStore` names the files.
+///
+/// New blocks and removals are flushed before returning, like the pins of
+/// [`crate::repo::kv::KvDataStore`], so that a pin written after a put never outlives the block.
+///
+/// [`sled`]: https://github.com/spacejam/sled
+#[derive(Debug)]
+pub struct KvBlockStore {
+    path: PathBuf,
+    // see KvDataStore for the same trick
+    db: OnceCell<Db>,
+
+    /// Bytes written as new blocks since the store was created; like for
+    /// [`crate::repo::fs::FsBlockStore`], this does not include the existing blocks.
+    written_bytes: AtomicU64,
+}
+
+impl KvBlockStore {
+    fn get_db(&self) -> &Db {
+        self.db.get().unwrap()
+    }
+}
+
+#[async_trait]
+impl BlockStore for KvBlockStore {
+    fn new(path: PathBuf) -> Self {
+        KvBlockStore {
+            path,
+            db: Default::default(),
+            written_bytes: Default::default(),
+        }
+    }
+
+    async fn init(&self) -> Result<(), Error> {
+        let config = DbConfig::new();
+
+        let db = config
+            .mode(DbMode::HighThroughput)
+            .path(self.path.as_path())
+            .open()?;
+
+        match self.db.set(db) {
+            Ok(()) => Ok(()),
+            Err(_) => Err(anyhow::anyhow!("failed to init sled")),
+        }
+    }
+
+    async fn open(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn contains(&self, cid: &Cid) -> Result<bool, Error> {
+        let key = block_key(cid);
+        let db = self.get_db().to_owned();
+
+        tokio::task::spawn_blocking(move || Ok(db.contains_key(key)?)).await?
+    }
+
+    async fn get(&self, cid: &Cid) -> Result<Option<Block>, Error> {
+        let key = block_key(cid);
+        let cid = cid.to_owned();
+        let db = self.get_db().to_owned();
+
+        let span = tracing::trace_span!("get block", cid = %cid);
+
+        tokio::task::spawn_blocking(move || {
+            let _g = span.enter();
+
+            let block = db
+                .get(key)?
+                .map(|data| Block::new(data.to_vec().into_boxed_slice(), cid));
+
+            Ok(block)
+        })
+        .await?
+    }
+
+    async fn put(&self, block: Block) -> Result<(Cid, BlockPut), Error> {
+        let key = block_key(block.cid());
+        let Block { cid, data } = block;
+        let db = self.get_db().to_owned();
+
+        let span = tracing::trace_span!("put block", cid = %cid);
+
+        let (written, res) = tokio::task::spawn_blocking(move || {
+            let _g = span.enter();
+
+            let len = data.len();
+
+            // compare_and_swap makes sure only one of the concurrent writers will be the one
+            // writing the block, the others will see it as existing.
+            let res = match db.compare_and_swap(key, None as Option<&[u8]>, Some(&*data))? {
+                Ok(()) => {
+                    trace!(bytes = len, "new block");
+                    db.flush()?;
+                    (len, BlockPut::NewBlock)
+                }
+                Err(_) => {
+                    trace!("already existing block");
+                    (0, BlockPut::Existed)
+                }
+            };
+
+            Ok::<_, Error>(res)
+        })
+        .await??;
+
+        self.written_bytes
+            .fetch_add(written as u64, Ordering::SeqCst);
+
+        Ok((cid, res))
+    }
+
+    async fn remove(&self, cid: &Cid) -> Result<Result<BlockRm, BlockRmError>, Error> {
+        let key = block_key(cid);
+        let cid = cid.to_owned();
+        let db = self.get_db().to_owned();
+
+        tokio::task::spawn_blocking(move || match db.remove(key)? {
+            Some(_) => {
+                db.flush()?;
+                Ok(Ok(BlockRm::Removed(cid)))
+            }
+            None => Ok(Err(BlockRmError::NotFound(cid))),
+        })
+        .await?
+    }
+
+    async fn list(&self) -> Result<Vec<Cid>, Error> {
+        self.list_stream().try_collect().await
+    }
+
+    fn list_stream(&self) -> BoxStream<'_, Result<Cid, Error>> {
+        let db = self.get_db().to_owned();
+        let (tx, rx) = tokio::sync::mpsc::channel(LIST_BATCH);
+
+        // the tree is iterated on a blocking thread until the receiving stream is dropped
+        tokio::task::spawn_blocking(move || {
+            for key in db.iter().keys() {
+                let cid = key.map_err(Error::from).and_then(|key| {
+                    Cid::try_from(&*key).map_err(|e| {
+                        Error::new(e).context(format!("invalid block key: {:?}", &*key))
+                    })
+                });
+
+                if tx.blocking_send(cid).is_err() {
+                    break;
+                }
+            }
+        });
+
+        ReceiverStream::new(rx).boxed()
+    }
+
+    async fn wipe(&self) {
+        let db = self.get_db().to_owned();
+
+        let res = tokio::task::spawn_blocking(move || {
+            db.clear()?;
+            db.flush()?;
+            Ok::<_, Error>(())
+        })
+        .await;
+
+        match res {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!("failed to wipe the blockstore: {}", e),
+            Err(e) => warn!("failed to wipe the blockstore: {}", e),
+        }
+    }
+}
+
+/// The key is the binary representation of the CIDv1 of the block.
+fn block_key(cid: &Cid) -> Vec<u8> {
+    if cid.version() == cid::Version::V1 {
+        cid.to_bytes()
+    } else {
+        Cid::new_v1(cid.codec(), cid.hash().to_owned()).to_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cid::Codec;
+    use hex_literal::hex;
+    use multihash::Sha2_256;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    async fn blockstore() -> (TempDir, KvBlockStore) {
+        let tmp = TempDir::new().unwrap();
+        let store = KvBlockStore::new(tmp.path().join("blockstore"));
+        store.init().await.unwrap();
+        store.open().await.unwrap();
+        (tmp, store)
+    }
+
+    #[tokio::test]
+    async fn test_kv_blockstore() {
+        let (_tmp, store) = blockstore().await;
+
+        let data = b"1".to_vec().into_boxed_slice();
+        let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
+        let block = Block::new(data, cid.clone());
+
+        assert_eq!(store.contains(&cid).await.unwrap(), false);
+        assert_eq!(store.get(&cid).await.unwrap(), None);
+        if store.remove(&cid).await.unwrap().is_ok() {
+            panic!("block should not be found")
+        }
+
+        let put = store.put(block.clone()).await.unwrap();
+        assert_eq!(put, (cid.clone(), BlockPut::NewBlock));
+        assert_eq!(store.contains(&cid).await.unwrap(), true);
+        assert_eq!(store.get(&cid).await.unwrap(), Some(block.clone()));
+
+        let put = store.put(block.clone()).await.unwrap();
+        assert_eq!(put, (cid.clone(), BlockPut::Existed));
+
+        store.remove(&cid).await.unwrap().unwrap();
+        assert_eq!(store.contains(&cid).await.unwrap(), false);
+        assert_eq!(store.get(&cid).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn cidv0_and_cidv1_are_the_same_block() {
+        let (_tmp, store) = blockstore().await;
+
+        let cid = Cid::try_from("QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL").unwrap();
+        let data = hex!("0a0d08021207666f6f6261720a1807");
+
+        store
+            .put(Block {
+                cid: cid.clone(),
+                data: data.into(),
+            })
+            .await
+            .unwrap();
+
+        let cid_v1 = Cid::new_v1(cid.codec(), cid.hash().to_owned());
+
+        let block = store.get(&cid_v1).await.unwrap().unwrap();
+        assert_eq!(block.cid(), &cid_v1);
+        assert_eq!(&*block.data(), &data[..]);
+
+        // listing gives out the v1 as it's the stored one
+        assert_eq!(store.list().await.unwrap(), vec![cid_v1]);
+    }
+
+    #[tokio::test]
+    async fn test_kv_blockstore_list_and_wipe() {
+        let (_tmp, store) = blockstore().await;
+
+        for data in &[b"1", b"2", b"3"] {
+            let data_slice = data.to_vec().into_boxed_slice();
+            let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data_slice));
+            let block = Block::new(data_slice, cid);
+            store.put(block.clone()).await.unwrap();
+        }
+
+        let cids = store.list().await.unwrap();
+        assert_eq!(cids.len(), 3);
+        for cid in cids.iter() {
+            assert!(store.contains(cid).await.unwrap());
+        }
+
+        store.wipe().await;
+        assert!(store.list().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn race_to_insert_new() {
+        let (_tmp, store) = blockstore().await;
+        let store = Arc::new(store);
+
+        let cid = Cid::try_from("QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL").unwrap();
+        let data = hex!("0a0d08021207666f6f6261720a1807");
+
+        let block = Block {
+            cid,
+            data: data.into(),
+        };
+
+        let count = 10;
+        let barrier = Arc::new(tokio::sync::Barrier::new(count));
+
+        let join_handles = (0..count)
+            .map(|_| {
+                tokio::spawn({
+                    let store = Arc::clone(&store);
+                    let barrier = Arc::clone(&barrier);
+                    let block = block.clone();
+                    async move {
+                        barrier.wait().await;
+                        store.put(block).await
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut writes = 0usize;
+        let mut existing = 0usize;
+
+        for jh in join_handles {
+            match jh.await.unwrap().unwrap() {
+                (_, BlockPut::NewBlock) => writes += 1,
+                (_, BlockPut::Existed) => existing += 1,
+            }
+        }
+
+        assert_eq!(writes, 1);
+        assert_eq!(existing, count - 1);
+        assert_eq!(store.written_bytes.load(Ordering::SeqCst), 15);
+    }
+}
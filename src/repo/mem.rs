@@ -677,6 +677,9 @@ impl Lock for MemLock {
 #[cfg(test)]
 crate::pinstore_interface_tests!(common_tests, crate::repo::mem::MemDataStore::new);
 
+#[cfg(test)]
+crate::datastore_interface_tests!(datastore_common_tests, crate::repo::mem::MemDataStore::new);
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -2,16 +2,20 @@
 use async_trait::async_trait;
 use cid::Cid;
 use core::fmt::Debug;
+use futures::stream::{BoxStream, StreamExt, TryStreamExt};
 use std::borrow::Borrow;
+use std::collections::HashSet;
 use std::hash::{Hash, Hasher};
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use std::{error, fmt, io};
+use tokio::sync::{Semaphore, SemaphorePermit};
 
 use libp2p_rs::core::PeerId;
 
 use crate::error::Error;
-use crate::path::IpfsPath;
+use crate::ipns::record::IpnsRecord;
 use crate::{Block, BsBlockStore, IpfsOptions};
 
 #[macro_use]
@@ -29,6 +33,10 @@ pub trait RepoTypes: Send + Sync + 'static {
     /// Describes a datastore.
     type TDataStore: DataStore;
     type TLock: Lock;
+    /// Whether the repo is kept on disk under [`crate::IpfsOptions::ipfs_path`]. Only then are the
+    /// records of the DHT, the named keys of the keystore and the list of bootstrap nodes kept
+    /// there as well, so that they survive restarts. Defaults to [`DataStore::PERSISTENT`].
+    const PERSISTENT: bool = <Self::TDataStore as DataStore>::PERSISTENT;
 }
 
 /// Configuration for a repo.
@@ -106,6 +114,15 @@ pub trait BlockStore: Debug + Send + Sync + Unpin + 'static {
     async fn remove(&self, cid: &Cid) -> Result<Result<BlockRm, BlockRmError>, Error>;
     /// Returns a list of the blocks (Cids), in the blockstore.
     async fn list(&self) -> Result<Vec<Cid>, Error>;
+    /// Returns the blocks (Cids) in the blockstore as a stream. Stores which can iterate their
+    /// blocks without first collecting all of them should override the default, which lists
+    /// them with [`BlockStore::list`].
+    fn list_stream(&self) -> BoxStream<'_, Result<Cid, Error>> {
+        futures::stream::once(self.list())
+            .map_ok(|cids| futures::stream::iter(cids.into_iter().map(Ok)))
+            .try_flatten()
+            .boxed()
+    }
     /// Wipes the blockstore.
     async fn wipe(&self);
 }
@@ -113,6 +130,9 @@ pub trait BlockStore: Debug + Send + Sync + Unpin + 'static {
 #[async_trait]
 /// Generic layer of abstraction for a key-value data store.
 pub trait DataStore: PinStore + Debug + Send + Sync + Unpin + 'static {
+    /// Whether the datastore is kept on disk under the path given to [`DataStore::new`].
+    const PERSISTENT: bool = false;
+
     fn new(path: PathBuf) -> Self;
     async fn init(&self) -> Result<(), Error>;
     async fn open(&self) -> Result<(), Error>;
@@ -222,6 +242,20 @@ pub enum Column {
     Ipns,
 }
 
+impl Column {
+    /// All of the columns, useful for the implementations which need to initialize or wipe the
+    /// storage of each column.
+    const ALL: &'static [Column] = &[Column::Ipns];
+
+    /// Stable name for the column, used by the persistent implementations for naming the storage,
+    /// so this must not change.
+    fn name(&self) -> &'static str {
+        match self {
+            Column::Ipns => "ipns",
+        }
+    }
+}
+
 /// `PinMode` is the description of pin type for quering purposes.
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum PinMode {
@@ -321,11 +355,20 @@ impl<T: RepoTypes> Clone for Repo<T> {
     }
 }
 
+/// The number of permits of the gc lock, which is the most puts and pins which can run at once.
+const GC_PERMITS: u32 = 1 << 16;
+
+/// How often [`Repo::gc`] tries to take all of the permits of the gc lock.
+const GC_LOCK_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
 #[derive(Debug)]
 struct RepoBase<TRepoTypes: RepoTypes> {
     block_store: TRepoTypes::TBlockStore,
     data_store: TRepoTypes::TDataStore,
     lockfile: Arc<Mutex<TRepoTypes::TLock>>,
+    /// The single lock between writing and garbage collection: every put and pin takes a permit,
+    /// while [`Repo::gc`] takes all of them for the duration of both the mark and the sweep.
+    gc_lock: Semaphore,
 }
 
 impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
@@ -345,6 +388,7 @@ impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
             block_store,
             data_store,
             lockfile: Arc::new(Mutex::new(lockfile)),
+            gc_lock: Semaphore::new(GC_PERMITS as usize),
         }))
     }
 
@@ -382,6 +426,7 @@ impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
 
     /// Puts a block into the block store.
     pub async fn put_block(&self, block: Block) -> Result<(Cid, BlockPut), Error> {
+        let _permit = self.gc_permit().await;
         let cid = block.cid.clone();
         let (_cid, res) = self.0.block_store.put(block).await?;
         Ok((cid, res))
@@ -422,36 +467,30 @@ impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
         }
     }
 
-    /// Get an ipld path from the datastore.
-    pub async fn get_ipns(&self, ipns: &PeerId) -> Result<Option<IpfsPath>, Error> {
-        use std::str::FromStr;
-
+    /// Get the IPNS record of the peer from the datastore. The record is not validated, so it
+    /// may have expired since it was stored.
+    pub async fn get_ipns(&self, ipns: &PeerId) -> Result<Option<IpnsRecord>, Error> {
         let data_store = &self.0.data_store;
         let key = ipns.to_owned();
         // FIXME: needless vec<u8> creation
         let bytes = data_store.get(Column::Ipns, &key.to_bytes()[..]).await?;
         match bytes {
-            Some(ref bytes) => {
-                let string = String::from_utf8_lossy(bytes);
-                let path = IpfsPath::from_str(&string)?;
-                Ok(Some(path))
-            }
+            Some(ref bytes) => Ok(Some(IpnsRecord::from_bytes(bytes)?)),
             None => Ok(None),
         }
     }
 
-    /// Put an ipld path into the datastore.
-    pub async fn put_ipns(&self, ipns: &PeerId, path: &IpfsPath) -> Result<(), Error> {
-        let string = path.to_string();
-        let value = string.as_bytes();
+    /// Put an IPNS record of the peer into the datastore, replacing any earlier one.
+    pub async fn put_ipns(&self, ipns: &PeerId, record: &IpnsRecord) -> Result<(), Error> {
+        let value = record.to_bytes();
         // FIXME: needless vec<u8> creation
         self.0
             .data_store
-            .put(Column::Ipns, &ipns.to_bytes()[..], value)
+            .put(Column::Ipns, &ipns.to_bytes()[..], &value)
             .await
     }
 
-    /// Remove an ipld path from the datastore.
+    /// Remove an IPNS record from the datastore.
     pub async fn remove_ipns(&self, ipns: &PeerId) -> Result<(), Error> {
         // FIXME: us needing to clone the peerid is wasteful to pass it as a reference only to be
         // cloned again
@@ -463,21 +502,25 @@ impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
 
     /// Inserts a direct pin for a `Cid`.
     pub async fn insert_direct_pin(&self, cid: &Cid) -> Result<(), Error> {
+        let _permit = self.gc_permit().await;
         self.0.data_store.insert_direct_pin(cid).await
     }
 
     /// Inserts a recursive pin for a `Cid`.
     pub async fn insert_recursive_pin(&self, cid: &Cid, refs: References<'_>) -> Result<(), Error> {
+        let _permit = self.gc_permit().await;
         self.0.data_store.insert_recursive_pin(cid, refs).await
     }
 
     /// Removes a direct pin for a `Cid`.
     pub async fn remove_direct_pin(&self, cid: &Cid) -> Result<(), Error> {
+        let _permit = self.gc_permit().await;
         self.0.data_store.remove_direct_pin(cid).await
     }
 
     /// Removes a recursive pin for a `Cid`.
     pub async fn remove_recursive_pin(&self, cid: &Cid, refs: References<'_>) -> Result<(), Error> {
+        let _permit = self.gc_permit().await;
         // FIXME: not really sure why is there not an easier way to to transfer control
         self.0.data_store.remove_recursive_pin(cid, refs).await
     }
@@ -501,6 +544,67 @@ impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
     ) -> Result<Vec<(Cid, PinKind<Cid>)>, Error> {
         self.0.data_store.query(cids, requirement).await
     }
+
+    /// Keeps [`Repo::gc`] from starting until the returned permit is dropped. Every put and pin
+    /// takes one, and one can be held over a sequence of them, such as putting the blocks of a
+    /// DAG and then pinning it, so that the blocks cannot be swept in between.
+    ///
+    /// Taking a permit never waits for a pending [`Repo::gc`], only for one which is running, so
+    /// the holders of a permit can take more of them.
+    pub async fn gc_permit(&self) -> SemaphorePermit<'_> {
+        self.0
+            .gc_lock
+            .acquire()
+            .await
+            .expect("gc semaphore is never closed")
+    }
+
+    /// Mark-and-sweep garbage collection: removes every block which is not pinned directly,
+    /// recursively or indirectly, yielding the removed `Cid`s as they are removed.
+    ///
+    /// Runs while no permit from [`Repo::gc_permit`] is held, and holds off new permits until the
+    /// returned stream completes or is dropped, so the blocks put and pinned by the holders of a
+    /// permit are never swept. Blocks are compared by their multihash, so a block is kept if it
+    /// has been pinned with any codec or `Cid` version.
+    pub fn gc(&self) -> BoxStream<'_, Result<Cid, Error>> {
+        let st = async_stream::try_stream! {
+            // waiting in the queue of the semaphore would keep the holders of a permit from
+            // taking another one, which they may need before releasing theirs
+            let _permits = loop {
+                match self.0.gc_lock.try_acquire_many(GC_PERMITS) {
+                    Ok(permits) => break permits,
+                    Err(_) => tokio::time::sleep(GC_LOCK_POLL_INTERVAL).await,
+                }
+            };
+
+            // mark
+            let mut live = HashSet::new();
+            let mut pins = self.0.data_store.list(None).await;
+
+            while let Some((cid, _mode)) = pins.try_next().await? {
+                live.insert(RepoCid(cid));
+            }
+
+            trace!(live = live.len(), "marked pinned blocks");
+
+            // sweep
+            let mut blocks = self.0.block_store.list_stream();
+
+            while let Some(cid) = blocks.try_next().await? {
+                if live.contains(&RepoCid(cid.clone())) {
+                    continue;
+                }
+
+                match self.0.block_store.remove(&cid).await? {
+                    Ok(BlockRm::Removed(cid)) => yield cid,
+                    // someone else already removed it
+                    Err(BlockRmError::NotFound(_)) => {}
+                }
+            }
+        };
+
+        st.boxed()
+    }
 }
 
 // for bitswap
@@ -515,6 +619,7 @@ impl<TRepoTypes: RepoTypes> BsBlockStore for Repo<TRepoTypes> {
     }
 
     async fn put(&self, block: Block) -> Result<(Cid, bool), Box<dyn error::Error>> {
+        let _permit = self.gc_permit().await;
         self.0
             .block_store
             .put(block)
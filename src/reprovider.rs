@@ -0,0 +1,360 @@
+//! Periodic republishing of the provider records for the local content.
+//!
+//! Provider records expire in the DHT, so the node needs to announce the content it has again
+//! every now and then for others to keep finding it. The [`Reprovider`] does this in a background
+//! task, on an interval and when triggered with [`crate::Ipfs::reprovide`].
+use std::collections::HashSet;
+use std::fmt;
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use cid::Cid;
+use futures::channel::{mpsc, oneshot};
+use futures::{SinkExt, StreamExt, TryStreamExt};
+use libp2p_rs::kad::Control as KadControl;
+use tokio::time::{sleep_until, timeout, Instant};
+use tracing::Span;
+use tracing_futures::Instrument;
+
+use crate::error::Error;
+use crate::repo::{PinMode, Repo, RepoTypes};
+
+/// The delay before the first reprovide after the node has started.
+const INITIAL_DELAY: Duration = Duration::from_secs(60);
+
+/// The number of provider records published at a time.
+const PROVIDE_CONCURRENCY: usize = 32;
+
+/// The default time after which a single provider record is counted as failed.
+const PROVIDE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Which of the local blocks are announced by the [`Reprovider`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReprovideStrategy {
+    /// All of the blocks in the blockstore.
+    All,
+    /// All of the pinned blocks, including the blocks pinned indirectly by a recursive pin.
+    Pinned,
+    /// Only the directly and recursively pinned roots.
+    Roots,
+}
+
+impl FromStr for ReprovideStrategy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(ReprovideStrategy::All),
+            "pinned" => Ok(ReprovideStrategy::Pinned),
+            "roots" => Ok(ReprovideStrategy::Roots),
+            other => Err(anyhow::anyhow!("unknown reprovide strategy: {:?}", other)),
+        }
+    }
+}
+
+impl fmt::Display for ReprovideStrategy {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ReprovideStrategy::All => "all",
+            ReprovideStrategy::Pinned => "pinned",
+            ReprovideStrategy::Roots => "roots",
+        };
+        fmt.write_str(s)
+    }
+}
+
+/// Configuration of the [`Reprovider`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReproviderOptions {
+    /// Which blocks to announce.
+    pub strategy: ReprovideStrategy,
+    /// The interval between the announcements, or `None` for announcing only when triggered with
+    /// [`crate::Ipfs::reprovide`].
+    pub interval: Option<Duration>,
+    /// The time after which announcing a single block is counted as failed.
+    pub provide_timeout: Duration,
+}
+
+impl Default for ReproviderOptions {
+    /// The go-ipfs defaults: all blocks every 12 hours.
+    fn default() -> Self {
+        ReproviderOptions {
+            strategy: ReprovideStrategy::All,
+            interval: Some(Duration::from_secs(12 * 60 * 60)),
+            provide_timeout: PROVIDE_TIMEOUT,
+        }
+    }
+}
+
+/// The number of provider records published.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReprovideStats {
+    /// The number of completed reprovides.
+    pub runs: u64,
+    /// The number of blocks announced successfully.
+    pub provided: u64,
+    /// The number of blocks which failed to be announced.
+    pub failed: u64,
+}
+
+impl ReprovideStats {
+    fn add(&mut self, other: &ReprovideStats) {
+        self.runs += other.runs;
+        self.provided += other.provided;
+        self.failed += other.failed;
+    }
+}
+
+type Trigger = oneshot::Sender<Result<ReprovideStats, Error>>;
+
+/// Handle to the background task announcing the local content. The task exits once all of the
+/// handles have been dropped.
+#[derive(Clone, Debug)]
+pub struct Reprovider {
+    tx: mpsc::Sender<Trigger>,
+    totals: Arc<Mutex<ReprovideStats>>,
+}
+
+impl Reprovider {
+    /// Spawns the background task.
+    pub(crate) fn start<T: RepoTypes>(
+        repo: Repo<T>,
+        kad: KadControl,
+        options: ReproviderOptions,
+        span: Span,
+    ) -> Self {
+        let (tx, rx) = mpsc::channel(1);
+        let totals = Arc::new(Mutex::new(ReprovideStats::default()));
+
+        tokio::spawn(run(repo, kad, options, rx, Arc::clone(&totals)).instrument(span));
+
+        Reprovider { tx, totals }
+    }
+
+    /// Announces the blocks right away, returning the number of records provided by this run.
+    pub async fn reprovide(&self) -> Result<ReprovideStats, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .clone()
+            .send(tx)
+            .await
+            .map_err(|_| anyhow::anyhow!("reprovider has stopped"))?;
+        rx.await?
+    }
+
+    /// Returns the number of records provided since the node was started.
+    pub fn stats(&self) -> ReprovideStats {
+        *self.totals.lock().unwrap()
+    }
+}
+
+async fn run<T: RepoTypes>(
+    repo: Repo<T>,
+    kad: KadControl,
+    options: ReproviderOptions,
+    mut rx: mpsc::Receiver<Trigger>,
+    totals: Arc<Mutex<ReprovideStats>>,
+) {
+    let mut next = options
+        .interval
+        .map(|interval| Instant::now() + INITIAL_DELAY.min(interval));
+
+    loop {
+        let trigger = match next {
+            Some(at) => tokio::select! {
+                _ = sleep_until(at) => None,
+                trigger = rx.next() => match trigger {
+                    Some(trigger) => Some(trigger),
+                    None => break,
+                },
+            },
+            None => match rx.next().await {
+                Some(trigger) => Some(trigger),
+                None => break,
+            },
+        };
+
+        let res = reprovide(&repo, &kad, &options).await;
+
+        match &res {
+            Ok(stats) => {
+                debug!(
+                    provided = stats.provided,
+                    failed = stats.failed,
+                    "reprovided with {} strategy",
+                    options.strategy
+                );
+                totals.lock().unwrap().add(stats);
+            }
+            Err(e) => warn!("reprovide failed: {}", e),
+        }
+
+        if let Some(trigger) = trigger {
+            let _ = trigger.send(res);
+        }
+
+        next = options.interval.map(|interval| Instant::now() + interval);
+    }
+
+    trace!("reprovider stopped");
+}
+
+async fn reprovide<T: RepoTypes>(
+    repo: &Repo<T>,
+    kad: &KadControl,
+    options: &ReproviderOptions,
+) -> Result<ReprovideStats, Error> {
+    let strategy = options.strategy;
+    let cids = match strategy {
+        ReprovideStrategy::All => repo.list_blocks().await?,
+        ReprovideStrategy::Pinned | ReprovideStrategy::Roots => {
+            let mut cids = HashSet::new();
+            let mut pins = repo.list_pins(None).await;
+
+            while let Some((cid, mode)) = pins.try_next().await? {
+                if strategy == ReprovideStrategy::Roots && mode == PinMode::Indirect {
+                    continue;
+                }
+                cids.insert(cid);
+            }
+
+            cids.into_iter().collect::<Vec<Cid>>()
+        }
+    };
+
+    let provide = |cid: Cid| {
+        let mut kad = kad.clone();
+        async move { kad.provide(cid.to_bytes()).await }
+    };
+
+    Ok(provide_all(cids, provide, options.provide_timeout).await)
+}
+
+/// Publishes the provider records with at most [`PROVIDE_CONCURRENCY`] in flight, counting the
+/// records not published within `limit` as failed.
+async fn provide_all<F, Fut, E>(cids: Vec<Cid>, provide: F, limit: Duration) -> ReprovideStats
+where
+    F: Fn(Cid) -> Fut,
+    Fut: Future<Output = Result<(), E>>,
+    E: fmt::Debug,
+{
+    let stats = ReprovideStats {
+        runs: 1,
+        ..Default::default()
+    };
+
+    futures::stream::iter(cids)
+        .map(|cid| {
+            let provided = timeout(limit, provide(cid.clone()));
+            async move { (cid, provided.await) }
+        })
+        .buffer_unordered(PROVIDE_CONCURRENCY)
+        .fold(stats, |mut stats, (cid, res)| async move {
+            match res {
+                Ok(Ok(())) => stats.provided += 1,
+                Ok(Err(e)) => {
+                    trace!("failed to provide {}: {:?}", cid, e);
+                    stats.failed += 1;
+                }
+                Err(_) => {
+                    trace!("providing {} timed out", cid);
+                    stats.failed += 1;
+                }
+            }
+            stats
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Block, IpfsOptions, Node};
+    use multihash::Sha2_256;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn strategy_names() {
+        for strategy in &[
+            ReprovideStrategy::All,
+            ReprovideStrategy::Pinned,
+            ReprovideStrategy::Roots,
+        ] {
+            assert_eq!(
+                strategy.to_string().parse::<ReprovideStrategy>().unwrap(),
+                *strategy
+            );
+        }
+
+        assert!("flat".parse::<ReprovideStrategy>().is_err());
+    }
+
+    #[tokio::test]
+    async fn provides_are_bounded() {
+        let cids = (0u32..100)
+            .map(|i| Cid::new_v1(cid::Codec::Raw, Sha2_256::digest(&i.to_be_bytes())))
+            .collect::<Vec<_>>();
+        let stuck = cids.iter().step_by(10).cloned().collect::<Vec<_>>();
+
+        struct InFlight<'a>(&'a AtomicUsize);
+
+        impl Drop for InFlight<'_> {
+            fn drop(&mut self) {
+                self.0.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+
+        let in_flight = AtomicUsize::new(0);
+        let max_in_flight = AtomicUsize::new(0);
+
+        let provide = |cid: Cid| {
+            let stuck = stuck.contains(&cid);
+            let (in_flight, max_in_flight) = (&in_flight, &max_in_flight);
+            async move {
+                let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                max_in_flight.fetch_max(now, Ordering::SeqCst);
+                // the stuck ones are dropped on timeout
+                let _guard = InFlight(in_flight);
+
+                if stuck {
+                    futures::future::pending::<()>().await;
+                }
+                tokio::task::yield_now().await;
+
+                Ok::<_, ()>(())
+            }
+        };
+
+        let stats = provide_all(cids, provide, Duration::from_millis(50)).await;
+
+        assert_eq!(stats.runs, 1);
+        assert_eq!(stats.provided, 90);
+        assert_eq!(stats.failed, 10);
+        assert!(max_in_flight.load(Ordering::SeqCst) <= PROVIDE_CONCURRENCY);
+    }
+
+    #[tokio::test]
+    async fn reprovide_announces_every_block() {
+        let mut opts = IpfsOptions::inmemory_with_generated_keys();
+        // without peers to announce to, do not wait for the default timeout
+        opts.reprovider.provide_timeout = Duration::from_millis(100);
+        let ipfs = Node::with_options(opts).await;
+
+        for data in &[&b"1"[..], &b"2"[..]] {
+            let cid = Cid::new_v1(cid::Codec::Raw, Sha2_256::digest(data));
+            ipfs.put_block(Block::new(data.to_vec().into_boxed_slice(), cid))
+                .await
+                .unwrap();
+        }
+
+        let stats = ipfs.reprovide().await.unwrap();
+
+        // without peers in the routing table the announcements fail or time out, but each block
+        // is tried
+        assert_eq!(stats.runs, 1);
+        assert_eq!(stats.provided + stats.failed, 2);
+        assert_eq!(ipfs.reprovider_stats(), stats);
+    }
+}
@@ -80,6 +80,9 @@ where
             None => return,
         };
 
+        // fetch all of the blocks of the file in one session
+        let session = ipfs.borrow().new_bitswap_session();
+
         loop {
             // TODO: if it was possible, it would make sense to start downloading N of these
             // we could just create an FuturesUnordered which would drop the value right away. that
@@ -89,7 +92,7 @@ where
             let (next, _) = visit.pending_links();
 
             let borrow = ipfs.borrow();
-            let Block { cid, data } = match borrow.get_block(&next).await {
+            let Block { cid, data } = match borrow.get_block_in_session(&next, &session).await {
                 Ok(block) => block,
                 Err(e) => {
                     yield Err(TraversalFailed::Loading(next.to_owned(), e));
@@ -46,3 +46,113 @@ async fn long_get_block() {
     nodes[0].put_block(block.clone()).await.unwrap();
     nodes[N - 1].get_block(&block.cid).await.unwrap();
 }
+
+// the blocks of a multi-block file are fetched in a single session by cat, asking only the first
+// block from every connected peer
+#[tokio::test]
+async fn cat_in_session() {
+    use futures::stream::TryStreamExt;
+    use ipfs::unixfs::ll::file::adder::{Chunker, FileAdder};
+
+    // the first node is connected to the provider and to a node without the blocks
+    let nodes = spawn_nodes(3, Topology::Star).await;
+    let content = (0..1000u32).map(|i| i as u8).collect::<Vec<_>>();
+
+    let mut adder = FileAdder::builder()
+        .with_chunker(Chunker::Size(100))
+        .unwrap()
+        .build();
+    let mut blocks = Vec::new();
+    let mut pushed = 0;
+
+    while pushed < content.len() {
+        let (ready, consumed) = adder.push(&content[pushed..]);
+        blocks.extend(ready);
+        pushed += consumed;
+    }
+    blocks.extend(adder.finish());
+
+    let (root, _) = blocks.last().cloned().unwrap();
+    let block_count = blocks.len() as u64;
+    assert!(block_count > 1);
+
+    for (cid, data) in blocks {
+        let block = Block {
+            cid,
+            data: data.into_boxed_slice(),
+        };
+        nodes[1].put_block(block).await.unwrap();
+    }
+
+    let catted = timeout(Duration::from_secs(10), async {
+        nodes[0]
+            .cat_unixfs(root, None)
+            .await
+            .unwrap()
+            .try_concat()
+            .await
+            .unwrap()
+    })
+    .await
+    .expect("cat did not complete in time");
+
+    assert_eq!(catted, content);
+
+    let provider = nodes[1].bitswap_ledger(nodes[0].id).await.unwrap();
+    assert!(provider.wants_received >= block_count);
+
+    // after the root, the blocks were only asked from the peer of the session
+    let bystander = nodes[2].bitswap_ledger(nodes[0].id).await.unwrap();
+    assert_eq!(bystander.wants_received, 1);
+}
+
+// the linked documents are fetched in a single session by refs, asking only the first one from
+// every connected peer
+#[tokio::test]
+async fn refs_in_session() {
+    use futures::stream::TryStreamExt;
+    use ipfs::make_ipld;
+
+    // the first node is connected to the provider and to a node without the blocks
+    let nodes = spawn_nodes(3, Topology::Star).await;
+
+    let mut leaves = Vec::new();
+    for i in 0..3 {
+        leaves.push(nodes[1].put_dag(make_ipld!(i)).await.unwrap());
+    }
+
+    let root = make_ipld!([leaves[0].clone(), leaves[1].clone(), leaves[2].clone()]);
+    let root_cid = nodes[1].put_dag(root.clone()).await.unwrap();
+
+    let edges = timeout(
+        Duration::from_secs(10),
+        nodes[0]
+            .refs(vec![(root_cid.clone(), root)], None, false)
+            .try_collect::<Vec<_>>(),
+    )
+    .await
+    .expect("refs did not complete in time")
+    .unwrap();
+
+    let destinations = edges
+        .into_iter()
+        .map(|edge| {
+            assert_eq!(edge.source, root_cid);
+            edge.destination
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(destinations, leaves);
+
+    // the leaves were downloaded while walking
+    for leaf in &leaves {
+        assert!(nodes[0].refs_local().await.unwrap().contains(leaf));
+    }
+
+    let provider = nodes[1].bitswap_ledger(nodes[0].id).await.unwrap();
+    assert!(provider.wants_received >= leaves.len() as u64);
+
+    // after the first leaf, the leaves were only asked from the peer of the session
+    let bystander = nodes[2].bitswap_ledger(nodes[0].id).await.unwrap();
+    assert_eq!(bystander.wants_received, 1);
+}
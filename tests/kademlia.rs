@@ -1,5 +1,5 @@
 use cid::{Cid, Codec};
-use ipfs::{p2p::MultiaddrWithPeerId, Block, Node};
+use ipfs::{p2p::MultiaddrWithPeerId, Block, IpfsPath, IpnsResolveOptions, KeyType, Node};
 use libp2p_rs::{multiaddr::Multiaddr, multiaddr::Protocol};
 use multihash::Sha2_256;
 use tokio::time::timeout;
@@ -184,3 +184,133 @@ async fn dht_get_put() {
     // and the first node should be able to get it
     assert_eq!(nodes[0].dht_get(key).await.unwrap(), value);
 }
+
+/// Check if an IPNS record published by one node can be resolved by another.
+#[tokio::test]
+async fn ipns_publish_resolve() {
+    const CHAIN_LEN: usize = 10;
+    let (nodes, foreign_node) = spawn_bootstrapped_nodes(CHAIN_LEN).await;
+    let last_index = CHAIN_LEN - if foreign_node.is_none() { 1 } else { 2 };
+
+    let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(b"hello block\n"));
+    let path = IpfsPath::from(cid);
+
+    let name = nodes[last_index]
+        .publish_ipns(&path, Duration::from_secs(60 * 60))
+        .await
+        .unwrap();
+
+    assert_eq!(name, IpfsPath::from(nodes[last_index].id));
+    assert_eq!(nodes[0].resolve_ipns(&name, true).await.unwrap(), path);
+
+    // the paths under the name are resolved under the published path
+    let file = name.sub_path("some/file").unwrap();
+    assert_eq!(
+        nodes[0].resolve_ipns(&file, true).await.unwrap(),
+        path.sub_path("some/file").unwrap()
+    );
+
+    // a newer record replaces the earlier one
+    let other_path = IpfsPath::from(Cid::new_v1(Codec::Raw, Sha2_256::digest(b"other block\n")));
+    nodes[last_index]
+        .publish_ipns(&other_path, Duration::from_secs(60 * 60))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        nodes[last_index].resolve_ipns(&name, true).await.unwrap(),
+        other_path
+    );
+    // the other node keeps using the record it has already resolved unless told otherwise
+    assert_eq!(nodes[0].resolve_ipns(&name, true).await.unwrap(), path);
+
+    let nocache = IpnsResolveOptions {
+        nocache: true,
+        dht_timeout: Some(Duration::from_secs(30)),
+    };
+    assert_eq!(
+        nodes[0]
+            .resolve_ipns_with(&name, true, nocache)
+            .await
+            .unwrap(),
+        other_path
+    );
+}
+
+/// Check if names published with the keys of the keystore resolve separately from the node's own.
+#[tokio::test]
+async fn ipns_publish_with_named_key() {
+    const CHAIN_LEN: usize = 10;
+    let (nodes, foreign_node) = spawn_bootstrapped_nodes(CHAIN_LEN).await;
+    let last_index = CHAIN_LEN - if foreign_node.is_none() { 1 } else { 2 };
+
+    let key = nodes[last_index]
+        .keystore()
+        .generate("other", KeyType::Ed25519)
+        .await
+        .unwrap();
+
+    let own_path = IpfsPath::from(Cid::new_v1(Codec::Raw, Sha2_256::digest(b"own block\n")));
+    let key_path = IpfsPath::from(Cid::new_v1(Codec::Raw, Sha2_256::digest(b"key block\n")));
+
+    let own_name = nodes[last_index]
+        .publish_ipns_with_key("self", &own_path, Duration::from_secs(60 * 60))
+        .await
+        .unwrap();
+    let key_name = nodes[last_index]
+        .publish_ipns_with_key("other", &key_path, Duration::from_secs(60 * 60))
+        .await
+        .unwrap();
+
+    assert_eq!(own_name, IpfsPath::from(nodes[last_index].id));
+    assert_eq!(key_name, IpfsPath::from(key.id));
+
+    assert_eq!(
+        nodes[0].resolve_ipns(&own_name, true).await.unwrap(),
+        own_path
+    );
+    assert_eq!(
+        nodes[0].resolve_ipns(&key_name, true).await.unwrap(),
+        key_path
+    );
+
+    assert!(nodes[last_index]
+        .publish_ipns_with_key("missing", &key_path, Duration::from_secs(60 * 60))
+        .await
+        .is_err());
+}
+
+/// Check that names pointing to each other fail to resolve instead of resolving to a name.
+#[tokio::test]
+async fn ipns_cycle_is_an_error() {
+    const CHAIN_LEN: usize = 10;
+    let (nodes, foreign_node) = spawn_bootstrapped_nodes(CHAIN_LEN).await;
+    let last_index = CHAIN_LEN - if foreign_node.is_none() { 1 } else { 2 };
+
+    let key = nodes[last_index]
+        .keystore()
+        .generate("other", KeyType::Ed25519)
+        .await
+        .unwrap();
+
+    let own_name = IpfsPath::from(nodes[last_index].id);
+    let key_name = IpfsPath::from(key.id);
+
+    nodes[last_index]
+        .publish_ipns_with_key("self", &key_name, Duration::from_secs(60 * 60))
+        .await
+        .unwrap();
+    nodes[last_index]
+        .publish_ipns_with_key("other", &own_name, Duration::from_secs(60 * 60))
+        .await
+        .unwrap();
+
+    // a single step still resolves to the other name
+    assert_eq!(
+        nodes[0].resolve_ipns(&own_name, false).await.unwrap(),
+        key_name
+    );
+
+    let err = nodes[0].resolve_ipns(&own_name, true).await.unwrap_err();
+    assert!(err.to_string().contains("cycle"), "{}", err);
+}
@@ -1,4 +1,5 @@
 use futures::future::pending;
+use futures::stream::StreamExt;
 use ipfs::Node;
 use std::time::Duration;
 use tokio::time::timeout;
@@ -13,18 +14,85 @@ async fn subscribe_only_once() {
     // a.pubsub_subscribe("some_topic".into()).await.unwrap_or_else(|e|e);
 }
 
-// #[tokio::test]
-// async fn unsubscribe_via_drop() {
-//     let a = Node::new("test_node").await;
+#[tokio::test]
+async fn unsubscribe_via_drop() {
+    let a = Node::new("test_node").await;
+
+    let msgs = a.pubsub_subscribe("topic".into()).await.unwrap();
+    assert_eq!(a.pubsub_subscribed().await.unwrap(), &["topic"]);
+
+    drop(msgs);
+
+    let empty: &[&str] = &[];
+    assert_eq!(a.pubsub_subscribed().await.unwrap(), empty);
+}
+
+#[tokio::test]
+async fn streams_of_a_topic_share_the_subscription() {
+    let a = Node::new("test_node").await;
+
+    let mut first = a.pubsub_subscribe("topic".into()).await.unwrap();
+    let mut second = a.pubsub_subscribe("topic".into()).await.unwrap();
+    let third = a.pubsub_subscribe("topic".into()).await.unwrap();
 
-//     let msgs = a.pubsub_subscribe("topic".into()).await.unwrap();
-//     assert_eq!(a.pubsub_subscribed().await.unwrap(), &["topic"]);
+    // the local messages are delivered to every stream
+    a.pubsub_publish("topic".into(), b"foobar".to_vec())
+        .await
+        .unwrap();
+
+    for stream in vec![&mut first, &mut second] {
+        let msg = timeout(Duration::from_secs(5), stream.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(msg.data, b"foobar");
+    }
 
-//     drop(msgs);
+    // dropping one of the streams keeps the topic subscribed to
+    drop(third);
+    assert_eq!(a.pubsub_subscribed().await.unwrap(), &["topic"]);
 
-//     let empty: &[&str] = &[];
-//     assert_eq!(a.pubsub_subscribed().await.unwrap(), empty);
-// }
+    // while unsubscribing ends all of them
+    assert!(a.pubsub_unsubscribe("topic").await.unwrap());
+    assert!(!a.pubsub_unsubscribe("topic").await.unwrap());
+    assert_eq!(first.next().await, None);
+    assert_eq!(second.next().await, None);
+
+    let empty: &[&str] = &[];
+    assert_eq!(a.pubsub_subscribed().await.unwrap(), empty);
+}
+
+#[tokio::test]
+async fn validator_filters_messages() {
+    use ipfs::ValidationResult;
+
+    let a = Node::new("test_node").await;
+
+    a.pubsub_set_validator("topic".into(), |msg| async move {
+        match &msg.data[..] {
+            b"bad" => ValidationResult::Reject,
+            b"boring" => ValidationResult::Ignore,
+            _ => ValidationResult::Accept,
+        }
+    });
+
+    let mut msgs = a.pubsub_subscribe("topic".into()).await.unwrap();
+
+    for data in &[&b"bad"[..], &b"boring"[..], &b"good"[..]] {
+        a.pubsub_publish("topic".into(), data.to_vec())
+            .await
+            .unwrap();
+    }
+
+    let msg = timeout(Duration::from_secs(5), msgs.next())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(msg.data, b"good");
+
+    assert!(a.pubsub_remove_validator("topic"));
+    assert!(!a.pubsub_remove_validator("topic"));
+}
 
 #[tokio::test]
 async fn can_publish_without_subscribing() {
@@ -131,6 +199,95 @@ async fn publish_between_two_nodes() {
     assert!(disappeared, "timed out before a saw b's unsubscription");
 }
 
+#[tokio::test]
+async fn ipns_over_pubsub() {
+    use cid::{Cid, Codec};
+    use ipfs::{ipns::pubsub::topic, IpfsOptions, IpfsPath};
+    use multihash::Sha2_256;
+
+    let mut nodes = Vec::with_capacity(2);
+    for _ in 0..2 {
+        let mut opts = IpfsOptions::inmemory_with_generated_keys();
+        opts.ipns_pubsub = true;
+        nodes.push(Node::with_options(opts).await);
+    }
+
+    nodes[1].connect(nodes[0].addrs[0].clone()).await.unwrap();
+
+    let name = IpfsPath::from(nodes[0].id);
+    let path = IpfsPath::from(Cid::new_v1(Codec::Raw, Sha2_256::digest(b"pubsub block\n")));
+
+    // nothing has been published yet, but the name is now subscribed to
+    assert!(nodes[1].resolve_ipns(&name, false).await.is_err());
+    assert_eq!(nodes[1].ipns_pubsub_subscriptions(), vec![nodes[0].id]);
+
+    let topic = topic(&nodes[0].id);
+    let mut appeared = false;
+    for _ in 0..100usize {
+        if nodes[0]
+            .pubsub_peers(Some(topic.clone()))
+            .await
+            .unwrap()
+            .contains(&nodes[1].id)
+        {
+            appeared = true;
+            break;
+        }
+        timeout(Duration::from_millis(100), pending::<()>())
+            .await
+            .unwrap_err();
+    }
+
+    assert!(appeared, "timed out before the subscription was seen");
+
+    // putting the record into the dht can fail with this few peers, but the record is broadcast
+    // before that
+    let _ = nodes[0]
+        .publish_ipns(&path, Duration::from_secs(60 * 60))
+        .await;
+
+    let mut resolved = None;
+    for _ in 0..100usize {
+        if let Ok(found) = nodes[1].resolve_ipns(&name, false).await {
+            resolved = Some(found);
+            break;
+        }
+        timeout(Duration::from_millis(100), pending::<()>())
+            .await
+            .unwrap_err();
+    }
+
+    assert_eq!(resolved, Some(path));
+
+    assert!(nodes[1].ipns_pubsub_cancel(&nodes[0].id));
+    assert!(!nodes[1].ipns_pubsub_cancel(&nodes[0].id));
+    assert!(nodes[1].ipns_pubsub_subscriptions().is_empty());
+}
+
+#[tokio::test]
+async fn concurrent_ipns_resolves_subscribe_once() {
+    use ipfs::{ipns::pubsub::topic, IpfsOptions, IpfsPath, PeerId};
+
+    let mut opts = IpfsOptions::inmemory_with_generated_keys();
+    opts.ipns_pubsub = true;
+    let node = Node::with_options(opts).await;
+
+    let peer = PeerId::random();
+    let name = IpfsPath::from(peer);
+
+    let (first, second) = futures::future::join(
+        node.resolve_ipns(&name, false),
+        node.resolve_ipns(&name, false),
+    )
+    .await;
+
+    // nothing has been published, but both resolves share the one subscription
+    assert!(first.is_err());
+    assert!(second.is_err());
+    assert_eq!(node.ipns_pubsub_subscriptions(), vec![peer]);
+    assert_eq!(node.pubsub_subscribed().await.unwrap(), vec![topic(&peer)]);
+}
+
 #[cfg(any(feature = "test_go_interop", feature = "test_js_interop"))]
 #[tokio::test]
 #[ignore = "doesn't work yet"]
@@ -67,7 +67,7 @@ pub fn run_adder(size: usize) {
     // Setting a small chunker size should exacerbate the issue as the BalanceCollector needs to
     // work harder as a result.
     let chunker = Chunker::Size(1);
-    let mut adder = FileAdder::builder().with_chunker(chunker).build();
+    let mut adder = FileAdder::builder().with_chunker(chunker).unwrap().build();
     let mut total = 0;
 
     while total < size {
@@ -1,3 +1,4 @@
+use crate::HashFunction;
 use cid::Cid;
 use core::fmt;
 
@@ -13,6 +14,8 @@ pub use buffered::BufferingTreeBuilder;
 mod custom_pb;
 use custom_pb::CustomFlatUnixFs;
 
+mod hamt;
+
 enum Entry {
     Leaf(Leaf),
     Directory(DirBuilder),
@@ -55,6 +58,8 @@ impl fmt::Debug for Leaf {
 pub struct TreeOptions {
     block_size_limit: Option<u64>,
     wrap_with_directory: bool,
+    cid_version: cid::Version,
+    hash: HashFunction,
 }
 
 impl Default for TreeOptions {
@@ -63,13 +68,16 @@ impl Default for TreeOptions {
             // this is just a guess; our bitswap message limit is a bit more
             block_size_limit: Some(512 * 1024),
             wrap_with_directory: false,
+            cid_version: cid::Version::V0,
+            hash: HashFunction::default(),
         }
     }
 }
 
 impl TreeOptions {
-    /// Overrides the default directory block size limit. If the size limit is set to `None`, no
-    /// directory will be too large.
+    /// Overrides the default directory block size limit. Directories which would not fit into a
+    /// single block are sharded into a HAMT as in go-ipfs. If the size limit is set to `None`, no
+    /// directory will be too large and no directory is sharded.
     pub fn block_size_limit(&mut self, limit: Option<u64>) {
         self.block_size_limit = limit;
     }
@@ -79,6 +87,17 @@ impl TreeOptions {
     pub fn wrap_with_directory(&mut self) {
         self.wrap_with_directory = true;
     }
+
+    /// Overrides the Cid version of the directory blocks, which defaults to version 0.
+    pub fn cid_version(&mut self, version: cid::Version) {
+        self.cid_version = version;
+    }
+
+    /// Overrides the hash function of the directory blocks, which defaults to sha2-256. Cid
+    /// version 1 is used with any other hash function.
+    pub fn hash(&mut self, hash: HashFunction) {
+        self.hash = hash;
+    }
 }
 
 /// Tree building failure cases.
@@ -129,9 +148,11 @@ impl std::error::Error for TreeBuildingFailed {}
 pub enum TreeConstructionFailed {
     /// Failed to serialize the protobuf node for the directory
     Protobuf(quick_protobuf::Error),
-    /// The resulting directory would be too large and HAMT sharding is yet to be implemented or
-    /// denied.
+    /// A block of the resulting directory would be too large even after HAMT sharding.
     TooLargeBlock(u64),
+    /// The two names of a directory have the same hash and cannot be placed into different
+    /// buckets of a HAMT shard.
+    HashCollision(String, String),
 }
 
 impl fmt::Display for TreeConstructionFailed {
@@ -141,6 +162,7 @@ impl fmt::Display for TreeConstructionFailed {
         match self {
             Protobuf(e) => write!(fmt, "serialization failed: {}", e),
             TooLargeBlock(size) => write!(fmt, "attempted to create block of {} bytes", size),
+            HashCollision(a, b) => write!(fmt, "names {:?} and {:?} have the same hash", a, b),
         }
     }
 }
@@ -376,6 +376,38 @@ mod tests {
         );
     }
 
+    #[test]
+    fn metadata_is_written() {
+        use crate::pb::FlatUnixFs;
+
+        let metadata = Metadata::default()
+            .with_mode(0o755)
+            .with_mtime(1_600_000_000, 0);
+
+        let mut builder = BufferingTreeBuilder::default();
+        builder.set_metadata("a/b", metadata.clone()).unwrap();
+        builder.put_link("a/b/c.txt", some_cid(0), 1).unwrap();
+
+        let actual = builder
+            .build()
+            .map(|res| {
+                res.map(|OwnedTreeNode { path, block, .. }| {
+                    let flat = FlatUnixFs::try_from(&block[..]).unwrap();
+                    (path, Metadata::from(&flat.data))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(
+            actual,
+            [
+                ("a/b".to_string(), metadata),
+                ("a".to_string(), Metadata::default())
+            ]
+        );
+    }
+
     #[test]
     fn dir_with_cidv1_link() {
         // this is `echo '{ "name": "hello" }` | ./ipfs dag put`
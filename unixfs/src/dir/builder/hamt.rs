@@ -0,0 +1,324 @@
+//! HAMT sharding of the directories which would not fit into a single block, in the layout of
+//! go-ipfs: the 256 buckets of a shard are selected by the successive bytes of the 64-bit murmur3
+//! hash of the entry name, starting from the most significant byte.
+//!
+//! A bucket with a single entry links to the entry by its name prefixed with the uppercase hex
+//! index of the bucket. A bucket with more entries links to a nested shard by the hex index alone.
+//! The nesting only depends on the names, so the same entries always produce the same shards.
+
+use super::iter::render_node;
+use super::{CustomFlatUnixFs, Leaf, NamedLeaf, TreeConstructionFailed, TreeOptions};
+use crate::pb::{UnixFs, UnixFsType};
+use crate::Metadata;
+use alloc::borrow::Cow;
+use cid::Cid;
+use std::collections::VecDeque;
+
+/// The number of buckets in a shard, the only fanout supported by go-ipfs.
+const FANOUT: usize = 256;
+
+/// The multicodec of murmur3-x64-64, used as the `hashType` of the shards.
+const HASH_TYPE: u64 = 0x22;
+
+/// A rendered shard of a sharded directory.
+pub(super) struct Shard {
+    pub(super) cid: Cid,
+    pub(super) total_size: u64,
+    pub(super) block: Vec<u8>,
+}
+
+/// Renders the `links` of a directory as HAMT shards, pushing the shards to `shards` in post
+/// order. Returns the root shard, which is pushed last and holds the `metadata`.
+pub(super) fn render_sharded(
+    links: &[Option<NamedLeaf>],
+    metadata: &Metadata,
+    opts: &TreeOptions,
+    shards: &mut VecDeque<Shard>,
+) -> Result<Leaf, TreeConstructionFailed> {
+    let mut hashed = links
+        .iter()
+        .map(|link| {
+            let link = link.as_ref().expect("all links have been rendered");
+            (murmur3_64(link.0.as_bytes()), link)
+        })
+        .collect::<Vec<_>>();
+
+    // the buckets of all levels are now continuous ranges in the index order
+    hashed.sort_unstable_by_key(|(hash, _)| *hash);
+
+    render_shard(&hashed, 0, Some(metadata), opts, shards)
+}
+
+fn render_shard(
+    entries: &[(u64, &NamedLeaf)],
+    level: usize,
+    metadata: Option<&Metadata>,
+    opts: &TreeOptions,
+    shards: &mut VecDeque<Shard>,
+) -> Result<Leaf, TreeConstructionFailed> {
+    let index_at = |hash: u64| (hash >> (56 - 8 * level)) as u8 as usize;
+
+    let mut links = Vec::new();
+    let mut bitfield = [0u8; FANOUT / 8];
+    let mut remaining = entries;
+
+    while let Some((hash, _)) = remaining.first() {
+        let index = index_at(*hash);
+        let len = remaining
+            .iter()
+            .take_while(|(other, _)| index_at(*other) == index)
+            .count();
+
+        let (bucket, rest) = remaining.split_at(len);
+        remaining = rest;
+
+        // go-bitfield is big-endian
+        bitfield[bitfield.len() - 1 - index / 8] |= 1 << (index % 8);
+
+        let link = match bucket {
+            [(_, NamedLeaf(name, cid, total_size))] => {
+                NamedLeaf(format!("{:02X}{}", index, name), cid.clone(), *total_size)
+            }
+            [(_, first), (_, second), ..] if level + 1 == core::mem::size_of::<u64>() => {
+                return Err(TreeConstructionFailed::HashCollision(
+                    first.0.clone(),
+                    second.0.clone(),
+                ));
+            }
+            _ => {
+                let Leaf { link, total_size } =
+                    render_shard(bucket, level + 1, None, opts, shards)?;
+                NamedLeaf(format!("{:02X}", index), link, total_size)
+            }
+        };
+
+        links.push(Some(link));
+    }
+
+    // leading zeroes are left out as with go-bitfield
+    let first_set = bitfield.iter().position(|&b| b != 0).unwrap_or(0);
+
+    let mut node = CustomFlatUnixFs {
+        links: &links,
+        data: UnixFs {
+            Type: UnixFsType::HAMTShard,
+            Data: Some(Cow::Borrowed(&bitfield[first_set..])),
+            hashType: Some(HASH_TYPE),
+            fanout: Some(FANOUT as u64),
+            ..Default::default()
+        },
+    };
+
+    if let Some(metadata) = metadata {
+        metadata.write_into(&mut node.data);
+    }
+
+    let mut block = Vec::new();
+    let leaf = render_node(&node, &mut block, opts)?;
+
+    shards.push_back(Shard {
+        cid: leaf.link.clone(),
+        total_size: leaf.total_size,
+        block,
+    });
+
+    Ok(leaf)
+}
+
+/// The first 64 bits of the x64 128-bit variant of murmur3 with zero seed, as used by go-ipfs.
+fn murmur3_64(data: &[u8]) -> u64 {
+    const C1: u64 = 0x87c3_7b91_1142_53d5;
+    const C2: u64 = 0x4cf5_ad43_2745_937f;
+
+    fn mix_k1(k1: u64) -> u64 {
+        k1.wrapping_mul(C1).rotate_left(31).wrapping_mul(C2)
+    }
+
+    fn mix_k2(k2: u64) -> u64 {
+        k2.wrapping_mul(C2).rotate_left(33).wrapping_mul(C1)
+    }
+
+    fn fmix(mut k: u64) -> u64 {
+        k ^= k >> 33;
+        k = k.wrapping_mul(0xff51_afd7_ed55_8ccd);
+        k ^= k >> 33;
+        k = k.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+        k ^ (k >> 33)
+    }
+
+    let read = |bytes: &[u8]| {
+        let mut le = [0u8; 8];
+        le.copy_from_slice(bytes);
+        u64::from_le_bytes(le)
+    };
+
+    let mut h1 = 0u64;
+    let mut h2 = 0u64;
+
+    let mut blocks = data.chunks_exact(16);
+
+    for block in &mut blocks {
+        h1 ^= mix_k1(read(&block[..8]));
+        h1 = h1
+            .rotate_left(27)
+            .wrapping_add(h2)
+            .wrapping_mul(5)
+            .wrapping_add(0x52dc_e729);
+
+        h2 ^= mix_k2(read(&block[8..]));
+        h2 = h2
+            .rotate_left(31)
+            .wrapping_add(h1)
+            .wrapping_mul(5)
+            .wrapping_add(0x3849_5ab5);
+    }
+
+    let tail = blocks.remainder();
+
+    if !tail.is_empty() {
+        let mut padded = [0u8; 16];
+        padded[..tail.len()].copy_from_slice(tail);
+
+        if tail.len() > 8 {
+            h2 ^= mix_k2(read(&padded[8..]));
+        }
+        h1 ^= mix_k1(read(&padded[..8]));
+    }
+
+    h1 ^= data.len() as u64;
+    h2 ^= data.len() as u64;
+
+    h1 = h1.wrapping_add(h2);
+    h2 = h2.wrapping_add(h1);
+
+    h1 = fmix(h1);
+    h2 = fmix(h2);
+
+    h1.wrapping_add(h2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::murmur3_64;
+    use crate::dir::builder::{BufferingTreeBuilder, OwnedTreeNode, TreeOptions};
+    use crate::dir::{resolve, MaybeResolved};
+    use crate::test_support::FakeBlockstore;
+    use cid::Cid;
+    use core::convert::TryFrom;
+
+    #[test]
+    fn murmur3() {
+        assert_eq!(murmur3_64(b""), 0);
+        assert_eq!(murmur3_64(b"hello"), 0xcbd8_a7b3_41bd_9b02);
+        assert_eq!(
+            murmur3_64(b"The quick brown fox jumps over the lazy dog"),
+            0xe34b_bc7b_bc07_1b6c
+        );
+    }
+
+    #[test]
+    fn go_ipfs_sharded_directory() {
+        // the fixture QmZbFPTnDBMWbQ6iBxQAhuhLz8Nu9XptYS96e7cuf5wvbk, where each bucket of the root
+        // shard has two entries
+        let names = [
+            "003", "004", "009", "016", "017", "025", "033", "034", "037", "038", "040", "041",
+            "048", "049", "050", "058",
+        ];
+        let empty = Cid::try_from("QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH").unwrap();
+
+        let mut opts = TreeOptions::default();
+        opts.wrap_with_directory();
+        // the flat directory would be 980 bytes
+        opts.block_size_limit(Some(512));
+
+        let mut builder = BufferingTreeBuilder::new(opts);
+
+        for name in &names {
+            builder
+                .put_link(&format!("long-named-file-{}", name), empty.clone(), 6)
+                .unwrap();
+        }
+
+        let nodes = builder.build().collect::<Result<Vec<_>, _>>().unwrap();
+        let blocks = FakeBlockstore::with_fixtures();
+
+        assert_eq!(nodes.len(), 9);
+
+        for (i, node) in nodes.iter().enumerate() {
+            assert_eq!(node.path, "");
+            assert_eq!(node.intermediate_shard, i + 1 < nodes.len());
+            assert_eq!(blocks.get_by_cid(&node.cid), &node.block[..]);
+        }
+
+        let OwnedTreeNode {
+            cid, total_size, ..
+        } = nodes.last().unwrap();
+
+        assert_eq!(
+            cid.to_string(),
+            "QmZbFPTnDBMWbQ6iBxQAhuhLz8Nu9XptYS96e7cuf5wvbk"
+        );
+        assert_eq!(*total_size, 1788);
+    }
+
+    #[test]
+    fn sharded_directory_round_trip() {
+        let mut opts = TreeOptions::default();
+        // large enough for a full root shard, too small for the flat directory
+        opts.block_size_limit(Some(16 * 1024));
+
+        let mut builder = BufferingTreeBuilder::new(opts);
+        let file = Cid::try_from("QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL").unwrap();
+
+        for i in 0..2000 {
+            builder
+                .put_link(&format!("dir/sub/{}.txt", i), file.clone(), 15)
+                .unwrap();
+        }
+        builder.put_link("dir/small.txt", file, 15).unwrap();
+
+        let mut blocks = FakeBlockstore::default();
+        let mut directories = Vec::new();
+
+        for node in builder.build() {
+            let node = node.unwrap();
+            assert!(node.block.len() <= 16 * 1024);
+            assert_eq!(blocks.insert_v0(&node.block), node.cid);
+
+            if !node.intermediate_shard {
+                directories.push((node.path, node.cid));
+            }
+        }
+
+        let paths = directories
+            .iter()
+            .map(|(path, _)| path.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, ["dir/sub", "dir"]);
+
+        let (_, root) = &directories[1];
+        let sub = match resolve(blocks.get_by_cid(root), "sub", &mut None).unwrap() {
+            MaybeResolved::Found(cid) => cid,
+            x => unreachable!("{:?}", x),
+        };
+        assert_eq!(sub, directories[0].1);
+
+        for needle in &["0.txt", "1999.txt", "1024.txt", "2000.txt"] {
+            let mut cache = None;
+            let mut step = resolve(blocks.get_by_cid(&sub), needle, &mut cache).unwrap();
+
+            let found = loop {
+                step = match step {
+                    MaybeResolved::Found(cid) => break Some(cid),
+                    MaybeResolved::NotFound => break None,
+                    MaybeResolved::NeedToLoadMore(lookup) => {
+                        let block = blocks.get_by_cid(lookup.pending_links().0);
+                        lookup.continue_walk(block, &mut cache).unwrap()
+                    }
+                };
+            };
+
+            assert_eq!(found.is_some(), *needle != "2000.txt", "{}", needle);
+        }
+    }
+}
@@ -1,9 +1,11 @@
+use super::hamt::{render_sharded, Shard};
 use super::{
     CustomFlatUnixFs, DirBuilder, Entry, Leaf, NamedLeaf, TreeConstructionFailed, TreeOptions,
 };
+use crate::Metadata;
 use cid::Cid;
 use core::fmt;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 
 /// Constructs the directory nodes required for a tree.
 ///
@@ -21,6 +23,8 @@ pub struct PostOrderIterator {
     reused_children: Vec<Visited>,
     cid: Option<Cid>,
     total_size: u64,
+    // the rendered shards of the latest directory, if it was sharded
+    shards: VecDeque<Shard>,
     // from TreeOptions
     opts: TreeOptions,
 }
@@ -55,9 +59,11 @@ enum Visited {
         /// Leaves will be stored directly in this field when there are no DirBuilder descendants,
         /// in the `PostOrderIterator::persisted_cids` otherwise.
         leaves: LeafStorage,
+        metadata: Metadata,
     },
     PostRoot {
         leaves: LeafStorage,
+        metadata: Metadata,
     },
 }
 
@@ -73,34 +79,24 @@ impl PostOrderIterator {
             reused_children: Vec::new(),
             cid: None,
             total_size: 0,
+            shards: VecDeque::new(),
             opts,
         }
     }
 
+    /// Renders the directory into the `buffer`, or into `shards` if the directory would not fit
+    /// into a single block.
     fn render_directory(
         links: &[Option<NamedLeaf>],
+        metadata: &Metadata,
         buffer: &mut Vec<u8>,
-        block_size_limit: &Option<u64>,
+        shards: &mut VecDeque<Shard>,
+        opts: &TreeOptions,
     ) -> Result<Leaf, TreeConstructionFailed> {
         use crate::pb::{UnixFs, UnixFsType};
-        use quick_protobuf::{BytesWriter, MessageWrite, Writer};
-        use sha2::{Digest, Sha256};
-
-        // FIXME: ideas on how to turn this into a HAMT sharding on some heuristic. we probably
-        // need to introduce states in to the "iterator":
-        //
-        // 1. bucketization
-        // 2. another post order visit of the buckets?
-        //
-        // the nested post order visit should probably re-use the existing infra ("message
-        // passing") and new ids can be generated by giving this iterator the counter from
-        // BufferedTreeBuilder.
-        //
-        // could also be that the HAMT shard building should start earlier, since the same
-        // heuristic can be detected *at* bufferedtreewriter. there the split would be easier, and
-        // this would "just" be a single node rendering, and not need any additional states..
-
-        let node = CustomFlatUnixFs {
+        use quick_protobuf::MessageWrite;
+
+        let mut node = CustomFlatUnixFs {
             links,
             data: UnixFs {
                 Type: UnixFsType::Directory,
@@ -108,54 +104,31 @@ impl PostOrderIterator {
             },
         };
 
-        let size = node.get_size();
-
-        if let Some(limit) = block_size_limit {
-            let size = size as u64;
-            if *limit < size {
-                // FIXME: this could probably be detected at builder
-                return Err(TreeConstructionFailed::TooLargeBlock(size));
-            }
-        }
-
-        let cap = buffer.capacity();
+        metadata.write_into(&mut node.data);
 
-        if let Some(additional) = size.checked_sub(cap) {
-            buffer.reserve(additional);
-        }
-
-        if let Some(mut needed_zeroes) = size.checked_sub(buffer.len()) {
-            let zeroes = [0; 8];
-
-            while needed_zeroes > 8 {
-                buffer.extend_from_slice(&zeroes[..]);
-                needed_zeroes -= zeroes.len();
+        if let Some(limit) = opts.block_size_limit {
+            if limit < node.get_size() as u64 {
+                return render_sharded(links, metadata, opts, shards);
             }
-
-            buffer.extend(core::iter::repeat(0).take(needed_zeroes));
         }
 
-        let mut writer = Writer::new(BytesWriter::new(&mut buffer[..]));
-        node.write_message(&mut writer)
-            .map_err(TreeConstructionFailed::Protobuf)?;
-
-        buffer.truncate(size);
+        render_node(&node, buffer, opts)
+    }
 
-        let mh = multihash::wrap(multihash::Code::Sha2_256, &Sha256::digest(&buffer));
-        let cid = Cid::new_v0(mh).expect("sha2_256 is the correct multihash for cidv0");
+    /// Returns the next of the rendered shards, the root shard of the directory being the last.
+    fn next_shard(&mut self) -> Option<TreeNode<'_>> {
+        let shard = self.shards.pop_front()?;
 
-        let combined_from_links = links
-            .iter()
-            .map(|opt| {
-                opt.as_ref()
-                    .map(|NamedLeaf(_, _, total_size)| total_size)
-                    .unwrap()
-            })
-            .sum::<u64>();
+        self.block_buffer = shard.block;
+        self.cid = Some(shard.cid);
+        self.total_size = shard.total_size;
 
-        Ok(Leaf {
-            link: cid,
-            total_size: buffer.len() as u64 + combined_from_links,
+        Some(TreeNode {
+            path: self.full_path.as_str(),
+            cid: self.cid.as_ref().unwrap(),
+            total_size: self.total_size,
+            block: &self.block_buffer,
+            intermediate_shard: !self.shards.is_empty(),
         })
     }
 
@@ -163,6 +136,10 @@ impl PostOrderIterator {
     ///
     /// Returns a `TreeNode` of the latest constructed tree node.
     pub fn next_borrowed(&mut self) -> Option<Result<TreeNode<'_>, TreeConstructionFailed>> {
+        if !self.shards.is_empty() {
+            return self.next_shard().map(Ok);
+        }
+
         while let Some(visited) = self.pending.pop() {
             let (name, depth) = match &visited {
                 Visited::DescentRoot(_) => (None, 0),
@@ -186,7 +163,10 @@ impl PostOrderIterator {
                         leaves.into()
                     };
 
-                    self.pending.push(Visited::PostRoot { leaves });
+                    self.pending.push(Visited::PostRoot {
+                        leaves,
+                        metadata: node.metadata,
+                    });
                     self.pending.extend(children.drain(..));
                 }
                 Visited::Descent {
@@ -213,6 +193,7 @@ impl PostOrderIterator {
                         depth,
                         leaves,
                         index,
+                        metadata: node.metadata,
                     });
 
                     self.pending.extend(children.drain(..));
@@ -222,15 +203,15 @@ impl PostOrderIterator {
                     name,
                     leaves,
                     index,
+                    metadata,
                     ..
                 } => {
                     let leaves = leaves.into_inner(&mut self.persisted_cids);
                     let buffer = &mut self.block_buffer;
+                    let shards = &mut self.shards;
 
                     let leaf = match Self::render_directory(
-                        &leaves,
-                        buffer,
-                        &self.opts.block_size_limit,
+                        &leaves, &metadata, buffer, shards, &self.opts,
                     ) {
                         Ok(leaf) => leaf,
                         Err(e) => return Some(Err(e)),
@@ -258,14 +239,19 @@ impl PostOrderIterator {
                         }
                     }
 
+                    if !self.shards.is_empty() {
+                        return self.next_shard().map(Ok);
+                    }
+
                     return Some(Ok(TreeNode {
                         path: self.full_path.as_str(),
                         cid: self.cid.as_ref().unwrap(),
                         total_size: self.total_size,
                         block: &self.block_buffer,
+                        intermediate_shard: false,
                     }));
                 }
-                Visited::PostRoot { leaves } => {
+                Visited::PostRoot { leaves, metadata } => {
                     let leaves = leaves.into_inner(&mut self.persisted_cids);
 
                     if !self.opts.wrap_with_directory {
@@ -273,11 +259,10 @@ impl PostOrderIterator {
                     }
 
                     let buffer = &mut self.block_buffer;
+                    let shards = &mut self.shards;
 
                     let leaf = match Self::render_directory(
-                        &leaves,
-                        buffer,
-                        &self.opts.block_size_limit,
+                        &leaves, &metadata, buffer, shards, &self.opts,
                     ) {
                         Ok(leaf) => leaf,
                         Err(e) => return Some(Err(e)),
@@ -286,11 +271,16 @@ impl PostOrderIterator {
                     self.cid = Some(leaf.link.clone());
                     self.total_size = leaf.total_size;
 
+                    if !self.shards.is_empty() {
+                        return self.next_shard().map(Ok);
+                    }
+
                     return Some(Ok(TreeNode {
                         path: self.full_path.as_str(),
                         cid: self.cid.as_ref().unwrap(),
                         total_size: self.total_size,
                         block: &self.block_buffer,
+                        intermediate_shard: false,
                     }));
                 }
             }
@@ -318,6 +308,9 @@ pub struct TreeNode<'a> {
     pub total_size: u64,
     /// Raw dag-pb document.
     pub block: &'a [u8],
+    /// True for the nested HAMT shards of a sharded directory, which need to be stored but are
+    /// not directories of their own; the `path` is the path of the sharded directory.
+    pub intermediate_shard: bool,
 }
 
 impl<'a> fmt::Debug for TreeNode<'a> {
@@ -327,6 +320,7 @@ impl<'a> fmt::Debug for TreeNode<'a> {
             .field("cid", &format_args!("{}", self.cid))
             .field("total_size", &self.total_size)
             .field("size", &self.block.len())
+            .field("intermediate_shard", &self.intermediate_shard)
             .finish()
     }
 }
@@ -339,6 +333,7 @@ impl TreeNode<'_> {
             cid: self.cid.to_owned(),
             total_size: self.total_size,
             block: self.block.into(),
+            intermediate_shard: self.intermediate_shard,
         }
     }
 }
@@ -353,6 +348,69 @@ pub struct OwnedTreeNode {
     pub total_size: u64,
     /// Raw dag-pb document.
     pub block: Box<[u8]>,
+    /// True for the nested HAMT shards of a sharded directory, see
+    /// [`TreeNode::intermediate_shard`].
+    pub intermediate_shard: bool,
+}
+
+/// Renders the dag-pb node into the `buffer`, failing if the node exceeds the block size limit.
+pub(super) fn render_node(
+    node: &CustomFlatUnixFs<'_>,
+    buffer: &mut Vec<u8>,
+    opts: &TreeOptions,
+) -> Result<Leaf, TreeConstructionFailed> {
+    use quick_protobuf::{BytesWriter, MessageWrite, Writer};
+
+    let size = node.get_size();
+
+    if let Some(limit) = opts.block_size_limit {
+        let size = size as u64;
+        if limit < size {
+            return Err(TreeConstructionFailed::TooLargeBlock(size));
+        }
+    }
+
+    let cap = buffer.capacity();
+
+    if let Some(additional) = size.checked_sub(cap) {
+        buffer.reserve(additional);
+    }
+
+    if let Some(mut needed_zeroes) = size.checked_sub(buffer.len()) {
+        let zeroes = [0; 8];
+
+        while needed_zeroes > 8 {
+            buffer.extend_from_slice(&zeroes[..]);
+            needed_zeroes -= zeroes.len();
+        }
+
+        buffer.extend(core::iter::repeat(0).take(needed_zeroes));
+    }
+
+    let mut writer = Writer::new(BytesWriter::new(&mut buffer[..]));
+    node.write_message(&mut writer)
+        .map_err(TreeConstructionFailed::Protobuf)?;
+
+    buffer.truncate(size);
+
+    let cid = opts
+        .hash
+        .cid(opts.cid_version, cid::Codec::DagProtobuf, &buffer);
+
+    let combined_from_links = node
+        .links
+        .iter()
+        .map(|opt| {
+            opt.as_ref()
+                .map(|NamedLeaf(_, _, total_size)| total_size)
+                .unwrap()
+        })
+        .sum::<u64>();
+
+    Ok(Leaf {
+        link: cid,
+        total_size: buffer.len() as u64 + combined_from_links,
+    })
 }
 
 fn update_full_path(
@@ -1,24 +1,28 @@
-use cid::Cid;
+use cid::{Cid, Codec, Version};
 
 use crate::pb::{FlatUnixFs, PBLink, UnixFs, UnixFsType};
+use crate::{HashFunction, Metadata};
 use alloc::borrow::Cow;
+use core::convert::TryFrom;
 use core::fmt;
 use quick_protobuf::{MessageWrite, Writer};
 
-use sha2::{Digest, Sha256};
+mod rabin;
 
 /// File tree builder. Implements [`core::default::Default`] which tracks the recent defaults.
 ///
 /// Custom file tree builder can be created with [`FileAdder::builder()`] and configuring the
-/// chunker and collector.
+/// chunker, collector and the format of the blocks.
 ///
-/// Current implementation maintains an internal buffer for the block creation and uses a
-/// non-customizable hash function to produce Cid version 0 links. Currently does not support
+/// Current implementation maintains an internal buffer for the block creation. By default the
+/// blocks are hashed with sha2-256 to produce Cid version 0 links. Currently does not support
 /// inline links.
 #[derive(Default)]
 pub struct FileAdder {
     chunker: Chunker,
     collector: Collector,
+    format: BlockFormat,
+    metadata: Metadata,
     block_buffer: Vec<u8>,
     // all unflushed links as a flat vec; this is compacted as we grow and need to create a link
     // block for the last N blocks, as decided by the collector.
@@ -109,17 +113,44 @@ impl fmt::Debug for Link {
     }
 }
 
+/// The encoding and the Cids of the created blocks.
+#[derive(Debug, Clone)]
+struct BlockFormat {
+    raw_leaves: bool,
+    version: Version,
+    hash: HashFunction,
+}
+
+impl Default for BlockFormat {
+    fn default() -> Self {
+        BlockFormat {
+            raw_leaves: false,
+            version: Version::V0,
+            hash: HashFunction::default(),
+        }
+    }
+}
+
+impl BlockFormat {
+    fn cid(&self, codec: Codec, block: &[u8]) -> Cid {
+        self.hash.cid(self.version, codec, block)
+    }
+}
+
 /// Convenience type to facilitate configuring [`FileAdder`]s.
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct FileAdderBuilder {
     chunker: Chunker,
     collector: Collector,
+    format: BlockFormat,
+    metadata: Metadata,
 }
 
 impl FileAdderBuilder {
-    /// Configures the builder to use the given chunker.
-    pub fn with_chunker(self, chunker: Chunker) -> Self {
-        FileAdderBuilder { chunker, ..self }
+    /// Configures the builder to use the given chunker, unless [`Chunker::check`] refuses it.
+    pub fn with_chunker(self, chunker: Chunker) -> Result<Self, ChunkerError> {
+        chunker.check()?;
+        Ok(FileAdderBuilder { chunker, ..self })
     }
 
     /// Configures the builder to use the given collector or layout.
@@ -130,13 +161,50 @@ impl FileAdderBuilder {
         }
     }
 
+    /// Configures the builder to store the file content as `raw` blocks instead of wrapping it
+    /// in UnixFs nodes, as go-ipfs does with `--raw-leaves`. The raw blocks always have Cid
+    /// version 1. Defaults to false.
+    pub fn with_raw_leaves(mut self, raw_leaves: bool) -> Self {
+        self.format.raw_leaves = raw_leaves;
+        self
+    }
+
+    /// Configures the Cid version of the created blocks. Note that go-ipfs also enables raw
+    /// leaves with `--cid-version=1`, unless they have been explicitly disabled. Defaults to
+    /// version 0.
+    pub fn with_cid_version(mut self, version: Version) -> Self {
+        self.format.version = version;
+        self
+    }
+
+    /// Configures the hash function of the created blocks. Cid version 1 is used with any other
+    /// hash function than sha2-256.
+    pub fn with_hash(mut self, hash: HashFunction) -> Self {
+        self.format.hash = hash;
+        self
+    }
+
+    /// Configures the mode and the modification time stored in the root node of the file. Files
+    /// whose root would be a single raw or already returned leaf get a new root node linking to
+    /// the leaf. Defaults to no metadata.
+    pub fn with_metadata(self, metadata: Metadata) -> Self {
+        FileAdderBuilder { metadata, ..self }
+    }
+
     /// Returns a new FileAdder
     pub fn build(self) -> FileAdder {
-        let FileAdderBuilder { chunker, collector } = self;
+        let FileAdderBuilder {
+            chunker,
+            collector,
+            format,
+            metadata,
+        } = self;
 
         FileAdder {
             chunker,
             collector,
+            format,
+            metadata,
             ..Default::default()
         }
     }
@@ -171,7 +239,13 @@ impl FileAdder {
             // blocks and user takes care of chunking (and buffering)?
             //
             // cat file | my_awesome_chunker | my_brilliant_collector
-            let leaf = Self::flush_buffered_leaf(accepted, &mut self.unflushed_links, false);
+            let leaf = Self::flush_buffered_leaf(
+                accepted,
+                &mut self.unflushed_links,
+                false,
+                &self.collector,
+                &self.format,
+            );
             assert!(leaf.is_some(), "chunk completed, must produce a new block");
             self.block_buffer.clear();
             let links = self.flush_buffered_links(false);
@@ -197,6 +271,8 @@ impl FileAdder {
                     self.block_buffer.as_slice(),
                     &mut self.unflushed_links,
                     false,
+                    &self.collector,
+                    &self.format,
                 );
                 assert!(leaf.is_some(), "chunk completed, must produce a new block");
                 self.block_buffer.clear();
@@ -219,10 +295,71 @@ impl FileAdder {
             &self.block_buffer.as_slice(),
             &mut self.unflushed_links,
             true,
+            &self.collector,
+            &self.format,
         );
         let root_links = self.flush_buffered_links(true);
         // should probably error if there is neither?
-        last_leaf.into_iter().chain(root_links.into_iter())
+        let mut blocks = last_leaf.into_iter().chain(root_links).collect::<Vec<_>>();
+
+        if !self.metadata.is_empty() {
+            self.write_metadata(&mut blocks);
+        }
+
+        blocks.into_iter()
+    }
+
+    /// Writes the metadata into the root node, which is the last of the `blocks` unless the file
+    /// is a single leaf returned from an earlier `push`. Such leaves and the raw leaves, which
+    /// cannot have metadata, are linked from a new root node instead.
+    fn write_metadata(&self, blocks: &mut Vec<(Cid, Vec<u8>)>) {
+        let root = match self.unflushed_links.as_slice() {
+            [root] => root,
+            other => unreachable!(
+                "finishing should had left only the root link: {}",
+                LinkFormatter(other)
+            ),
+        };
+
+        let rendered_root = matches!(
+            blocks.last(),
+            Some((cid, _)) if cid == &root.target && cid.codec() == Codec::DagProtobuf
+        );
+
+        let (cid, vec) = if rendered_root {
+            let (_, block) = blocks.pop().expect("the root was just found");
+            let mut inner =
+                FlatUnixFs::try_from(block.as_slice()).expect("the root was rendered by the adder");
+            self.metadata.write_into(&mut inner.data);
+            render_and_hash(&inner, &self.format)
+        } else {
+            let mut links = Vec::with_capacity(1);
+            let mut blocksizes = Vec::with_capacity(1);
+            let mut nested_size = 0;
+            let mut nested_total_size = 0;
+
+            BalancedCollector::partition_link(
+                root,
+                &mut links,
+                &mut blocksizes,
+                &mut nested_size,
+                &mut nested_total_size,
+            );
+
+            let mut inner = FlatUnixFs {
+                links,
+                data: UnixFs {
+                    Type: UnixFsType::File,
+                    filesize: Some(nested_size),
+                    blocksizes,
+                    ..Default::default()
+                },
+            };
+            self.metadata.write_into(&mut inner.data);
+            render_and_hash(&inner, &self.format)
+        };
+
+        blocks.push((cid, vec));
     }
 
     /// Returns `None` when the input is empty but there are links, otherwise a new Cid and a
@@ -231,33 +368,41 @@ impl FileAdder {
         input: &[u8],
         unflushed_links: &mut Vec<Link>,
         finishing: bool,
+        collector: &Collector,
+        format: &BlockFormat,
     ) -> Option<(Cid, Vec<u8>)> {
         if input.is_empty() && (!finishing || !unflushed_links.is_empty()) {
             return None;
         }
 
-        // for empty unixfs file the bytes is missing but filesize is present.
-
-        let data = if !input.is_empty() {
-            Some(Cow::Borrowed(input))
+        let (cid, vec) = if format.raw_leaves && (!input.is_empty() || collector.has_leaf_root()) {
+            let vec = input.to_vec();
+            (format.cid(Codec::Raw, &vec), vec)
         } else {
-            None
-        };
+            // for empty unixfs file the bytes is missing but filesize is present.
 
-        let filesize = Some(input.len() as u64);
+            let (data, leaf_type) = if !input.is_empty() {
+                (Some(Cow::Borrowed(input)), collector.leaf_type())
+            } else {
+                // the empty file is a single block, which is the root regardless of the layout
+                (None, UnixFsType::File)
+            };
 
-        let inner = FlatUnixFs {
-            links: Vec::new(),
-            data: UnixFs {
-                Type: UnixFsType::File,
-                Data: data,
-                filesize,
-                // no blocksizes as there are no links
-                ..Default::default()
-            },
-        };
+            let filesize = Some(input.len() as u64);
+
+            let inner = FlatUnixFs {
+                links: Vec::new(),
+                data: UnixFs {
+                    Type: leaf_type,
+                    Data: data,
+                    filesize,
+                    // no blocksizes as there are no links
+                    ..Default::default()
+                },
+            };
 
-        let (cid, vec) = render_and_hash(&inner);
+            render_and_hash(&inner, format)
+        };
 
         let total_size = vec.len();
 
@@ -275,7 +420,7 @@ impl FileAdder {
 
     fn flush_buffered_links(&mut self, finishing: bool) -> Vec<(Cid, Vec<u8>)> {
         self.collector
-            .flush_links(&mut self.unflushed_links, finishing)
+            .flush_links(&mut self.unflushed_links, finishing, &self.format)
     }
 
     /// Test helper for collecting all of the produced blocks; probably not a good idea outside
@@ -307,7 +452,7 @@ impl FileAdder {
     }
 }
 
-fn render_and_hash(flat: &FlatUnixFs<'_>) -> (Cid, Vec<u8>) {
+fn render_and_hash(flat: &FlatUnixFs<'_>, format: &BlockFormat) -> (Cid, Vec<u8>) {
     // TODO: as shown in later dagger we don't really need to render the FlatUnixFs fully; we could
     // either just render a fixed header and continue with the body OR links, though the links are
     // a bit more complicated.
@@ -315,8 +460,7 @@ fn render_and_hash(flat: &FlatUnixFs<'_>) -> (Cid, Vec<u8>) {
     let mut writer = Writer::new(&mut out);
     flat.write_message(&mut writer)
         .expect("unsure how this could fail");
-    let mh = multihash::wrap(multihash::Code::Sha2_256, &Sha256::digest(&out));
-    let cid = Cid::new_v0(mh).expect("sha2_256 is the correct multihash for cidv0");
+    let cid = format.cid(Codec::DagProtobuf, &out);
     (cid, out)
 }
 
@@ -325,6 +469,22 @@ fn render_and_hash(flat: &FlatUnixFs<'_>) -> (Cid, Vec<u8>) {
 pub enum Chunker {
     /// Size based chunking
     Size(usize),
+    /// Content defined chunking with Rabin fingerprints, producing the same chunks as the go-ipfs
+    /// `rabin-{min}-{avg}-{max}` chunker. Inserting or removing bytes only changes the chunks
+    /// around the change, unlike with [`Chunker::Size`].
+    ///
+    /// Chunks are between `min` and `max` bytes, except for the last one which can be shorter.
+    /// `avg` is rounded down to a power of two. As in go-ipfs, `min` must be at least 16, the size
+    /// of the fingerprinted window, and less than `avg`, which must be less than `max`; see
+    /// [`Chunker::check`].
+    Rabin {
+        /// Minimum chunk size.
+        min: usize,
+        /// Average chunk size.
+        avg: usize,
+        /// Maximum chunk size.
+        max: usize,
+    },
 }
 
 impl Default for Chunker {
@@ -335,6 +495,44 @@ impl Default for Chunker {
 }
 
 impl Chunker {
+    /// Returns a Rabin chunker with the given average chunk size and the minimum and maximum
+    /// derived from it as go-ipfs does for `rabin-{avg}`.
+    pub fn rabin(avg: usize) -> Self {
+        Chunker::Rabin {
+            min: avg / 3,
+            avg,
+            max: avg + avg / 2,
+        }
+    }
+
+    /// Returns a Rabin chunker with the given parameters if they pass [`Chunker::check`].
+    pub fn rabin_checked(min: usize, avg: usize, max: usize) -> Result<Self, ChunkerError> {
+        let chunker = Chunker::Rabin { min, avg, max };
+        chunker.check()?;
+        Ok(chunker)
+    }
+
+    /// Checks the parameters with the rules of go-ipfs: the size of [`Chunker::Size`] must not be
+    /// zero, and the `min`, `avg` and `max` of [`Chunker::Rabin`] must be increasing with `min`
+    /// at least 16 bytes.
+    pub fn check(&self) -> Result<(), ChunkerError> {
+        match *self {
+            Chunker::Size(0) => Err(ChunkerError::ZeroSize),
+            Chunker::Size(_) => Ok(()),
+            Chunker::Rabin { min, avg, max } => {
+                if min < rabin::WINDOW_SIZE {
+                    Err(ChunkerError::RabinMinTooSmall(min))
+                } else if avg <= min {
+                    Err(ChunkerError::RabinAvgNotAboveMin { min, avg })
+                } else if max <= avg {
+                    Err(ChunkerError::RabinMaxNotAboveAvg { avg, max })
+                } else {
+                    Ok(())
+                }
+            }
+        }
+    }
+
     fn accept<'a>(&mut self, input: &'a [u8], buffered: &[u8]) -> (&'a [u8], bool) {
         use Chunker::*;
 
@@ -345,6 +543,13 @@ impl Chunker {
                 let ready = buffered.len() + l >= *max;
                 (accepted, ready)
             }
+            Rabin { min, avg, max } => {
+                let l = input.len().min(*max - buffered.len());
+                match rabin::boundary(*min, *avg, *max, buffered, &input[..l]) {
+                    Some(end) => (&input[..end], true),
+                    None => (&input[..l], false),
+                }
+            }
         }
     }
 
@@ -353,18 +558,69 @@ impl Chunker {
 
         match self {
             Size(max) => *max,
+            Rabin { max, .. } => *max,
         }
     }
 }
 
+/// The [`Chunker`] parameters refused by [`Chunker::check`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum ChunkerError {
+    /// The size of [`Chunker::Size`] is zero.
+    ZeroSize,
+    /// The minimum of [`Chunker::Rabin`] is below the 16 byte fingerprinted window.
+    RabinMinTooSmall(usize),
+    /// The average of [`Chunker::Rabin`] is not above the minimum.
+    RabinAvgNotAboveMin {
+        /// Minimum chunk size.
+        min: usize,
+        /// Average chunk size.
+        avg: usize,
+    },
+    /// The maximum of [`Chunker::Rabin`] is not above the average.
+    RabinMaxNotAboveAvg {
+        /// Average chunk size.
+        avg: usize,
+        /// Maximum chunk size.
+        max: usize,
+    },
+}
+
+impl fmt::Display for ChunkerError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ChunkerError::*;
+        match self {
+            ZeroSize => write!(fmt, "chunker size must be greater than 0"),
+            RabinMinTooSmall(min) => write!(fmt, "rabin min must be at least 16, not {}", min),
+            RabinAvgNotAboveMin { min, avg } => {
+                write!(
+                    fmt,
+                    "rabin avg must be greater than min: {} <= {}",
+                    avg, min
+                )
+            }
+            RabinMaxNotAboveAvg { avg, max } => {
+                write!(
+                    fmt,
+                    "rabin max must be greater than avg: {} <= {}",
+                    max, avg
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ChunkerError {}
+
 /// Collector or layout strategy. For more information, see the [Layout section of the spec].
-/// Currently only the default balanced collector/layout has been implemented.
 ///
 /// [Layout section of the spec]: https://github.com/ipfs/specs/blob/master/UNIXFS.md#layout
 #[derive(Debug, Clone)]
 pub enum Collector {
     /// Balanced trees.
     Balanced(BalancedCollector),
+    /// Trickle trees.
+    Trickle(TrickleCollector),
 }
 
 impl Default for Collector {
@@ -374,11 +630,33 @@ impl Default for Collector {
 }
 
 impl Collector {
-    fn flush_links(&mut self, pending: &mut Vec<Link>, finishing: bool) -> Vec<(Cid, Vec<u8>)> {
+    fn flush_links(
+        &mut self,
+        pending: &mut Vec<Link>,
+        finishing: bool,
+        format: &BlockFormat,
+    ) -> Vec<(Cid, Vec<u8>)> {
         use Collector::*;
 
         match self {
-            Balanced(bc) => bc.flush_links(pending, finishing),
+            Balanced(bc) => bc.flush_links(pending, finishing, format),
+            Trickle(tc) => tc.flush_links(pending, finishing, format),
+        }
+    }
+
+    /// Returns true if a file of a single leaf, even an empty one, has the leaf as the root. In
+    /// go-ipfs this is the case with the balanced layout, so single block files can be raw blocks.
+    fn has_leaf_root(&self) -> bool {
+        matches!(self, Collector::Balanced(_))
+    }
+
+    /// The type of the UnixFs leaf nodes; go-ipfs creates `Raw` leaves for the trickle layout.
+    fn leaf_type(&self) -> UnixFsType {
+        use Collector::*;
+
+        match self {
+            Balanced(_) => UnixFsType::File,
+            Trickle(_) => UnixFsType::Raw,
         }
     }
 }
@@ -438,7 +716,12 @@ impl BalancedCollector {
     /// In-place compression of the `pending` links to a balanced hierarchy. When `finishing`, the
     /// links will be compressed iteratively from the lowest level to produce a single root link
     /// block.
-    fn flush_links(&mut self, pending: &mut Vec<Link>, finishing: bool) -> Vec<(Cid, Vec<u8>)> {
+    fn flush_links(
+        &mut self,
+        pending: &mut Vec<Link>,
+        finishing: bool,
+        format: &BlockFormat,
+    ) -> Vec<(Cid, Vec<u8>)> {
         /*
 
         file    |- - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -|
@@ -578,7 +861,7 @@ impl BalancedCollector {
                     },
                 };
 
-                let (cid, vec) = render_and_hash(&inner);
+                let (cid, vec) = render_and_hash(&inner, format);
 
                 // start overwriting at the first index of this level, then continue forward on
                 // next iterations.
@@ -640,10 +923,198 @@ impl BalancedCollector {
     }
 }
 
+/// The number of subtrees of each depth in a trickle tree node, as in go-ipfs.
+const DEPTH_REPEAT: usize = 4;
+
+/// TrickleCollector creates trickle UnixFs trees, which are optimized for reading the file
+/// sequentially from the start, for example logs or media. A node of the tree links to up to
+/// branching factor leaves, followed by four subtrees of each depth less than its own, starting
+/// from one. The root has no depth limit and so grows wider as the file grows.
+///
+/// The produced trees are identical to the ones of go-ipfs `add --trickle`.
+#[derive(Clone)]
+pub struct TrickleCollector {
+    branching_factor: usize,
+    // the nodes being filled from the root to the innermost one; their links are kept in the
+    // pending links
+    open: Vec<TrickleNode>,
+    // the amount of pending links already assigned to the open nodes
+    tracked: usize,
+}
+
+#[derive(Debug, Clone)]
+struct TrickleNode {
+    /// Index of the first link of this node in the pending links.
+    start: usize,
+    /// Depth of this subtree, or `None` for the root.
+    max_depth: Option<usize>,
+    /// Depth of the subtrees currently being added after the leaves.
+    depth: usize,
+    /// The amount of subtrees of `depth` added so far.
+    repeats: usize,
+}
+
+impl TrickleNode {
+    fn is_complete(&self, links: usize, branching_factor: usize) -> bool {
+        match self.max_depth {
+            Some(max_depth) => links >= branching_factor && self.depth >= max_depth,
+            None => false,
+        }
+    }
+}
+
+impl fmt::Debug for TrickleCollector {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            fmt,
+            "TrickleCollector {{ branching_factor: {}, open: {} }}",
+            self.branching_factor,
+            self.open.len()
+        )
+    }
+}
+
+impl Default for TrickleCollector {
+    /// Returns a default collector which matches go-ipfs 0.6, with the same branching factor as
+    /// [`BalancedCollector::default`].
+    fn default() -> Self {
+        Self::with_branching_factor(174)
+    }
+}
+
+impl From<TrickleCollector> for Collector {
+    fn from(t: TrickleCollector) -> Self {
+        Collector::Trickle(t)
+    }
+}
+
+impl TrickleCollector {
+    /// Configure Trickle collector with the given branching factor, which limits the amount of
+    /// leaves linked from each node.
+    pub fn with_branching_factor(branching_factor: usize) -> Self {
+        assert!(branching_factor > 0);
+
+        Self {
+            branching_factor,
+            open: Vec::new(),
+            tracked: 0,
+        }
+    }
+
+    /// Assigns the new leaves of `pending` to the open nodes, replacing the links of the
+    /// completed nodes with a link to their new link block. When `finishing`, all of the open
+    /// nodes are completed, leaving only the root link.
+    fn flush_links(
+        &mut self,
+        pending: &mut Vec<Link>,
+        finishing: bool,
+        format: &BlockFormat,
+    ) -> Vec<(Cid, Vec<u8>)> {
+        let mut ret = Vec::new();
+
+        if self.open.is_empty() {
+            if finishing && pending.len() == 1 && pending[0].file_size == 0 {
+                // the empty file has no links
+                return ret;
+            }
+
+            self.open.push(TrickleNode {
+                start: 0,
+                max_depth: None,
+                depth: 1,
+                repeats: 0,
+            });
+        }
+
+        for leaf in pending.split_off(self.tracked) {
+            let top = self.open.last().expect("the root is open until finishing");
+
+            if pending.len() - top.start >= self.branching_factor {
+                // the leaves of the node are full; continue with a new subtree
+                let max_depth = Some(top.depth);
+                self.open.push(TrickleNode {
+                    start: pending.len(),
+                    max_depth,
+                    depth: 1,
+                    repeats: 0,
+                });
+            }
+
+            pending.push(leaf);
+
+            while let Some(top) = self.open.last() {
+                if !top.is_complete(pending.len() - top.start, self.branching_factor) {
+                    break;
+                }
+                ret.push(self.close(pending, format));
+            }
+        }
+
+        if finishing {
+            while !self.open.is_empty() {
+                ret.push(self.close(pending, format));
+            }
+        }
+
+        self.tracked = pending.len();
+
+        ret
+    }
+
+    /// Replaces the links of the innermost open node with a link to its new link block.
+    fn close(&mut self, pending: &mut Vec<Link>, format: &BlockFormat) -> (Cid, Vec<u8>) {
+        let node = self.open.pop().expect("closing requires an open node");
+
+        let mut links = Vec::with_capacity(pending.len() - node.start);
+        let mut blocksizes = Vec::with_capacity(pending.len() - node.start);
+        let mut nested_size = 0;
+        let mut nested_total_size = 0;
+
+        for link in pending.drain(node.start..) {
+            BalancedCollector::partition_link(
+                &link,
+                &mut links,
+                &mut blocksizes,
+                &mut nested_size,
+                &mut nested_total_size,
+            );
+        }
+
+        let inner = FlatUnixFs {
+            links,
+            data: UnixFs {
+                Type: UnixFsType::File,
+                filesize: Some(nested_size),
+                blocksizes,
+                ..Default::default()
+            },
+        };
+
+        let (cid, vec) = render_and_hash(&inner, format);
+
+        pending.push(Link {
+            depth: node.max_depth.unwrap_or(node.depth),
+            target: cid.clone(),
+            total_size: nested_total_size + vec.len() as u64,
+            file_size: nested_size,
+        });
+
+        if let Some(parent) = self.open.last_mut() {
+            parent.repeats += 1;
+            if parent.repeats == DEPTH_REPEAT {
+                parent.depth += 1;
+                parent.repeats = 0;
+            }
+        }
+
+        (cid, vec)
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
-    use super::{BalancedCollector, Chunker, FileAdder};
+    use super::{BalancedCollector, Chunker, FileAdder, TrickleCollector};
     use crate::test_support::FakeBlockstore;
     use cid::Cid;
     use core::convert::TryFrom;
@@ -668,6 +1139,115 @@ mod tests {
         (accepted.len(), ready)
     }
 
+    #[test]
+    fn rabin_chunks_do_not_depend_on_the_pushed_amounts() {
+        let content = pseudorandom(20_000);
+        let chunker = Chunker::Rabin {
+            min: 64,
+            avg: 256,
+            max: 1024,
+        };
+
+        let expected = FileAdder::builder()
+            .with_chunker(chunker.clone())
+            .unwrap()
+            .build()
+            .collect_blocks(&content, 0);
+
+        for amt in &[1, 15, 16, 17, 1000] {
+            let blocks_received = FileAdder::builder()
+                .with_chunker(chunker.clone())
+                .unwrap()
+                .build()
+                .collect_blocks(&content, *amt);
+            assert_eq!(blocks_received, expected, "amt: {}", amt);
+        }
+    }
+
+    #[test]
+    fn invalid_chunkers_are_refused() {
+        assert_eq!(
+            Chunker::rabin_checked(8, 256, 1024).unwrap_err(),
+            ChunkerError::RabinMinTooSmall(8)
+        );
+        assert_eq!(
+            Chunker::rabin_checked(256, 64, 1024).unwrap_err(),
+            ChunkerError::RabinAvgNotAboveMin { min: 256, avg: 64 }
+        );
+        assert_eq!(
+            Chunker::rabin_checked(64, 256, 256).unwrap_err(),
+            ChunkerError::RabinMaxNotAboveAvg { avg: 256, max: 256 }
+        );
+        assert!(Chunker::rabin_checked(16, 32, 64).is_ok());
+
+        for chunker in &[
+            Chunker::Size(0),
+            Chunker::Rabin {
+                min: 0,
+                avg: 0,
+                max: 0,
+            },
+            // the minimum derived from the average is below the window
+            Chunker::rabin(32),
+        ] {
+            assert!(
+                FileAdder::builder().with_chunker(chunker.clone()).is_err(),
+                "{:?}",
+                chunker
+            );
+        }
+    }
+
+    #[test]
+    fn rabin_chunks_survive_an_insertion() {
+        let content = pseudorandom(20_000);
+        let mut modified = content.clone();
+        modified.insert(100, 0xff);
+
+        let leaves = |content: &[u8]| {
+            let mut chunker = Chunker::rabin(256);
+            let mut leaves = Vec::new();
+            let mut written = 0;
+
+            while written < content.len() {
+                let (accepted, ready) = chunker.accept(&content[written..], &[]);
+                assert!(accepted.len() <= 384);
+                assert!(!ready || accepted.len() >= 85);
+                leaves.push(accepted.to_vec());
+                written += accepted.len();
+            }
+            leaves
+        };
+
+        let original = leaves(&content);
+        let changed = leaves(&modified);
+
+        // only the chunk containing the inserted byte, and possibly the following one, differ
+        let shared = changed
+            .iter()
+            .filter(|leaf| original.contains(leaf))
+            .count();
+        assert!(original.len() > 30);
+        assert!(
+            shared + 2 >= original.len(),
+            "{}/{}",
+            shared,
+            original.len()
+        );
+    }
+
+    fn pseudorandom(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 24) as u8
+            })
+            .collect()
+    }
+
     #[test]
     fn favourite_single_block_file() {
         let blocks = FakeBlockstore::with_fixtures();
@@ -701,7 +1281,10 @@ mod tests {
 
         let blocks = FakeBlockstore::with_fixtures();
         let content = b"foobar\n";
-        let adder = FileAdder::builder().with_chunker(Chunker::Size(2)).build();
+        let adder = FileAdder::builder()
+            .with_chunker(Chunker::Size(2))
+            .unwrap()
+            .build();
 
         let blocks_received = adder.collect_blocks(content, 0);
 
@@ -726,6 +1309,265 @@ mod tests {
         assert_eq!(blocks_received, expected);
     }
 
+    #[test]
+    fn favourite_multi_block_file_trickle() {
+        let blocks = FakeBlockstore::with_fixtures();
+        let content = b"foobar\n";
+        let adder = FileAdder::builder()
+            .with_chunker(Chunker::Size(2))
+            .unwrap()
+            .with_collector(TrickleCollector::default())
+            .build();
+
+        let blocks_received = adder.collect_blocks(content, 0);
+
+        // "fo", "ob", "ar", "\n" as leaves of type raw and the root block
+        assert_eq!(blocks_received.len(), 5);
+        for (cid, block) in &blocks_received {
+            assert_eq!(blocks.get_by_cid(cid), block.as_slice());
+        }
+        assert_eq!(
+            blocks_received.last().unwrap().0.to_string(),
+            "QmWfQ48ChJUj4vWKFsUDe4646xCBmXgdmNfhjz9T7crywd"
+        );
+    }
+
+    #[test]
+    fn trickle_layers() {
+        use crate::file::visit::IdleFileVisit;
+        use crate::pb::FlatUnixFs;
+
+        // with two leaves per node the root links to two leaves, four subtrees of depth one with
+        // two leaves each, four subtrees of depth two with ten leaves each and then a subtree of
+        // depth three for the last byte
+        let content = (0..51u8).collect::<Vec<_>>();
+
+        let adder = FileAdder::builder()
+            .with_chunker(Chunker::Size(1))
+            .unwrap()
+            .with_collector(TrickleCollector::with_branching_factor(2))
+            .build();
+
+        let blocks_received = adder.collect_blocks(&content, 0);
+
+        // the leaves, 4 + 4 * (1 + 4) link blocks, one for the last subtree and the root
+        assert_eq!(blocks_received.len(), 51 + 24 + 1 + 1);
+
+        let (root, root_block) = blocks_received.last().unwrap();
+        let flat = FlatUnixFs::try_from(root_block.as_slice()).unwrap();
+        assert_eq!(flat.links.len(), 2 + 4 + 4 + 1);
+
+        let mut blocks = FakeBlockstore::default();
+        for (cid, block) in &blocks_received {
+            assert_eq!(&blocks.insert_v0(block), cid);
+        }
+
+        let (first, _, _, mut step) = IdleFileVisit::default()
+            .start(blocks.get_by_cid(root))
+            .unwrap();
+        let mut read = first.to_vec();
+
+        while let Some(visit) = step {
+            let (next, _) = visit.pending_links();
+            let (bytes, next_step) = visit
+                .continue_walk(blocks.get_by_cid(next), &mut None)
+                .unwrap();
+            read.extend(bytes);
+            step = next_step;
+        }
+
+        assert_eq!(read, content);
+    }
+
+    #[test]
+    fn trickle_empty_file() {
+        let adder = FileAdder::builder()
+            .with_collector(TrickleCollector::default())
+            .build();
+        let blocks = adder.collect_blocks(b"", 0);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(
+            blocks[0].0.to_string(),
+            "QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH"
+        );
+    }
+
+    #[test]
+    fn raw_leaves() {
+        use crate::pb::FlatUnixFs;
+        use cid::{Codec, Version};
+
+        // single block files are just the raw block, even when empty
+        for (content, expected) in &[
+            (
+                &b""[..],
+                "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku",
+            ),
+            (
+                &b"foobar\n"[..],
+                "bafkreifoybygix7fh3r3g5rqle3wcnhqldgdg4shzf4k3ulyw3gn7mabt4",
+            ),
+        ] {
+            let blocks = FileAdder::builder()
+                .with_raw_leaves(true)
+                .build()
+                .collect_blocks(content, 0);
+            assert_eq!(blocks.len(), 1);
+            assert_eq!(blocks[0].0.to_string(), *expected);
+            assert_eq!(blocks[0].1.as_slice(), *content);
+        }
+
+        let blocks = FileAdder::builder()
+            .with_chunker(Chunker::Size(2))
+            .unwrap()
+            .with_raw_leaves(true)
+            .build()
+            .collect_blocks(b"foobar\n", 0);
+
+        let (root, root_block) = blocks.last().unwrap();
+        assert_eq!(root.version(), Version::V0);
+
+        let leaves = &blocks[..blocks.len() - 1];
+        assert_eq!(
+            leaves
+                .iter()
+                .map(|(_, block)| block.as_slice())
+                .collect::<Vec<_>>(),
+            vec![&b"fo"[..], b"ob", b"ar", b"\n"]
+        );
+        assert!(leaves
+            .iter()
+            .all(|(cid, _)| cid.version() == Version::V1 && cid.codec() == Codec::Raw));
+
+        let flat = FlatUnixFs::try_from(root_block.as_slice()).unwrap();
+        let linked = flat
+            .links
+            .iter()
+            .map(|link| Cid::try_from(link.Hash.as_deref().unwrap()).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            linked,
+            leaves
+                .iter()
+                .map(|(cid, _)| cid.clone())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn cid_version_and_hash() {
+        use crate::HashFunction;
+        use cid::{Codec, Version};
+        use multihash::Code;
+
+        let content = b"foobar\n";
+
+        let blocks = FileAdder::builder()
+            .with_chunker(Chunker::Size(2))
+            .unwrap()
+            .with_cid_version(Version::V1)
+            .build()
+            .collect_blocks(content, 0);
+        assert_eq!(blocks.len(), 5);
+        assert!(blocks.iter().all(|(cid, _)| cid.version() == Version::V1
+            && cid.codec() == Codec::DagProtobuf
+            && cid.hash().algorithm() == Code::Sha2_256));
+
+        // the blocks are the same as with CIDv0, only the links differ
+        let v0 = FileAdder::builder()
+            .with_chunker(Chunker::Size(2))
+            .unwrap()
+            .build()
+            .collect_blocks(content, 0);
+        assert_eq!(
+            blocks[..4],
+            v0[..4]
+                .iter()
+                .map(|(cid, block)| {
+                    (
+                        Cid::new_v1(Codec::DagProtobuf, cid.hash().to_owned()),
+                        block.clone(),
+                    )
+                })
+                .collect::<Vec<_>>()[..]
+        );
+
+        for (hash, code) in &[
+            (HashFunction::Blake2b256, Code::Blake2b256),
+            (HashFunction::Sha3_256, Code::Sha3_256),
+        ] {
+            // CIDv0 is only possible with sha2-256
+            let blocks = FileAdder::builder()
+                .with_hash(*hash)
+                .build()
+                .collect_blocks(content, 0);
+            assert_eq!(blocks.len(), 1);
+            assert_eq!(blocks[0].0.version(), Version::V1);
+            assert_eq!(blocks[0].0.hash().algorithm(), *code);
+        }
+    }
+
+    #[test]
+    fn metadata_on_the_root() {
+        use crate::file::visit::IdleFileVisit;
+        use crate::pb::FlatUnixFs;
+        use crate::Metadata;
+
+        let metadata = Metadata::default()
+            .with_mode(0o640)
+            .with_mtime(1_600_000_000, 500);
+
+        let read_root = |blocks: &[(Cid, Vec<u8>)]| {
+            let (_, root_block) = blocks.last().unwrap();
+            let (_, file_size, found, _) = IdleFileVisit::default().start(root_block).unwrap();
+            (file_size, found)
+        };
+
+        let builder = FileAdder::builder().with_metadata(metadata.clone());
+
+        // the single leaf is the root
+        let blocks = builder.clone().build().collect_blocks(b"foobar\n", 0);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(read_root(&blocks), (7, metadata.clone()));
+
+        // the link block is the root
+        let blocks = builder
+            .clone()
+            .with_chunker(Chunker::Size(2))
+            .unwrap()
+            .build()
+            .collect_blocks(b"foobar\n", 0);
+        assert_eq!(blocks.len(), 5);
+        assert_eq!(read_root(&blocks), (7, metadata.clone()));
+
+        // raw leaves and the leaves returned from `push` cannot be modified
+        for builder in &[
+            builder.clone().with_raw_leaves(true),
+            builder.with_chunker(Chunker::Size(7)).unwrap(),
+        ] {
+            let blocks = builder.clone().build().collect_blocks(b"foobar\n", 0);
+            assert_eq!(blocks.len(), 2);
+            assert_eq!(read_root(&blocks), (7, metadata.clone()));
+
+            let flat = FlatUnixFs::try_from(blocks[1].1.as_slice()).unwrap();
+            assert_eq!(flat.links.len(), 1);
+            assert_eq!(flat.data.blocksizes, [7]);
+            assert_eq!(
+                Cid::try_from(flat.links[0].Hash.as_deref().unwrap()).unwrap(),
+                blocks[0].0
+            );
+        }
+
+        // the zero nanoseconds are left out
+        let blocks = FileAdder::builder()
+            .with_metadata(Metadata::default().with_mtime(-1, 0))
+            .build()
+            .collect_blocks(b"", 0);
+        let flat = FlatUnixFs::try_from(blocks[0].1.as_slice()).unwrap();
+        assert_eq!(flat.data.mode, None);
+        assert_eq!(flat.data.mtime.unwrap().FractionalNanoseconds, None);
+    }
+
     #[test]
     fn three_layers() {
         let content = b"Lorem ipsum dolor sit amet, sit enim montes aliquam. Cras non lorem, \
@@ -741,7 +1583,10 @@ mod tests {
         //
         // in future, if we ever add inline Cid generation this test would need to be changed not
         // to use those inline cids or raw leaves
-        let adder = FileAdder::builder().with_chunker(Chunker::Size(1)).build();
+        let adder = FileAdder::builder()
+            .with_chunker(Chunker::Size(1))
+            .unwrap()
+            .build();
 
         let blocks_received = adder.collect_blocks(content, 0);
 
@@ -760,7 +1605,10 @@ mod tests {
             wisi ipsum, vel rhoncus eget faucibus varius, luctus turpis nibh vel odio nulla pede.";
 
         for amt in 1..32 {
-            let adder = FileAdder::builder().with_chunker(Chunker::Size(32)).build();
+            let adder = FileAdder::builder()
+                .with_chunker(Chunker::Size(32))
+                .unwrap()
+                .build();
             let blocks_received = adder.collect_blocks(content, amt);
             assert_eq!(
                 blocks_received.last().unwrap().0.to_string(),
@@ -803,6 +1651,7 @@ mod tests {
 
         let mut adder = FileAdder::builder()
             .with_chunker(Chunker::Size(2))
+            .unwrap()
             .with_collector(BalancedCollector::with_branching_factor(branching_factor))
             .build();
         let mut blocks_count = 0;
@@ -842,6 +1691,7 @@ mod tests {
 
         let mut adder = FileAdder::builder()
             .with_chunker(Chunker::Size(1))
+            .unwrap()
             .with_collector(BalancedCollector::with_branching_factor(branching_factor))
             .build();
         let mut blocks_count = 0;
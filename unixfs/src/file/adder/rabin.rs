@@ -0,0 +1,152 @@
+//! Rabin fingerprinting for [`super::Chunker::Rabin`], producing the same chunk boundaries as the
+//! `rabin` chunker of go-ipfs. go-ipfs uses a fork of the restic chunker, which fingerprints a
+//! sliding window of 16 bytes with a fixed irreducible polynomial and ends a chunk when the low bits
+//! of the fingerprint are all zero.
+//!
+//! The fingerprint is reset for every chunk and the first `min - 16` bytes of a chunk are not
+//! fingerprinted, so whenever a chunk could end the fingerprint only depends on the last 16 bytes.
+//! This allows finding the boundary from the buffered bytes without keeping any state in between
+//! [`super::FileAdder::push`] calls.
+
+/// The polynomial go-ipfs uses for the fingerprints (`IpfsRabinPoly`).
+const POLYNOMIAL: u64 = 17_437_180_132_763_653;
+
+/// The size of the sliding window, also the smallest supported minimum chunk size.
+pub(super) const WINDOW_SIZE: usize = 16;
+
+/// The fingerprint is shifted by a byte at a time; the top 8 bits select the reduction.
+const SHIFT: u32 = degree(POLYNOMIAL) as u32 - 8;
+
+static TABLES: Tables = Tables::new(POLYNOMIAL);
+
+struct Tables {
+    /// Fingerprint of the byte followed by `WINDOW_SIZE - 1` zeroes, for sliding the byte out.
+    out: [u64; 256],
+    /// Reduction of the 8 bits above the degree of the polynomial.
+    reduce: [u64; 256],
+}
+
+impl Tables {
+    const fn new(polynomial: u64) -> Self {
+        let mut out = [0; 256];
+        let mut reduce = [0; 256];
+        let k = degree(polynomial) as u32;
+
+        let mut b = 0;
+        while b < 256 {
+            let mut hash = append_byte(0, b as u8, polynomial);
+            let mut i = 0;
+            while i < WINDOW_SIZE - 1 {
+                hash = append_byte(hash, 0, polynomial);
+                i += 1;
+            }
+            out[b] = hash;
+
+            // the reduction modulo the polynomial and the cancellation of the top bits
+            reduce[b] = modulo((b as u64) << k, polynomial) | ((b as u64) << k);
+            b += 1;
+        }
+
+        Tables { out, reduce }
+    }
+}
+
+/// Degree of the polynomial over GF(2), -1 for zero.
+const fn degree(x: u64) -> i32 {
+    63 - x.leading_zeros() as i32
+}
+
+const fn modulo(mut x: u64, d: u64) -> u64 {
+    while x != 0 && degree(x) >= degree(d) {
+        x ^= d << (degree(x) - degree(d)) as u32;
+    }
+    x
+}
+
+const fn append_byte(hash: u64, b: u8, polynomial: u64) -> u64 {
+    modulo((hash << 8) | b as u64, polynomial)
+}
+
+/// The fingerprinted window, starting out as it does in go-ipfs for every chunk.
+struct Window {
+    bytes: [u8; WINDOW_SIZE],
+    pos: usize,
+    digest: u64,
+}
+
+impl Default for Window {
+    fn default() -> Self {
+        let mut window = Window {
+            bytes: [0; WINDOW_SIZE],
+            pos: 0,
+            digest: 0,
+        };
+        window.slide(1);
+        window
+    }
+}
+
+impl Window {
+    fn slide(&mut self, b: u8) {
+        let out = core::mem::replace(&mut self.bytes[self.pos], b);
+        self.digest ^= TABLES.out[out as usize];
+        self.pos = (self.pos + 1) % WINDOW_SIZE;
+
+        let index = (self.digest >> SHIFT) as usize;
+        self.digest = ((self.digest << 8) | b as u64) ^ TABLES.reduce[index];
+    }
+}
+
+/// Returns the amount of `input` ending the chunk which begins with `buffered`, or `None` if the
+/// chunk continues past the `input`. The parameters must have passed [`super::Chunker::check`]
+/// and the caller must not pass more than `max - buffered.len()` bytes of `input`.
+pub(super) fn boundary(
+    min: usize,
+    avg: usize,
+    max: usize,
+    buffered: &[u8],
+    input: &[u8],
+) -> Option<usize> {
+    debug_assert!(
+        WINDOW_SIZE <= min && min < avg && avg < max,
+        "unchecked rabin chunker parameters: min {}, avg {}, max {}",
+        min,
+        avg,
+        max
+    );
+
+    // the average size is rounded down to a power of two
+    let mask = (1u64 << (63 - (avg as u64).leading_zeros())) - 1;
+
+    let len = buffered.len() + input.len();
+    let byte_at = |i: usize| {
+        if i < buffered.len() {
+            buffered[i]
+        } else {
+            input[i - buffered.len()]
+        }
+    };
+
+    // the shortest length at which the chunk could still end
+    let first = (buffered.len() + 1).max(min);
+    if first > len {
+        return None;
+    }
+
+    let mut window = Window::default();
+    for i in first - WINDOW_SIZE..first {
+        window.slide(byte_at(i));
+    }
+
+    for end in first..=len {
+        if end > first {
+            window.slide(byte_at(end - 1));
+        }
+
+        if window.digest & mask == 0 || end >= max {
+            return Some(end - buffered.len());
+        }
+    }
+
+    None
+}
@@ -163,6 +163,77 @@ impl Metadata {
         self.mtime()
             .map(|(seconds, nanos)| filetime::FileTime::from_unix_time(seconds, nanos))
     }
+
+    /// Returns `true` if neither the mode nor the mtime has been specified.
+    pub fn is_empty(&self) -> bool {
+        self.mode.is_none() && self.mtime.is_none()
+    }
+
+    /// Sets the full file mode, see [`Metadata::mode`].
+    pub fn with_mode(mut self, mode: u32) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    /// Sets the last modification time as seconds and nanoseconds since the unix epoch, see
+    /// [`Metadata::mtime`].
+    ///
+    /// # Panics
+    ///
+    /// When the nanoseconds are a full second or more.
+    pub fn with_mtime(mut self, seconds: i64, nanos: u32) -> Self {
+        assert!(nanos < 1_000_000_000, "nanos out of range: {}", nanos);
+        self.mtime = Some((seconds, nanos));
+        self
+    }
+
+    /// Writes the metadata into the UnixFs node; the zero nanoseconds are left out as required by
+    /// the specification.
+    pub(crate) fn write_into(&self, data: &mut UnixFs<'_>) {
+        data.mode = self.mode;
+        data.mtime = self.mtime.map(|(seconds, nanos)| pb::unixfs::UnixTime {
+            Seconds: seconds,
+            FractionalNanoseconds: if nanos == 0 { None } else { Some(nanos) },
+        });
+    }
+}
+
+/// Hash functions supported for the Cids of the created blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashFunction {
+    /// sha2-256, the default and the only one supported by CIDv0.
+    Sha2_256,
+    /// blake2b-256
+    Blake2b256,
+    /// sha3-256
+    Sha3_256,
+}
+
+impl Default for HashFunction {
+    fn default() -> Self {
+        HashFunction::Sha2_256
+    }
+}
+
+impl HashFunction {
+    /// Returns the Cid of a created block. CIDv0 is only used for dag-pb blocks hashed with
+    /// sha2-256, other blocks have version 1 Cids as in go-ipfs.
+    pub(crate) fn cid(self, version: cid::Version, codec: cid::Codec, block: &[u8]) -> cid::Cid {
+        use cid::{Cid, Codec, Version};
+
+        let mh = match self {
+            HashFunction::Sha2_256 => multihash::Sha2_256::digest(block),
+            HashFunction::Blake2b256 => multihash::Blake2b256::digest(block),
+            HashFunction::Sha3_256 => multihash::Sha3_256::digest(block),
+        };
+
+        match (version, codec, self) {
+            (Version::V0, Codec::DagProtobuf, HashFunction::Sha2_256) => {
+                Cid::new_v0(mh).expect("sha2_256 is the correct multihash for cidv0")
+            }
+            _ => Cid::new_v1(codec, mh),
+        }
+    }
 }
 
 impl<'a> From<&'a UnixFs<'_>> for Metadata {